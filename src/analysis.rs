@@ -0,0 +1,558 @@
+//! Static analysis of ROMs: basic blocks, subroutines and data regions, with
+//! the resulting control-flow graph exported as Graphviz DOT or JSON.
+//!
+//! The analysis follows every path from the load address that can be known
+//! without running the program. Computed jumps (`JumpWithOffset`) are only
+//! followed to their base address, and flagged as possible jump tables.
+
+use crate::chip::{MemAccess, OpCode};
+
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    Fallthrough,
+    Jump,
+    /// The next instruction is skipped.
+    Skip,
+    Call,
+    /// The base of a computed jump, which depends on `V0`.
+    Computed,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Fallthrough => "fallthrough",
+            Self::Jump => "jump",
+            Self::Skip => "skip",
+            Self::Call => "call",
+            Self::Computed => "computed",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions that is only ever entered at its start.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    /// The address of each instruction, and its raw word.
+    pub instructions: Vec<(u16, u16)>,
+    pub successors: Vec<Edge>,
+}
+
+impl Block {
+    /// The address right after the last instruction.
+    pub fn end(&self) -> u16 {
+        self.instructions
+            .last()
+            .map_or(self.start, |&(addr, _)| addr.wrapping_add(2))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: u16,
+    /// The start of every block reachable from the entry without calls.
+    pub blocks: Vec<u16>,
+}
+
+/// Bytes that are not code, but are pointed to by `SetIndexRegister`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataRegion {
+    pub range: Range<u16>,
+    /// The instructions setting the index register to the start of the region.
+    pub referenced_by: Vec<u16>,
+}
+
+/// An instruction writing over code, with an index register known statically.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfModification {
+    pub addr: u16,
+    pub target: Range<u16>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Analysis {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, Block>,
+    pub subroutines: Vec<Subroutine>,
+    /// Addresses of `JumpWithOffset` instructions.
+    pub jump_tables: Vec<u16>,
+    pub data: Vec<DataRegion>,
+    pub self_modifying: Vec<SelfModification>,
+    /// Reachable addresses that do not hold a valid instruction, or lie
+    /// outside the ROM.
+    pub invalid: Vec<u16>,
+}
+
+impl Analysis {
+    /// Analyses `rom`, as loaded at `load_addr` and executed from there.
+    pub fn new(rom: &[u8], load_addr: u16) -> Self {
+        let word = |addr: u16| {
+            let offset = usize::from(addr.checked_sub(load_addr)?);
+            Some(u16::from_be_bytes([
+                *rom.get(offset)?,
+                *rom.get(offset + 1)?,
+            ]))
+        };
+
+        // Find every reachable instruction, and where blocks have to start
+        let mut code = BTreeMap::new();
+        let mut leaders = BTreeSet::from([load_addr]);
+        let mut invalid = BTreeSet::new();
+        let mut pending = vec![load_addr];
+        while let Some(mut addr) = pending.pop() {
+            while !code.contains_key(&addr) {
+                let Some(op) = word(addr).and_then(|word| OpCode::try_from(word).ok()) else {
+                    invalid.insert(addr);
+                    break;
+                };
+
+                code.insert(addr, op);
+                let edges = Self::edges(addr, op);
+                for edge in &edges {
+                    if edge.kind != EdgeKind::Fallthrough {
+                        leaders.insert(edge.target);
+                        pending.push(edge.target);
+                    }
+                }
+
+                match edges.iter().find(|edge| edge.kind == EdgeKind::Fallthrough) {
+                    Some(edge) if edges.len() == 1 => addr = edge.target,
+                    Some(edge) => {
+                        leaders.insert(edge.target);
+                        addr = edge.target;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        let mut analysis = Self {
+            entry: load_addr,
+            invalid: invalid.into_iter().collect(),
+            ..Self::default()
+        };
+        analysis.build_blocks(&code, &leaders, &word);
+        analysis.find_subroutines(&code);
+        analysis.find_data(&code, rom.len(), load_addr);
+        analysis.find_self_modification(&code);
+        analysis
+    }
+
+    /// The ways execution can leave the instruction at `addr`.
+    fn edges(addr: u16, op: OpCode) -> Vec<Edge> {
+        let edge = |target, kind| Edge { target, kind };
+        let next = addr.wrapping_add(2);
+        match op {
+            OpCode::Jump(target) => vec![edge(target, EdgeKind::Jump)],
+            OpCode::Call(target) => vec![
+                edge(target, EdgeKind::Call),
+                edge(next, EdgeKind::Fallthrough),
+            ],
+            OpCode::JumpWithOffset(base) => vec![edge(base, EdgeKind::Computed)],
+            OpCode::Return => Vec::new(),
+            OpCode::SkipEqual(..)
+            | OpCode::SkipNotEqual(..)
+            | OpCode::SkipEqualRegister(..)
+            | OpCode::SkipNotEqualRegister(..)
+            | OpCode::SkipKeyPressed(_)
+            | OpCode::SkipKeyNotPressed(_) => vec![
+                edge(next, EdgeKind::Fallthrough),
+                edge(addr.wrapping_add(4), EdgeKind::Skip),
+            ],
+            _ => vec![edge(next, EdgeKind::Fallthrough)],
+        }
+    }
+
+    fn build_blocks(
+        &mut self,
+        code: &BTreeMap<u16, OpCode>,
+        leaders: &BTreeSet<u16>,
+        word: &impl Fn(u16) -> Option<u16>,
+    ) {
+        let mut current: Option<Block> = None;
+        for (&addr, &op) in code {
+            // Instructions can overlap when code jumps to odd addresses, so
+            // anything that does not directly follow the previous one starts
+            // a new block as well
+            let contiguous = current.as_ref().is_some_and(|block| block.end() == addr);
+            if !contiguous || leaders.contains(&addr) {
+                if let Some(mut block) = current.take() {
+                    let end = block.end();
+                    block.successors.push(Edge {
+                        target: end,
+                        kind: EdgeKind::Fallthrough,
+                    });
+                    self.finish_block(block, code);
+                }
+                current = Some(Block {
+                    start: addr,
+                    instructions: Vec::new(),
+                    successors: Vec::new(),
+                });
+            }
+
+            let Some(block) = current.as_mut() else {
+                continue;
+            };
+            block
+                .instructions
+                .push((addr, word(addr).unwrap_or_default()));
+
+            let edges = Self::edges(addr, op);
+            if !matches!(
+                edges.as_slice(),
+                [Edge {
+                    kind: EdgeKind::Fallthrough,
+                    ..
+                }]
+            ) {
+                block.successors = edges;
+                if let Some(block) = current.take() {
+                    self.finish_block(block, code);
+                }
+            }
+        }
+
+        if let Some(mut block) = current {
+            let end = block.end();
+            block.successors.push(Edge {
+                target: end,
+                kind: EdgeKind::Fallthrough,
+            });
+            self.finish_block(block, code);
+        }
+    }
+
+    fn finish_block(&mut self, mut block: Block, code: &BTreeMap<u16, OpCode>) {
+        // Drop the fallthrough out of a block running into invalid code
+        block.successors.retain(|edge| {
+            edge.kind != EdgeKind::Fallthrough
+                || code.contains_key(&edge.target)
+                || self.invalid.contains(&edge.target)
+        });
+
+        if let Some(&(addr, _)) = block.instructions.last() {
+            if matches!(code.get(&addr), Some(OpCode::JumpWithOffset(_))) {
+                self.jump_tables.push(addr);
+            }
+        }
+        self.blocks.insert(block.start, block);
+    }
+
+    fn find_subroutines(&mut self, code: &BTreeMap<u16, OpCode>) {
+        let entries: BTreeSet<u16> = code
+            .values()
+            .filter_map(|op| match op {
+                OpCode::Call(target) => Some(*target),
+                _ => None,
+            })
+            .collect();
+
+        for entry in entries {
+            let mut blocks = BTreeSet::new();
+            let mut pending = vec![entry];
+            while let Some(start) = pending.pop() {
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+                if !blocks.insert(start) {
+                    continue;
+                }
+
+                pending.extend(
+                    block
+                        .successors
+                        .iter()
+                        .filter(|edge| edge.kind != EdgeKind::Call)
+                        .map(|edge| edge.target),
+                );
+            }
+
+            self.subroutines.push(Subroutine {
+                entry,
+                blocks: blocks.into_iter().collect(),
+            });
+        }
+    }
+
+    fn find_data(&mut self, code: &BTreeMap<u16, OpCode>, rom_len: usize, load_addr: u16) {
+        let rom_end = load_addr.saturating_add(u16::try_from(rom_len).unwrap_or(u16::MAX));
+        let mut references: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+        for (&addr, op) in code {
+            if let OpCode::SetIndexRegister(target) = op {
+                if (load_addr..rom_end).contains(target) && !code.contains_key(target) {
+                    references.entry(*target).or_default().push(addr);
+                }
+            }
+        }
+
+        let starts: Vec<u16> = references.keys().copied().collect();
+        for (i, (start, referenced_by)) in references.into_iter().enumerate() {
+            // A region ends where code or the next region starts
+            let mut end = rom_end;
+            if let Some((&addr, _)) = code.range(start..).next() {
+                end = end.min(addr);
+            }
+            if let Some(&next) = starts.get(i + 1) {
+                end = end.min(next);
+            }
+
+            self.data.push(DataRegion {
+                range: start..end,
+                referenced_by,
+            });
+        }
+    }
+
+    fn find_self_modification(&mut self, code: &BTreeMap<u16, OpCode>) {
+        let mut found = Vec::new();
+        for block in self.blocks.values() {
+            // Only the index register set within the same block is known
+            let mut index = None;
+            for &(addr, _) in &block.instructions {
+                let Some(&op) = code.get(&addr) else {
+                    continue;
+                };
+
+                if let Some((MemAccess::Write, target)) =
+                    index.and_then(|index| op.memory_access(index))
+                {
+                    if target.clone().any(|byte| self.is_code_byte(byte)) {
+                        found.push(SelfModification { addr, target });
+                    }
+                }
+
+                index = match op {
+                    OpCode::SetIndexRegister(target) => Some(target),
+                    OpCode::AddIndexRegister(_)
+                    | OpCode::IndexAtSprite(_)
+                    | OpCode::StoreAllRegisters(_)
+                    | OpCode::LoadAllRegisters(_) => None,
+                    _ => index,
+                };
+            }
+        }
+
+        self.self_modifying = found;
+    }
+
+    fn is_code_byte(&self, addr: u16) -> bool {
+        self.blocks
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(_, block)| (block.start..block.end()).contains(&addr))
+    }
+
+    fn disassemble(word: u16) -> String {
+        OpCode::try_from(word).map_or_else(|_| String::from("???"), |op| op.to_string())
+    }
+
+    /// Writes the control-flow graph in the Graphviz DOT language.
+    pub fn write_dot(&self, mut out: impl Write) -> io::Result<()> {
+        let subroutines: BTreeSet<u16> = self.subroutines.iter().map(|s| s.entry).collect();
+
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for &(addr, word) in &block.instructions {
+                let _ = write!(label, "{addr:03X}  {}\\l", Self::disassemble(word));
+            }
+
+            let style = if block.start == self.entry {
+                ", style=bold"
+            } else if subroutines.contains(&block.start) {
+                ", style=rounded"
+            } else {
+                ""
+            };
+            writeln!(out, "    b{:03X} [label=\"{label}\"{style}];", block.start)?;
+        }
+
+        for region in &self.data {
+            writeln!(
+                out,
+                "    d{:03X} [label=\"data {:03X}-{:03X}\", shape=folder];",
+                region.range.start,
+                region.range.start,
+                region.range.end.wrapping_sub(1)
+            )?;
+        }
+
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let target = if self.blocks.contains_key(&edge.target) {
+                    format!("b{:03X}", edge.target)
+                } else {
+                    format!("\"{:03X}\"", edge.target)
+                };
+                writeln!(
+                    out,
+                    "    b{:03X} -> {target} [label=\"{}\"];",
+                    block.start,
+                    edge.kind.name()
+                )?;
+            }
+        }
+
+        for region in &self.data {
+            for &addr in &region.referenced_by {
+                if let Some((start, _)) = self.blocks.range(..=addr).next_back() {
+                    writeln!(
+                        out,
+                        "    b{start:03X} -> d{:03X} [style=dashed];",
+                        region.range.start
+                    )?;
+                }
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+
+    pub fn to_json(&self) -> Value {
+        let blocks: Vec<Value> = self
+            .blocks
+            .values()
+            .map(|block| {
+                json!({
+                    "start": block.start,
+                    "end": block.end(),
+                    "instructions": block.instructions.iter().map(|&(addr, word)| json!({
+                        "addr": addr,
+                        "word": word,
+                        "op": Self::disassemble(word),
+                    })).collect::<Vec<_>>(),
+                    "successors": block.successors.iter().map(|edge| json!({
+                        "target": edge.target,
+                        "kind": edge.kind.name(),
+                    })).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+
+        json!({
+            "entry": self.entry,
+            "blocks": blocks,
+            "subroutines": self.subroutines.iter().map(|s| json!({
+                "entry": s.entry,
+                "blocks": s.blocks,
+            })).collect::<Vec<_>>(),
+            "jump_tables": self.jump_tables,
+            "data": self.data.iter().map(|region| json!({
+                "start": region.range.start,
+                "end": region.range.end,
+                "referenced_by": region.referenced_by,
+            })).collect::<Vec<_>>(),
+            "self_modifying": self.self_modifying.iter().map(|m| json!({
+                "addr": m.addr,
+                "start": m.target.start,
+                "end": m.target.end,
+            })).collect::<Vec<_>>(),
+            "invalid": self.invalid,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_and_subroutines() {
+        let rom = [
+            0x22, 0x08, // 200: CALL 0x208
+            0x30, 0x01, // 202: SE   V0, 0x01
+            0x12, 0x00, // 204: JP   0x200
+            0xB3, 0x00, // 206: JP   V0, 0x300
+            0xA2, 0x10, // 208: LD   I, 0x210
+            0xF1, 0x55, // 20A: LD   [I], V1
+            0xA2, 0x00, // 20C: LD   I, 0x200
+            0xF0, 0x33, // 20E: LD   B, V0
+            0x00, 0xEE, // 210: RET
+            0xFF, 0xFF, // 212: data
+        ];
+        let analysis = Analysis::new(&rom, 0x200);
+
+        assert_eq!(
+            analysis.blocks.keys().copied().collect::<Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206, 0x208]
+        );
+        assert_eq!(
+            analysis.blocks[&0x202].successors,
+            [
+                Edge {
+                    target: 0x204,
+                    kind: EdgeKind::Fallthrough
+                },
+                Edge {
+                    target: 0x206,
+                    kind: EdgeKind::Skip
+                }
+            ]
+        );
+        assert_eq!(analysis.jump_tables, [0x206]);
+        assert_eq!(analysis.invalid, [0x300]);
+        assert_eq!(
+            analysis.subroutines,
+            [Subroutine {
+                entry: 0x208,
+                blocks: vec![0x208]
+            }]
+        );
+        assert_eq!(
+            analysis.self_modifying,
+            [
+                SelfModification {
+                    addr: 0x20A,
+                    target: 0x210..0x212
+                },
+                SelfModification {
+                    addr: 0x20E,
+                    target: 0x200..0x203
+                }
+            ]
+        );
+
+        let mut dot = Vec::new();
+        analysis.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("b202 -> b206 [label=\"skip\"];"));
+        assert_eq!(analysis.to_json()["blocks"][0]["start"], 0x200);
+    }
+
+    #[test]
+    fn data_regions() {
+        let rom = [
+            0xA2, 0x06, // 200: LD   I, 0x206
+            0xD0, 0x12, // 202: DRW  V0, V1, 2
+            0x12, 0x04, // 204: JP   0x204
+            0xFF, 0x81, // 206: sprite
+        ];
+        let analysis = Analysis::new(&rom, 0x200);
+
+        assert_eq!(
+            analysis.data,
+            [DataRegion {
+                range: 0x206..0x208,
+                referenced_by: vec![0x200]
+            }]
+        );
+    }
+}
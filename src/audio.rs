@@ -0,0 +1,211 @@
+//! A tone generator driven by the sound timer.
+//!
+//! Rather than switching a free running tone on and off, the mixer renders
+//! the samples of every 60 Hz frame from whether the sound timer was running
+//! during it, so the sound follows emulated time exactly. The tone fades in
+//! and out over a few milliseconds to avoid clicks.
+
+use std::f32::consts::TAU;
+
+/// The sample rate used when there is no audio device to match.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// How long the tone takes to fade in or out, in seconds.
+const FADE: f32 = 0.005;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Self::Square),
+            "sine" => Some(Self::Sine),
+            "triangle" => Some(Self::Triangle),
+            "noise" => Some(Self::Noise),
+            _ => None,
+        }
+    }
+}
+
+/// What the buzzer sounds like.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tone {
+    /// Pitch in Hz. For noise, how often a new random level is picked.
+    pub frequency: f32,
+    /// From 0 to 1.
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Tone {
+    pub fn new() -> Self {
+        Self {
+            frequency: 240.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Mixer {
+    tone: Tone,
+    sample_rate: u32,
+    muted: bool,
+    /// Position within the current period, from 0 to 1.
+    phase: f32,
+    /// The fade level, from 0 to 1.
+    level: f32,
+    /// Fraction of a sample left over from the previous frames.
+    remainder: f64,
+    noise: u32,
+    noise_level: f32,
+}
+
+impl Mixer {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        Self {
+            tone,
+            sample_rate,
+            muted: false,
+            phase: 0.0,
+            level: 0.0,
+            remainder: 0.0,
+            noise: 0x1234_5678,
+            noise_level: 1.0,
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        self.tone
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Returns whether the tone has completely faded out.
+    pub fn is_silent(&self) -> bool {
+        self.level == 0.0
+    }
+
+    /// Appends the samples of one frame lasting `1 / frame_rate` seconds to
+    /// `out`, with the tone on if `active`.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn render_frame(&mut self, active: bool, frame_rate: f64, out: &mut Vec<f32>) {
+        let samples = f64::from(self.sample_rate) / frame_rate + self.remainder;
+        let count = samples.floor();
+        self.remainder = samples - count;
+
+        let sample_rate = self.sample_rate as f32;
+        let step = self.tone.frequency / sample_rate;
+        let fade = 1.0 / (FADE * sample_rate);
+        let target = if active && !self.muted { 1.0 } else { 0.0 };
+
+        out.reserve(count as usize);
+        for _ in 0..count as usize {
+            self.level = if self.level < target {
+                (self.level + fade).min(target)
+            } else {
+                (self.level - fade).max(target)
+            };
+
+            if self.level == 0.0 {
+                // Start the next tone at the beginning of a period
+                self.phase = 0.0;
+                out.push(0.0);
+                continue;
+            }
+
+            out.push(self.wave() * self.tone.volume * self.level);
+            self.phase += step;
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+                self.noise_level = self.next_noise();
+            }
+        }
+    }
+
+    fn wave(&self) -> f32 {
+        match self.tone.waveform {
+            Waveform::Square => {
+                if self.phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (self.phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => self.noise_level,
+        }
+    }
+
+    /// Picks a random level from -1 to 1, with a xorshift generator.
+    #[allow(clippy::cast_precision_loss)]
+    fn next_noise(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_follow_the_sound_timer() {
+        let mut mixer = Mixer::new(Tone::new(), 44100);
+        let mut samples = Vec::new();
+
+        mixer.render_frame(false, 60.0, &mut samples);
+        assert_eq!(samples.len(), 735);
+        assert!(samples.iter().all(|&sample| sample == 0.0));
+
+        samples.clear();
+        mixer.render_frame(true, 60.0, &mut samples);
+        // Fades in instead of jumping to full volume
+        assert!(samples[0].abs() < 0.01);
+        assert!(samples.iter().any(|&sample| (sample - 0.25).abs() < 1e-6));
+
+        samples.clear();
+        mixer.render_frame(false, 60.0, &mut samples);
+        assert!(mixer.is_silent());
+        assert_eq!(samples.last(), Some(&0.0));
+    }
+
+    #[test]
+    fn keeps_fractional_samples() {
+        let mut mixer = Mixer::new(Tone::new(), 1000);
+        let mut samples = Vec::new();
+        for _ in 0..3 {
+            mixer.render_frame(false, 60.0, &mut samples);
+        }
+        assert_eq!(samples.len(), 50);
+    }
+}
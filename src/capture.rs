@@ -0,0 +1,166 @@
+//! Recording the display, as an animated GIF or a sequence of PNG images.
+//!
+//! Frames are expected at 60 Hz. Identical consecutive frames are only
+//! stored once: in a GIF the previous frame is shown for longer, and in a
+//! PNG sequence the file of the next frame is numbered after the frames
+//! skipped, so timing can be recovered from the file names.
+
+use crate::chip::FrameBuffer;
+use crate::image;
+use crate::image::Palette;
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// GIF delays are counted in hundredths of a second.
+const GIF_TICKS_PER_SECOND: u64 = 100;
+const FRAMES_PER_SECOND: u64 = 60;
+
+enum Sink<W: Write> {
+    Gif(gif::Encoder<W>),
+    Png(PathBuf),
+}
+
+pub struct Capture<W: Write = BufWriter<File>> {
+    sink: Sink<W>,
+    scale: usize,
+    palette: Palette,
+    /// Number of frames added so far.
+    frames: u64,
+    /// The last distinct frame, and the number of the frame it first
+    /// appeared in, while it has not been written yet.
+    pending: Option<(FrameBuffer, u64)>,
+}
+
+impl Capture {
+    /// Starts a capture to a GIF if `path` ends in `.gif`, and to a directory
+    /// of PNG images otherwise.
+    pub fn create(path: &Path, scale: usize, palette: Palette) -> io::Result<Self> {
+        let is_gif = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+        if is_gif {
+            Self::gif(BufWriter::new(File::create(path)?), scale, palette)
+        } else {
+            std::fs::create_dir_all(path)?;
+            Ok(Self::new(Sink::Png(path.to_path_buf()), scale, palette))
+        }
+    }
+}
+
+impl<W: Write> Capture<W> {
+    pub fn gif(w: W, scale: usize, palette: Palette) -> io::Result<Self> {
+        let (width, height) = image::size(scale);
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "the scale is too large");
+        let width = u16::try_from(width).map_err(|_| too_large())?;
+        let height = u16::try_from(height).map_err(|_| too_large())?;
+
+        let mut encoder =
+            gif::Encoder::new(w, width, height, &palette.to_bytes()).map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+
+        Ok(Self::new(Sink::Gif(encoder), scale, palette))
+    }
+
+    fn new(sink: Sink<W>, scale: usize, palette: Palette) -> Self {
+        Self {
+            sink,
+            scale,
+            palette,
+            frames: 0,
+            pending: None,
+        }
+    }
+
+    /// Adds the next frame, 1/60 s after the previous one.
+    pub fn add_frame(&mut self, frame_buffer: &FrameBuffer) -> io::Result<()> {
+        let repeated = self
+            .pending
+            .as_ref()
+            .is_some_and(|(pending, _)| pending == frame_buffer);
+        if !repeated {
+            self.flush()?;
+            self.pending = Some((*frame_buffer, self.frames));
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the last frame and returns the output of a GIF capture.
+    pub fn finish(mut self) -> io::Result<Option<W>> {
+        self.flush()?;
+        match self.sink {
+            Sink::Gif(encoder) => encoder.into_inner().map(Some),
+            Sink::Png(_) => Ok(None),
+        }
+    }
+
+    /// Writes the pending frame, which lasted until the current one.
+    fn flush(&mut self) -> io::Result<()> {
+        let Some((frame_buffer, start)) = self.pending.take() else {
+            return Ok(());
+        };
+
+        match &mut self.sink {
+            Sink::Gif(encoder) => {
+                // Rounding the start and end rather than the length keeps
+                // the total time right
+                let ticks = |frame| frame * GIF_TICKS_PER_SECOND / FRAMES_PER_SECOND;
+                let delay = u16::try_from(ticks(self.frames) - ticks(start)).unwrap_or(u16::MAX);
+
+                let (width, height) = image::size(self.scale);
+                let frame = gif::Frame {
+                    delay,
+                    width: u16::try_from(width).unwrap_or(u16::MAX),
+                    height: u16::try_from(height).unwrap_or(u16::MAX),
+                    buffer: image::indexed(&frame_buffer, self.scale).into(),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame).map_err(io::Error::other)
+            }
+            Sink::Png(dir) => {
+                let file = File::create(dir.join(format!("{start:06}.png")))?;
+                image::write_png(
+                    BufWriter::new(file),
+                    &frame_buffer,
+                    self.scale,
+                    &self.palette,
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{CHIP8_HEIGHT, CHIP8_WIDTH};
+
+    #[test]
+    fn skips_repeated_frames() {
+        let blank = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        let mut lit = blank;
+        lit[3][4] = 1;
+
+        let mut capture = Capture::gif(Vec::new(), 1, Palette::new()).unwrap();
+        for frame_buffer in [&blank, &blank, &blank, &lit, &lit, &blank] {
+            capture.add_frame(frame_buffer).unwrap();
+        }
+        let bytes = capture.finish().unwrap().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(&bytes[..]).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        // 3, 2 and 1 frames of 1/60 s
+        assert_eq!(delays, [5, 3, 2]);
+    }
+}
@@ -1,29 +1,55 @@
 mod clock;
 mod display;
+mod error;
+mod font;
+mod hook;
 mod keyboard;
 mod memory;
 mod opcode;
 mod register;
 mod settings;
+mod snapshot;
 mod stack;
+mod state;
 
+pub use clock::Clock;
 pub use display::FrameBuffer;
+pub use error::ChipError;
+pub use error::Fault;
+pub use error::RomWarning;
+pub use font::{Font, FontSet};
+pub use hook::SysHandler;
+pub use memory::FONT_ADDR;
+pub use opcode::MemAccess;
+pub use opcode::OpClass;
+pub use opcode::OpCode;
+pub use settings::OpPolicy;
+pub use settings::Platform;
+pub use settings::Settings;
+pub use snapshot::Snapshot;
+pub use stack::ReturnAddresses;
 
-use super::Sdl2Wrapper;
-use clock::Clock;
 use display::Display;
+use hook::SysHook;
 use keyboard::Keyboard;
 use memory::Memory;
-use opcode::OpCode;
 use register::Registers;
-use settings::Settings;
 use stack::Stack;
+use state::State;
 
-use anyhow::Result;
 use rand::Rng;
-use sdl2::event::Event;
 use std::convert::TryFrom;
 
+/// The outcome of a successfully executed `Chip8::cycle`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// An instruction was skipped under `OpPolicy::Warn`.
+    Warning(Fault),
+    /// An instruction was skipped under `OpPolicy::Pause`.
+    Pause(Fault),
+}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct Chip8 {
     settings: Settings,
@@ -36,90 +62,296 @@ pub struct Chip8 {
     program_counter: u16,
     delay_timer: u8,
     sound_timer: u8,
+    sys_hook: SysHook,
 }
 
 impl Chip8 {
+    /// # Panics
+    ///
+    /// Never: the default font fits in the default memory.
     pub fn new() -> Self {
-        Self {
-            settings: Settings::new(),
+        Self::with_settings(Settings::new()).expect("the default font fits in memory")
+    }
+
+    /// Fails if the font does not fit in the memory the settings give.
+    pub fn with_settings(settings: Settings) -> Result<Self, ChipError> {
+        Ok(Self {
+            stack: Stack::new(settings.stack_size),
             display: Display::new(),
-            memory: Memory::new(),
+            memory: Memory::new(settings.memory_size, &settings.font, settings.font_addr)?,
             v: Registers::new(),
-            stack: Stack::new(),
             input: Keyboard::new(),
             index: 0,
-            program_counter: 0x200,
+            program_counter: settings.rom_addr,
             delay_timer: 0,
             sound_timer: 0,
+            sys_hook: SysHook::default(),
+            settings,
+        })
+    }
+
+    /// Loads `rom` at the address the settings give. Returns what was odd
+    /// about it, if anything.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<Option<RomWarning>, ChipError> {
+        self.memory.load_rom(rom, self.settings.rom_addr)
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// Installs a handler for `0nnn` machine code calls, replacing the previous one.
+    pub fn set_sys_handler(&mut self, handler: SysHandler) {
+        self.sys_hook = SysHook(Some(handler));
+    }
+
+    pub fn register(&self, x: u8) -> u8 {
+        self.v[x & 0xF]
+    }
+
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.v[x & 0xF] = value;
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, addr: u16) {
+        self.program_counter = addr;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn read_byte(&self, addr: u16) -> Result<u8, ChipError> {
+        self.memory.get_byte(addr).copied()
+    }
+
+    pub fn write_byte(&mut self, addr: u16, value: u8) -> Result<(), ChipError> {
+        *self.memory.get_byte_mut(addr)? = value;
+        Ok(())
+    }
+
+    /// Reads the instruction word at `addr` without affecting the machine.
+    pub fn peek_word(&self, addr: u16) -> Result<u16, ChipError> {
+        self.memory.get_word(addr)
+    }
+
+    /// Iterates over the return addresses on the stack, from the innermost
+    /// subroutine out.
+    pub fn return_addresses(&self) -> ReturnAddresses<'_> {
+        self.stack.iter()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            v: self.v.to_array(),
+            index: self.index,
+            program_counter: self.program_counter,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            stack: self.stack.as_slice().to_vec(),
         }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
-        self.memory.load_rom(rom)
+    /// Encodes registers, stack, memory and display into a save state.
+    pub fn save_state(&self) -> Vec<u8> {
+        State {
+            snapshot: self.snapshot(),
+            memory: self.memory.as_slice().to_vec(),
+            frame_buffer: *self.display.get_frame_buffer(),
+        }
+        .encode()
     }
 
-    pub fn run(&mut self, sdl: &mut Sdl2Wrapper) -> Result<()> {
-        let mut cpu_clock = Clock::new(700.0);
-        let mut delay_clock = Clock::new(60.0);
-        let mut sound_clock = Clock::new(60.0);
+    /// Restores a save state made by `save_state`. Settings, handlers and the
+    /// keypad are left as they are.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), ChipError> {
+        let state = State::decode(state)?;
+        if state.memory.len() != self.memory.len() {
+            return Err(ChipError::InvalidState("the memory size does not match"));
+        }
 
-        loop {
-            match sdl.poll_event() {
-                Some(Event::Quit { .. }) => {
-                    break;
-                }
-                Some(Event::KeyDown { scancode, .. }) => {
-                    self.input
-                        .press_key(scancode.and_then(Sdl2Wrapper::translate_scancode));
-                }
-                _ => {
-                    if delay_clock.tick() {
-                        self.delay_timer = self.delay_timer.saturating_sub(1);
-                    }
+        let mut stack = Stack::new(self.settings.stack_size);
+        for &addr in &state.snapshot.stack {
+            stack
+                .push(addr)
+                .map_err(|_| ChipError::InvalidState("the stack is too deep"))?;
+        }
 
-                    if sound_clock.tick() {
-                        self.sound_timer = self.sound_timer.saturating_sub(1);
-                        if self.should_beep() {
-                            sdl.beep();
-                        } else {
-                            sdl.stop_beep();
-                        }
-                    }
+        for (x, &value) in (0..).zip(&state.snapshot.v) {
+            self.v[x] = value;
+        }
+        self.stack = stack;
+        self.index = state.snapshot.index;
+        self.program_counter = state.snapshot.program_counter;
+        self.delay_timer = state.snapshot.delay_timer;
+        self.sound_timer = state.snapshot.sound_timer;
+        self.memory.as_mut_slice().copy_from_slice(&state.memory);
+        self.display.set_frame_buffer(&state.frame_buffer);
+        Ok(())
+    }
 
-                    if cpu_clock.tick() {
-                        self.input.set_keys(sdl.poll_input());
-                        self.cycle()?;
-                        sdl.draw_on_canvas(self.get_frame_buffer())?;
-                    }
+    pub fn keys(&self) -> [bool; 16] {
+        self.input.keys()
+    }
+
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        self.input.set_keys(keys);
+    }
+
+    pub fn press_key(&mut self, key: Option<u8>) {
+        self.input.press_key(key);
+    }
+
+    pub fn tick_delay_timer(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+    }
+
+    pub fn tick_sound_timer(&mut self) {
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    pub fn should_beep(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn get_frame_buffer(&self) -> &FrameBuffer {
+        self.display.get_frame_buffer()
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    pub fn cycle(&mut self) -> Result<Flow, Fault> {
+        let pc = self.program_counter;
+        let word = self.fetch().map_err(|e| self.fault(e, pc, None))?;
+        let op = match Self::decode(word) {
+            Ok(op) => op,
+            Err(e) => return self.apply_policy(self.settings.invalid_op_policy, e, pc, word),
+        };
+
+        if let OpCode::SysAddr(addr) = op {
+            if self
+                .call_sys_handler(addr)
+                .map_err(|e| self.fault(e, pc, Some(word)))?
+            {
+                return Ok(Flow::Continue);
+            }
+
+            let policy = self.settings.sys_addr_policy;
+            return self.apply_policy(policy, ChipError::UnsupportedSysAddr(addr), pc, word);
+        }
+
+        self.execute(op)
+            .map_err(|e| self.fault(e, pc, Some(word)))?;
+        Ok(Flow::Continue)
+    }
+
+    fn call_sys_handler(&mut self, addr: u16) -> Result<bool, ChipError> {
+        // The handler is taken out for the duration of the call, so that it can
+        // borrow the machine mutably
+        match self.sys_hook.0.take() {
+            Some(mut handler) => {
+                let result = handler(self, addr);
+                if self.sys_hook.0.is_none() {
+                    self.sys_hook.0 = Some(handler);
                 }
+                result
             }
+            None => Ok(false),
         }
+    }
 
-        Ok(())
+    fn apply_policy(
+        &self,
+        policy: OpPolicy,
+        error: ChipError,
+        pc: u16,
+        word: u16,
+    ) -> Result<Flow, Fault> {
+        let fault = self.fault(error, pc, Some(word));
+        match policy {
+            OpPolicy::Ignore => Ok(Flow::Continue),
+            OpPolicy::Warn => Ok(Flow::Warning(fault)),
+            OpPolicy::Pause => Ok(Flow::Pause(fault)),
+            OpPolicy::Error => Err(fault),
+        }
     }
 
-    fn cycle(&mut self) -> Result<()> {
-        let word = self.fetch()?;
-        let op = Self::decode(word)?;
-        self.execute(op)
+    fn fault(&self, error: ChipError, program_counter: u16, word: Option<u16>) -> Fault {
+        // The fetch has already moved the program counter past the instruction
+        let snapshot = Snapshot {
+            program_counter,
+            ..self.snapshot()
+        };
+        Fault {
+            error,
+            program_counter,
+            word,
+            snapshot,
+        }
     }
 
-    fn fetch(&mut self) -> Result<u16> {
+    fn fetch(&mut self) -> Result<u16, ChipError> {
         let next_instr = self.memory.get_word(self.program_counter)?;
-        self.program_counter += 2;
+        self.skip()?;
         Ok(next_instr)
     }
 
-    fn decode(op: u16) -> Result<OpCode> {
+    /// Moves the program counter past the next instruction.
+    fn skip(&mut self) -> Result<(), ChipError> {
+        self.program_counter = self.offset(self.program_counter, 2)?;
+        Ok(())
+    }
+
+    /// Returns `addr + offset`, unless that is past the last address.
+    fn offset(&self, addr: u16, offset: u16) -> Result<u16, ChipError> {
+        addr.checked_add(offset)
+            .ok_or(ChipError::MemoryOutOfBounds {
+                addr,
+                max: self.memory.len(),
+            })
+    }
+
+    fn decode(op: u16) -> Result<OpCode, ChipError> {
         TryFrom::try_from(op)
     }
 
     #[allow(clippy::too_many_lines)]
-    fn execute(&mut self, op: OpCode) -> Result<()> {
+    fn execute(&mut self, op: OpCode) -> Result<(), ChipError> {
         match op {
             OpCode::SysAddr(_addr) => {
-                // Unimplemented on most machines, this is purposefully skipped
-                // TODO: Make this a possible error
+                // Machine code calls are dispatched in `cycle`, according to
+                // `Settings::sys_addr_policy`
             }
             OpCode::Clear => {
                 self.display.clear();
@@ -136,17 +368,17 @@ impl Chip8 {
             }
             OpCode::SkipEqual(x, kk) => {
                 if self.v[x] == kk {
-                    self.program_counter += 2;
+                    self.skip()?;
                 }
             }
             OpCode::SkipNotEqual(x, kk) => {
                 if self.v[x] != kk {
-                    self.program_counter += 2;
+                    self.skip()?;
                 }
             }
             OpCode::SkipEqualRegister(x, y) => {
                 if self.v[x] == self.v[y] {
-                    self.program_counter += 2;
+                    self.skip()?;
                 }
             }
             OpCode::Load(x, kk) => {
@@ -202,7 +434,7 @@ impl Chip8 {
             }
             OpCode::SkipNotEqualRegister(x, y) => {
                 if self.v[x] != self.v[y] {
-                    self.program_counter += 2;
+                    self.skip()?;
                 }
             }
             OpCode::SetIndexRegister(addr) => {
@@ -236,7 +468,7 @@ impl Chip8 {
                     }
 
                     #[allow(clippy::cast_possible_truncation)]
-                    let byte = self.memory.get_byte(self.index + oy as u16)?;
+                    let byte = self.memory.get_byte(self.offset(self.index, oy as u16)?)?;
                     for ox in 0..8 {
                         let pixel = (byte >> (7 - ox)) & 1;
                         let px_x = x + ox;
@@ -249,12 +481,12 @@ impl Chip8 {
             }
             OpCode::SkipKeyPressed(x) => {
                 if self.input.is_key_pressed(x) {
-                    self.program_counter += 2;
+                    self.skip()?;
                 }
             }
             OpCode::SkipKeyNotPressed(x) => {
                 if !self.input.is_key_pressed(x) {
-                    self.program_counter += 2;
+                    self.skip()?;
                 }
             }
             OpCode::LoadDelay(x) => {
@@ -282,30 +514,33 @@ impl Chip8 {
                 }
             }
             OpCode::IndexAtSprite(x) => {
-                self.index = Memory::index_of_font_char(x)?;
+                // Like the VIP, only the low digit of the register counts
+                self.index = Memory::index_of_font_char(self.v[x] & 0xF, self.settings.font_addr)?;
             }
             OpCode::BinaryCodeConversion(x) => {
                 let value = self.v[x];
                 *self.memory.get_byte_mut(self.index)? = value / 100;
-                *self.memory.get_byte_mut(self.index + 1)? = (value % 100) / 10;
-                *self.memory.get_byte_mut(self.index + 2)? = value % 10;
+                *self.memory.get_byte_mut(self.offset(self.index, 1)?)? = (value % 100) / 10;
+                *self.memory.get_byte_mut(self.offset(self.index, 2)?)? = value % 10;
             }
             OpCode::StoreAllRegisters(x) => {
                 for offset in 0..=x {
-                    *self.memory.get_byte_mut(self.index + u16::from(offset))? = self.v[offset];
+                    let addr = self.offset(self.index, u16::from(offset))?;
+                    *self.memory.get_byte_mut(addr)? = self.v[offset];
                 }
 
                 if self.settings.load_store_quirk {
-                    self.index += u16::from(x) + 1;
+                    self.index = self.index.wrapping_add(u16::from(x) + 1);
                 }
             }
             OpCode::LoadAllRegisters(x) => {
                 for offset in 0..=x {
-                    self.v[offset] = *self.memory.get_byte(self.index + u16::from(offset))?;
+                    let addr = self.offset(self.index, u16::from(offset))?;
+                    self.v[offset] = *self.memory.get_byte(addr)?;
                 }
 
                 if self.settings.load_store_quirk {
-                    self.index += u16::from(x) + 1;
+                    self.index = self.index.wrapping_add(u16::from(x) + 1);
                 }
             }
         }
@@ -314,23 +549,22 @@ impl Chip8 {
     }
 
     fn set_vf(&mut self, cond: bool) {
-        self.v[0xf] = if cond { 1 } else { 0 };
+        self.v[0xf] = u8::from(cond);
     }
 
     fn generate_random_byte() -> u8 {
         rand::thread_rng().gen::<u8>()
     }
+}
 
-    fn should_beep(&self) -> bool {
-        self.sound_timer > 0
-    }
-
-    fn get_frame_buffer(&mut self) -> &FrameBuffer {
-        self.display.get_frame_buffer()
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::identity_op)]
 mod tests {
     use super::*;
 
@@ -688,8 +922,20 @@ mod tests {
     #[test]
     fn index_at_sprite() {
         let mut cpu = Chip8::new();
+        cpu.v[0x1] = 0x1;
         assert!(cpu.execute(OpCode::IndexAtSprite(0x1)).is_ok());
-        assert_eq!(cpu.index, Memory::index_of_font_char(0x1).unwrap());
+        assert_eq!(
+            cpu.index,
+            Memory::index_of_font_char(0x1, FONT_ADDR).unwrap()
+        );
+    }
+
+    #[test]
+    fn index_at_sprite_uses_the_low_digit_of_the_register() {
+        let mut cpu = Chip8::new();
+        cpu.v[0x3] = 0x1A;
+        assert!(cpu.execute(OpCode::IndexAtSprite(0x3)).is_ok());
+        assert_eq!(cpu.index, FONT_ADDR + 0xA * 5);
     }
 
     #[test]
@@ -736,4 +982,245 @@ mod tests {
         assert_eq!(cpu.v[0x2], 0xE);
         assert_eq!(cpu.v[0x3], 0xF);
     }
+
+    #[test]
+    fn invalid_op_fault() {
+        let mut cpu = Chip8::new();
+        cpu.load_rom(&[0xFF, 0xFF]).unwrap();
+        cpu.v[0x3] = 0x42;
+
+        let fault = cpu.cycle().unwrap_err();
+        assert_eq!(fault.error, ChipError::InvalidOpCode(0xFFFF));
+        assert_eq!(fault.program_counter, 0x200);
+        assert_eq!(fault.word, Some(0xFFFF));
+        assert_eq!(fault.snapshot.v[0x3], 0x42);
+        assert_eq!(fault.snapshot.program_counter, 0x200);
+    }
+
+    #[test]
+    fn stack_underflow_fault() {
+        let mut cpu = Chip8::new();
+        cpu.load_rom(&[0x00, 0xEE]).unwrap();
+
+        let fault = cpu.cycle().unwrap_err();
+        assert_eq!(fault.error, ChipError::StackUnderflow);
+        assert_eq!(fault.program_counter, 0x200);
+        assert!(fault.snapshot.stack.is_empty());
+    }
+
+    #[test]
+    fn stack_size() {
+        let mut cpu = Chip8::with_settings(Settings::for_platform(Platform::Vip)).unwrap();
+        cpu.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..12 {
+            cpu.cycle().unwrap();
+        }
+        assert_eq!(cpu.return_addresses().len(), 12);
+        assert_eq!(cpu.return_addresses().next(), Some(0x202));
+
+        let fault = cpu.cycle().unwrap_err();
+        assert_eq!(fault.error, ChipError::StackOverflow);
+        assert_eq!(fault.snapshot.stack.len(), 12);
+
+        assert_eq!(cpu.settings().platform(), Some(Platform::Vip));
+        assert_eq!(Settings::new().platform(), None);
+    }
+
+    #[test]
+    fn load_rom() {
+        let mut cpu = Chip8::new();
+        assert_eq!(cpu.load_rom(&[0x12; 0x1000 - 0x200]), Ok(None));
+        assert_eq!(
+            cpu.load_rom(&[0x12; 0x1000 - 0x1FF]),
+            Err(ChipError::RomTooLarge {
+                size: 0xE01,
+                addr: 0x200,
+                max: 0xE00
+            })
+        );
+        assert_eq!(
+            cpu.load_rom(&[0x00, 0xE0, 0x12]),
+            Ok(Some(RomWarning::OddSize(3)))
+        );
+        assert_eq!(cpu.load_rom(&[]), Ok(Some(RomWarning::Empty)));
+
+        let mut eti = Chip8::with_settings(Settings::for_platform(Platform::Eti660)).unwrap();
+        assert!(eti.load_rom(&[0x12; 0x1000 - 0x5FF]).is_err());
+        eti.load_rom(&[0x66, 0x60]).unwrap();
+        assert_eq!(eti.program_counter(), 0x600);
+        eti.cycle().unwrap();
+        assert_eq!(eti.register(0x6), 0x60);
+        assert_eq!(eti.settings().platform(), Some(Platform::Eti660));
+
+        let mut modern = Chip8::with_settings(Settings::for_platform(Platform::Modern)).unwrap();
+        assert_eq!(modern.memory_size(), 0x10000);
+        assert!(modern.load_rom(&vec![0x12; 0x10000 - 0x200]).is_ok());
+
+        let mut past_the_end = Chip8::with_settings(Settings {
+            rom_addr: 0x1100,
+            ..Settings::new()
+        })
+        .unwrap();
+        assert_eq!(
+            past_the_end.load_rom(&[]),
+            Err(ChipError::MemoryOutOfBounds {
+                addr: 0x1100,
+                max: 0x1000
+            })
+        );
+        let mut at_the_end = Chip8::with_settings(Settings {
+            rom_addr: 0x1000,
+            ..Settings::new()
+        })
+        .unwrap();
+        assert_eq!(at_the_end.load_rom(&[]), Ok(Some(RomWarning::Empty)));
+    }
+
+    #[test]
+    fn end_of_memory() {
+        let mut cpu = Chip8::with_settings(Settings::for_platform(Platform::Modern)).unwrap();
+        cpu.program_counter = 0xFFFE;
+        let fault = cpu.cycle().unwrap_err();
+        assert_eq!(
+            fault.error,
+            ChipError::MemoryOutOfBounds {
+                addr: 0xFFFE,
+                max: 0x10000
+            }
+        );
+
+        let out_of_bounds = Err(ChipError::MemoryOutOfBounds {
+            addr: 0xFFFF,
+            max: 0x10000,
+        });
+        cpu.index = 0xFFFF;
+        assert_eq!(cpu.execute(OpCode::Draw(0x0, 0x0, 2)), out_of_bounds);
+        assert_eq!(
+            cpu.execute(OpCode::BinaryCodeConversion(0x0)),
+            out_of_bounds
+        );
+        assert_eq!(cpu.execute(OpCode::StoreAllRegisters(0x1)), out_of_bounds);
+        assert_eq!(cpu.execute(OpCode::LoadAllRegisters(0x1)), out_of_bounds);
+
+        cpu.v[0x0] = 0x1;
+        cpu.program_counter = 0xFFFE;
+        assert!(cpu.execute(OpCode::SkipEqual(0x0, 0x1)).is_err());
+    }
+
+    #[test]
+    fn fonts() {
+        let mut cpu = Chip8::with_settings(Settings {
+            font: FontSet::Vip.font(),
+            font_addr: 0x100,
+            ..Settings::new()
+        })
+        .unwrap();
+        cpu.v[0x0] = 0x7;
+        assert!(cpu.execute(OpCode::IndexAtSprite(0x0)).is_ok());
+        assert_eq!(cpu.index, 0x100 + 7 * 5);
+        let glyph: Vec<u8> = (0..5)
+            .map(|i| cpu.read_byte(cpu.index + i).unwrap())
+            .collect();
+        assert_eq!(glyph, [0xF0, 0x10, 0x10, 0x10, 0x10]);
+
+        let schip = Settings::for_platform(Platform::Schip);
+        assert_eq!(schip.font, FontSet::Schip.font());
+        assert_eq!(schip.font_range(), FONT_ADDR..FONT_ADDR + 180);
+        let eti = Settings::for_platform(Platform::Eti660);
+        assert_eq!(eti.font.as_bytes()[5..10], [0x20; 5]);
+
+        assert!(Font::from_bytes(vec![0; 80]).is_some());
+        assert!(Font::from_bytes(vec![0; 240]).is_some());
+        assert!(Font::from_bytes(vec![0; 79]).is_none());
+        assert!(Font::from_bytes(vec![0; 200]).is_none());
+
+        let past_the_end = Chip8::with_settings(Settings {
+            font_addr: 0xFF0,
+            ..Settings::new()
+        });
+        assert_eq!(
+            past_the_end.err(),
+            Some(ChipError::FontTooLarge {
+                size: 240,
+                addr: 0xFF0,
+                max: 0x10
+            })
+        );
+    }
+
+    #[test]
+    fn save_state() {
+        let mut cpu = Chip8::new();
+        cpu.load_rom(&[0x61, 0x12, 0x22, 0x06, 0x00, 0x00, 0xA3, 0x00, 0xD0, 0x05])
+            .unwrap();
+        for _ in 0..4 {
+            cpu.cycle().unwrap();
+        }
+
+        let state = cpu.save_state();
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored, cpu);
+
+        assert_eq!(
+            restored.load_state(&state[..state.len() - 1]),
+            Err(ChipError::InvalidState("truncated"))
+        );
+    }
+
+    #[test]
+    fn invalid_op_policy() {
+        let mut ignore = Chip8::new();
+        ignore.settings.invalid_op_policy = OpPolicy::Ignore;
+        ignore.load_rom(&[0xFF, 0xFF]).unwrap();
+        assert_eq!(ignore.cycle(), Ok(Flow::Continue));
+        assert_eq!(ignore.program_counter, 0x202);
+
+        let mut pause = Chip8::new();
+        pause.settings.invalid_op_policy = OpPolicy::Pause;
+        pause.load_rom(&[0xFF, 0xFF]).unwrap();
+        match pause.cycle() {
+            Ok(Flow::Pause(fault)) => assert_eq!(fault.error, ChipError::InvalidOpCode(0xFFFF)),
+            other => panic!("expected a pause, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sys_addr_policy() {
+        let mut warn = Chip8::new();
+        warn.settings.sys_addr_policy = OpPolicy::Warn;
+        warn.load_rom(&[0x01, 0x23]).unwrap();
+        match warn.cycle() {
+            Ok(Flow::Warning(fault)) => {
+                assert_eq!(fault.error, ChipError::UnsupportedSysAddr(0x123));
+            }
+            other => panic!("expected a warning, got {:?}", other),
+        }
+
+        let mut error = Chip8::new();
+        error.settings.sys_addr_policy = OpPolicy::Error;
+        error.load_rom(&[0x01, 0x23]).unwrap();
+        let fault = error.cycle().unwrap_err();
+        assert_eq!(fault.error, ChipError::UnsupportedSysAddr(0x123));
+        assert_eq!(fault.word, Some(0x0123));
+    }
+
+    #[test]
+    fn sys_handler() {
+        let mut cpu = Chip8::new();
+        cpu.settings.sys_addr_policy = OpPolicy::Error;
+        cpu.load_rom(&[0x01, 0x23, 0x04, 0x56]).unwrap();
+        cpu.set_sys_handler(Box::new(|cpu, addr| {
+            if addr == 0x123 {
+                cpu.set_register(0x0, 0xAB);
+                Ok(true)
+            } else {
+                Ok(false)
+            }
+        }));
+
+        assert_eq!(cpu.cycle(), Ok(Flow::Continue));
+        assert_eq!(cpu.v[0x0], 0xAB);
+        assert!(cpu.cycle().is_err());
+    }
 }
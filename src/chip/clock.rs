@@ -24,4 +24,8 @@ impl Clock {
             false
         }
     }
+
+    pub fn reset(&mut self) {
+        self.offset = Instant::now();
+    }
 }
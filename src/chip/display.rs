@@ -33,6 +33,10 @@ impl Display {
         &self.buffer
     }
 
+    pub fn set_frame_buffer(&mut self, buffer: &FrameBuffer) {
+        self.buffer = *buffer;
+    }
+
     #[cfg(test)]
     pub fn fill_buffer(&mut self) {
         self.buffer = [[1; 64]; 32];
@@ -0,0 +1,115 @@
+use super::snapshot::Snapshot;
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChipError {
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds {
+        addr: u16,
+        max: usize,
+    },
+    InvalidOpCode(u16),
+    UnsupportedSysAddr(u16),
+    InvalidFontChar(u8),
+    /// The rom does not fit in the `max` bytes from `addr` to the end of the
+    /// memory.
+    RomTooLarge {
+        size: usize,
+        addr: u16,
+        max: usize,
+    },
+    InvalidState(&'static str),
+    /// The font does not fit in the `max` bytes from `addr` to the end of the
+    /// memory.
+    FontTooLarge {
+        size: usize,
+        addr: u16,
+        max: usize,
+    },
+}
+
+impl fmt::Display for ChipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::StackOverflow => write!(f, "The stack has overflowed."),
+            Self::StackUnderflow => write!(f, "The stack has underflowed."),
+            Self::MemoryOutOfBounds { addr, max } => write!(
+                f,
+                "Attempting to access memory at {addr:X}, but {max:X} is the maximum."
+            ),
+            Self::InvalidOpCode(word) => write!(f, "Invalid op: 0x{word:04X}"),
+            Self::UnsupportedSysAddr(addr) => {
+                write!(f, "Machine code routine at 0x{addr:03X} is not supported.")
+            }
+            Self::InvalidFontChar(byte) => {
+                write!(f, "'{byte}' is not a character within the current font.")
+            }
+            Self::RomTooLarge { size, addr, max } => write!(
+                f,
+                "The rom that you are attempting to load is too large ({size}). {max} bytes is the maximum when loading at 0x{addr:03X}."
+            ),
+            Self::InvalidState(reason) => write!(f, "The save state is invalid: {reason}."),
+            Self::FontTooLarge { size, addr, max } => write!(
+                f,
+                "The font does not fit in memory ({size}). {max} bytes is the maximum when storing it at 0x{addr:03X}."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChipError {}
+
+/// Something odd about a rom that loaded anyway.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RomWarning {
+    Empty,
+    /// Instructions are two bytes long, so the rom may be cut short.
+    OddSize(usize),
+}
+
+impl fmt::Display for RomWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Empty => write!(f, "The rom is empty."),
+            Self::OddSize(size) => write!(
+                f,
+                "The rom has an odd size ({size}), its last instruction may be truncated."
+            ),
+        }
+    }
+}
+
+/// A `ChipError` raised while executing an instruction, together with the state
+/// of the machine at the moment it happened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fault {
+    pub error: ChipError,
+    /// Address of the instruction that caused the fault.
+    pub program_counter: u16,
+    /// The raw instruction, if it could be fetched.
+    pub word: Option<u16>,
+    /// The state of the machine, with the program counter still on the
+    /// faulting instruction.
+    pub snapshot: Snapshot,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.word {
+            Some(word) => write!(
+                f,
+                "{} (PC: 0x{:03X}, op: 0x{:04X})",
+                self.error, self.program_counter, word
+            ),
+            None => write!(f, "{} (PC: 0x{:03X})", self.error, self.program_counter),
+        }
+    }
+}
+
+impl std::error::Error for Fault {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
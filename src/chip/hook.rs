@@ -0,0 +1,32 @@
+use super::error::ChipError;
+use super::Chip8;
+
+use std::fmt;
+
+/// A routine emulating the machine code subroutine at the given address, as
+/// called by `0nnn`. It returns `Ok(true)` if it handled the call, and `Ok(false)`
+/// if the interpreter should fall back on `Settings::sys_addr_policy`.
+pub type SysHandler = Box<dyn FnMut(&mut Chip8, u16) -> Result<bool, ChipError>>;
+
+/// Holder for an optional `SysHandler`. Closures can neither be printed nor
+/// compared, so they are ignored when comparing or printing a `Chip8`.
+#[derive(Default)]
+pub struct SysHook(pub Option<SysHandler>);
+
+impl fmt::Debug for SysHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "SysHook(Some(..))"
+        } else {
+            "SysHook(None)"
+        })
+    }
+}
+
+impl PartialEq for SysHook {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SysHook {}
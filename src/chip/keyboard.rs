@@ -18,6 +18,10 @@ impl Keyboard {
         self.keys[key as usize]
     }
 
+    pub fn keys(&self) -> [bool; 16] {
+        self.keys
+    }
+
     pub fn set_keys(&mut self, keys: [bool; 16]) {
         self.keys = keys;
     }
@@ -1,89 +1,101 @@
-use anyhow::Error;
-use anyhow::Result;
+use super::error::{ChipError, RomWarning};
+use super::font::Font;
 
-const RAM_SIZE: usize = 4096;
+/// Where the font is stored by default.
+pub const FONT_ADDR: u16 = 0x50;
 
 #[derive(PartialEq, Eq, Debug)]
-pub struct Memory([u8; RAM_SIZE]);
+pub struct Memory(Box<[u8]>);
 
 impl Memory {
-    pub fn new() -> Self {
-        let mut mem = [0; RAM_SIZE];
-        Self::load_font(&mut mem);
-        Self(mem)
+    /// Creates a memory of `size` bytes holding `font` at `font_addr`.
+    pub fn new(size: usize, font: &Font, font_addr: u16) -> Result<Self, ChipError> {
+        let font = font.as_bytes();
+        let start = usize::from(font_addr);
+        if start + font.len() > size {
+            return Err(ChipError::FontTooLarge {
+                size: font.len(),
+                addr: font_addr,
+                max: size.saturating_sub(start),
+            });
+        }
+        let mut mem = vec![0; size];
+        mem[start..start + font.len()].copy_from_slice(font);
+        Ok(Self(mem.into_boxed_slice()))
     }
 
-    pub fn get_byte(&self, addr: u16) -> Result<&u8> {
-        self.0.get(addr as usize).ok_or_else(|| {
-            Error::msg(format!(
-                "Attempting to access memory at {:X}, but {:X} is the maximum. Exiting.",
-                addr, RAM_SIZE,
-            ))
-        })
+    pub fn get_byte(&self, addr: u16) -> Result<&u8, ChipError> {
+        self.0
+            .get(addr as usize)
+            .ok_or(ChipError::MemoryOutOfBounds {
+                addr,
+                max: self.0.len(),
+            })
     }
 
-    pub fn get_byte_mut(&mut self, addr: u16) -> Result<&mut u8> {
-        self.0.get_mut(addr as usize).ok_or_else(|| {
-            Error::msg(format!(
-                "Attempting to access memory at {:X}, but {:X} is the maximum. Exiting.",
-                addr, RAM_SIZE,
-            ))
-        })
+    pub fn get_byte_mut(&mut self, addr: u16) -> Result<&mut u8, ChipError> {
+        let max = self.0.len();
+        self.0
+            .get_mut(addr as usize)
+            .ok_or(ChipError::MemoryOutOfBounds { addr, max })
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0
     }
 
-    pub fn get_word(&self, addr: u16) -> Result<u16> {
-        Ok((u16::from(*self.get_byte(addr)?) << 8) | u16::from(*self.get_byte(addr + 1)?))
+    pub fn get_word(&self, addr: u16) -> Result<u16, ChipError> {
+        let next = addr.checked_add(1).ok_or(ChipError::MemoryOutOfBounds {
+            addr,
+            max: self.0.len(),
+        })?;
+        Ok((u16::from(*self.get_byte(addr)?) << 8) | u16::from(*self.get_byte(next)?))
     }
 
-    pub fn index_of_font_char(byte: u8) -> Result<u16> {
+    /// Returns the address of the small glyph of `byte` in a font stored at
+    /// `font_addr`.
+    pub fn index_of_font_char(byte: u8, font_addr: u16) -> Result<u16, ChipError> {
         if byte < 0x10 {
-            Ok(u16::from(0x50 + (byte * 5)))
+            Ok(font_addr.wrapping_add(u16::from(byte) * 5))
         } else {
-            Err(Error::msg(format!(
-                "'{}' is not a character within the current font. Exiting.",
-                byte
-            )))
+            Err(ChipError::InvalidFontChar(byte))
         }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) -> Result<()> {
-        if rom.len() >= (self.0.len() - 0x200) {
-            return Err(Error::msg(format!(
-                "The rom that you are attempting to load is too large ({}). {} bytes is the maximum.",
-                rom.len(),
-                self.0.len(),
-            )));
-        }
-
-        for (i, byte) in rom.iter().copied().enumerate() {
-            self.0[0x200 + i] = byte;
+    /// Copies `rom` to `addr`, as long as it fits in the memory from there.
+    pub fn load_rom(&mut self, rom: &[u8], addr: u16) -> Result<Option<RomWarning>, ChipError> {
+        let start = usize::from(addr);
+        // Even an empty rom needs its address to be in memory
+        let Some(max) = self.0.len().checked_sub(start) else {
+            return Err(ChipError::MemoryOutOfBounds {
+                addr,
+                max: self.0.len(),
+            });
+        };
+        if rom.len() > max {
+            return Err(ChipError::RomTooLarge {
+                size: rom.len(),
+                addr,
+                max,
+            });
         }
 
-        Ok(())
-    }
-
-    fn load_font(memory: &mut [u8]) {
-        const FONT: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
+        self.0[start..start + rom.len()].copy_from_slice(rom);
 
-        for (i, byte) in FONT.iter().copied().enumerate() {
-            memory[0x50 + i] = byte;
-        }
+        Ok(if rom.is_empty() {
+            Some(RomWarning::Empty)
+        } else if rom.len() % 2 == 1 {
+            Some(RomWarning::OddSize(rom.len()))
+        } else {
+            None
+        })
     }
 }
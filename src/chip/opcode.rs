@@ -1,6 +1,49 @@
-use anyhow::Error;
-use anyhow::Result;
+use super::error::ChipError;
+
 use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+
+/// Broad groups of instructions, used to filter tools such as the tracer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpClass {
+    /// Jumps, calls, returns and machine code calls
+    Flow,
+    /// Conditional skips on register values
+    Skip,
+    /// Loads and arithmetic on the V registers
+    Alu,
+    /// Instructions setting the index register
+    Index,
+    /// Instructions reading or writing memory at the index register
+    Memory,
+    Display,
+    Timer,
+    Input,
+}
+
+impl OpClass {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "flow" => Self::Flow,
+            "skip" => Self::Skip,
+            "alu" => Self::Alu,
+            "index" => Self::Index,
+            "memory" => Self::Memory,
+            "display" => Self::Display,
+            "timer" => Self::Timer,
+            "input" => Self::Input,
+            _ => return None,
+        })
+    }
+}
+
+/// The way in which an instruction accesses memory at the index register.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemAccess {
+    Read,
+    Write,
+}
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum OpCode {
@@ -41,11 +84,105 @@ pub enum OpCode {
     LoadAllRegisters(u8),
 }
 
+impl OpCode {
+    /// The name of the variant, without its operands.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SysAddr(..) => "SysAddr",
+            Self::Clear => "Clear",
+            Self::Return => "Return",
+            Self::Jump(..) => "Jump",
+            Self::Call(..) => "Call",
+            Self::SkipEqual(..) => "SkipEqual",
+            Self::SkipNotEqual(..) => "SkipNotEqual",
+            Self::SkipEqualRegister(..) => "SkipEqualRegister",
+            Self::Load(..) => "Load",
+            Self::Add(..) => "Add",
+            Self::LoadRegister(..) => "LoadRegister",
+            Self::OrRegister(..) => "OrRegister",
+            Self::AndRegister(..) => "AndRegister",
+            Self::XorRegister(..) => "XorRegister",
+            Self::AddRegister(..) => "AddRegister",
+            Self::SubRegister(..) => "SubRegister",
+            Self::ShiftRightRegister(..) => "ShiftRightRegister",
+            Self::SubReverseRegister(..) => "SubReverseRegister",
+            Self::ShiftLeftRegister(..) => "ShiftLeftRegister",
+            Self::SkipNotEqualRegister(..) => "SkipNotEqualRegister",
+            Self::SetIndexRegister(..) => "SetIndexRegister",
+            Self::JumpWithOffset(..) => "JumpWithOffset",
+            Self::Random(..) => "Random",
+            Self::Draw(..) => "Draw",
+            Self::SkipKeyPressed(..) => "SkipKeyPressed",
+            Self::SkipKeyNotPressed(..) => "SkipKeyNotPressed",
+            Self::LoadDelay(..) => "LoadDelay",
+            Self::LoadNextKeyPress(..) => "LoadNextKeyPress",
+            Self::SetDelayTimer(..) => "SetDelayTimer",
+            Self::SetSoundTimer(..) => "SetSoundTimer",
+            Self::AddIndexRegister(..) => "AddIndexRegister",
+            Self::IndexAtSprite(..) => "IndexAtSprite",
+            Self::BinaryCodeConversion(..) => "BinaryCodeConversion",
+            Self::StoreAllRegisters(..) => "StoreAllRegisters",
+            Self::LoadAllRegisters(..) => "LoadAllRegisters",
+        }
+    }
+
+    /// The memory this instruction reads or writes through the index register,
+    /// given the value of the index register before it is executed.
+    pub fn memory_access(self, index: u16) -> Option<(MemAccess, Range<u16>)> {
+        let (access, len) = match self {
+            Self::Draw(_, _, n) => (MemAccess::Read, u16::from(n)),
+            Self::LoadAllRegisters(x) => (MemAccess::Read, u16::from(x) + 1),
+            Self::StoreAllRegisters(x) => (MemAccess::Write, u16::from(x) + 1),
+            Self::BinaryCodeConversion(_) => (MemAccess::Write, 3),
+            _ => return None,
+        };
+
+        Some((access, index..index.saturating_add(len)))
+    }
+
+    pub fn class(self) -> OpClass {
+        match self {
+            Self::SysAddr(_)
+            | Self::Return
+            | Self::Jump(_)
+            | Self::Call(_)
+            | Self::JumpWithOffset(_) => OpClass::Flow,
+            Self::SkipEqual(..)
+            | Self::SkipNotEqual(..)
+            | Self::SkipEqualRegister(..)
+            | Self::SkipNotEqualRegister(..) => OpClass::Skip,
+            Self::Load(..)
+            | Self::Add(..)
+            | Self::LoadRegister(..)
+            | Self::OrRegister(..)
+            | Self::AndRegister(..)
+            | Self::XorRegister(..)
+            | Self::AddRegister(..)
+            | Self::SubRegister(..)
+            | Self::ShiftRightRegister(..)
+            | Self::SubReverseRegister(..)
+            | Self::ShiftLeftRegister(..)
+            | Self::Random(..) => OpClass::Alu,
+            Self::SetIndexRegister(_) | Self::AddIndexRegister(_) | Self::IndexAtSprite(_) => {
+                OpClass::Index
+            }
+            Self::BinaryCodeConversion(_)
+            | Self::StoreAllRegisters(_)
+            | Self::LoadAllRegisters(_) => OpClass::Memory,
+            Self::Clear | Self::Draw(..) => OpClass::Display,
+            Self::LoadDelay(_) | Self::SetDelayTimer(_) | Self::SetSoundTimer(_) => OpClass::Timer,
+            Self::SkipKeyPressed(_) | Self::SkipKeyNotPressed(_) | Self::LoadNextKeyPress(_) => {
+                OpClass::Input
+            }
+        }
+    }
+}
+
 impl TryFrom<u16> for OpCode {
-    type Error = anyhow::Error;
+    type Error = ChipError;
 
     #[allow(clippy::cast_possible_truncation)]
-    fn try_from(op: u16) -> Result<Self> {
+    fn try_from(op: u16) -> Result<Self, ChipError> {
         let nibbles = (
             ((op & 0xF000) >> 12) as u8,
             ((op & 0x0F00) >> 8) as u8,
@@ -95,7 +232,49 @@ impl TryFrom<u16> for OpCode {
             (0xF, _, 0x3, 0x3) => Self::BinaryCodeConversion(x),
             (0xF, _, 0x5, 0x5) => Self::StoreAllRegisters(x),
             (0xF, _, 0x6, 0x5) => Self::LoadAllRegisters(x),
-            _ => return Err(Error::msg(format!("Invalid op: 0x{:X}", op))),
+            _ => return Err(ChipError::InvalidOpCode(op)),
         })
     }
 }
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::SysAddr(addr) => write!(f, "SYS  0x{addr:03X}"),
+            Self::Clear => write!(f, "CLS"),
+            Self::Return => write!(f, "RET"),
+            Self::Jump(addr) => write!(f, "JP   0x{addr:03X}"),
+            Self::Call(addr) => write!(f, "CALL 0x{addr:03X}"),
+            Self::SkipEqual(x, kk) => write!(f, "SE   V{x:X}, 0x{kk:02X}"),
+            Self::SkipNotEqual(x, kk) => write!(f, "SNE  V{x:X}, 0x{kk:02X}"),
+            Self::SkipEqualRegister(x, y) => write!(f, "SE   V{x:X}, V{y:X}"),
+            Self::Load(x, kk) => write!(f, "LD   V{x:X}, 0x{kk:02X}"),
+            Self::Add(x, kk) => write!(f, "ADD  V{x:X}, 0x{kk:02X}"),
+            Self::LoadRegister(x, y) => write!(f, "LD   V{x:X}, V{y:X}"),
+            Self::OrRegister(x, y) => write!(f, "OR   V{x:X}, V{y:X}"),
+            Self::AndRegister(x, y) => write!(f, "AND  V{x:X}, V{y:X}"),
+            Self::XorRegister(x, y) => write!(f, "XOR  V{x:X}, V{y:X}"),
+            Self::AddRegister(x, y) => write!(f, "ADD  V{x:X}, V{y:X}"),
+            Self::SubRegister(x, y) => write!(f, "SUB  V{x:X}, V{y:X}"),
+            Self::ShiftRightRegister(x, y) => write!(f, "SHR  V{x:X}, V{y:X}"),
+            Self::SubReverseRegister(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Self::ShiftLeftRegister(x, y) => write!(f, "SHL  V{x:X}, V{y:X}"),
+            Self::SkipNotEqualRegister(x, y) => write!(f, "SNE  V{x:X}, V{y:X}"),
+            Self::SetIndexRegister(addr) => write!(f, "LD   I, 0x{addr:03X}"),
+            Self::JumpWithOffset(addr) => write!(f, "JP   V0, 0x{addr:03X}"),
+            Self::Random(x, kk) => write!(f, "RND  V{x:X}, 0x{kk:02X}"),
+            Self::Draw(x, y, n) => write!(f, "DRW  V{x:X}, V{y:X}, {n}"),
+            Self::SkipKeyPressed(x) => write!(f, "SKP  V{x:X}"),
+            Self::SkipKeyNotPressed(x) => write!(f, "SKNP V{x:X}"),
+            Self::LoadDelay(x) => write!(f, "LD   V{x:X}, DT"),
+            Self::LoadNextKeyPress(x) => write!(f, "LD   V{x:X}, K"),
+            Self::SetDelayTimer(x) => write!(f, "LD   DT, V{x:X}"),
+            Self::SetSoundTimer(x) => write!(f, "LD   ST, V{x:X}"),
+            Self::AddIndexRegister(x) => write!(f, "ADD  I, V{x:X}"),
+            Self::IndexAtSprite(x) => write!(f, "LD   F, V{x:X}"),
+            Self::BinaryCodeConversion(x) => write!(f, "LD   B, V{x:X}"),
+            Self::StoreAllRegisters(x) => write!(f, "LD   [I], V{x:X}"),
+            Self::LoadAllRegisters(x) => write!(f, "LD   V{x:X}, [I]"),
+        }
+    }
+}
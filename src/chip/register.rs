@@ -8,6 +8,10 @@ impl Registers {
     pub fn new() -> Self {
         Self([0; 16])
     }
+
+    pub fn to_array(&self) -> [u8; 16] {
+        self.0
+    }
 }
 
 impl Index<u8> for Registers {
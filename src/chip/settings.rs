@@ -1,7 +1,71 @@
+use super::font::{Font, FontSet};
+use super::memory::FONT_ADDR;
+
+use std::convert::TryFrom;
+use std::ops::Range;
+
+/// How the interpreter reacts to an instruction it cannot execute.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpPolicy {
+    /// Skip the instruction and carry on.
+    Ignore,
+    /// Skip the instruction, but report it to the frontend.
+    Warn,
+    /// Skip the instruction and ask the frontend to pause execution.
+    Pause,
+    /// Stop execution with an error.
+    Error,
+}
+
+/// A machine CHIP-8 programs were written for, with its quirks and limits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// The original interpreter on the COSMAC VIP.
+    Vip,
+    /// SUPER-CHIP on the HP 48 calculators.
+    Schip,
+    /// Modern interpreters such as Octo, with room for deep recursion and
+    /// the 64 KiB of XO-CHIP.
+    Modern,
+    /// The ETI-660, whose programs start at 0x600.
+    Eti660,
+}
+
+impl Platform {
+    pub const ALL: [Self; 4] = [Self::Vip, Self::Schip, Self::Modern, Self::Eti660];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "vip" => Some(Self::Vip),
+            "eti660" => Some(Self::Eti660),
+            "schip" => Some(Self::Schip),
+            "modern" => Some(Self::Modern),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Vip => "vip",
+            Self::Schip => "schip",
+            Self::Modern => "modern",
+            Self::Eti660 => "eti660",
+        }
+    }
+}
+
 #[allow(clippy::struct_excessive_bools)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Settings {
+    /// Where roms are loaded and execution starts.
     pub rom_addr: u16,
+    /// The size of the memory in bytes. Changes take effect when the machine
+    /// is created.
+    pub memory_size: usize,
+    /// The glyphs of the font, and where they are stored. Changes take
+    /// effect when the machine is created.
+    pub font: Font,
+    pub font_addr: u16,
     pub cpu_freq: u16,
     pub delay_freq: u16,
     pub sound_freq: u16,
@@ -10,12 +74,20 @@ pub struct Settings {
     pub index_overflow: bool,
     pub vertical_wrap: bool,
     pub jump_quirk: bool,
+    /// The most return addresses the stack holds. Changes take effect when
+    /// the machine is created.
+    pub stack_size: usize,
+    pub sys_addr_policy: OpPolicy,
+    pub invalid_op_policy: OpPolicy,
 }
 
 impl Settings {
     pub fn new() -> Self {
         Self {
             rom_addr: 0x200,
+            memory_size: 0x1000,
+            font: Font::default(),
+            font_addr: FONT_ADDR,
             cpu_freq: 700,
             delay_freq: 60,
             sound_freq: 60,
@@ -24,6 +96,83 @@ impl Settings {
             vertical_wrap: false,
             shift_quirk: true,
             jump_quirk: false,
+            stack_size: 16,
+            sys_addr_policy: OpPolicy::Ignore,
+            invalid_op_policy: OpPolicy::Error,
+        }
+    }
+
+    /// Returns the quirks and limits of `platform`, with the default policies.
+    pub fn for_platform(platform: Platform) -> Self {
+        let defaults = Self::new();
+        match platform {
+            Platform::Vip => Self {
+                load_store_quirk: true,
+                shift_quirk: true,
+                jump_quirk: false,
+                stack_size: 12,
+                font: FontSet::Vip.font(),
+                ..defaults
+            },
+            Platform::Schip => Self {
+                load_store_quirk: false,
+                shift_quirk: false,
+                jump_quirk: true,
+                stack_size: 16,
+                font: FontSet::Schip.font(),
+                ..defaults
+            },
+            Platform::Modern => Self {
+                load_store_quirk: true,
+                shift_quirk: true,
+                jump_quirk: false,
+                stack_size: 128,
+                memory_size: 0x10000,
+                ..defaults
+            },
+            Platform::Eti660 => Self {
+                rom_addr: 0x600,
+                font: FontSet::Eti660.font(),
+                ..Self::for_platform(Platform::Vip)
+            },
         }
     }
+
+    /// Switches to the quirks and limits of `platform`, keeping the rest.
+    pub fn apply_platform(&mut self, platform: Platform) {
+        let profile = Self::for_platform(platform);
+        self.load_store_quirk = profile.load_store_quirk;
+        self.shift_quirk = profile.shift_quirk;
+        self.jump_quirk = profile.jump_quirk;
+        self.stack_size = profile.stack_size;
+        self.rom_addr = profile.rom_addr;
+        self.memory_size = profile.memory_size;
+        self.font = profile.font;
+    }
+
+    /// Returns the addresses the font is stored at.
+    pub fn font_range(&self) -> Range<u16> {
+        let len = u16::try_from(self.font.as_bytes().len()).unwrap_or(u16::MAX);
+        self.font_addr..self.font_addr.saturating_add(len)
+    }
+
+    /// Returns the platform whose quirks and limits these settings have, if
+    /// any.
+    pub fn platform(&self) -> Option<Platform> {
+        Platform::ALL.iter().copied().find(|&platform| {
+            let profile = Self::for_platform(platform);
+            self.load_store_quirk == profile.load_store_quirk
+                && self.shift_quirk == profile.shift_quirk
+                && self.jump_quirk == profile.jump_quirk
+                && self.stack_size == profile.stack_size
+                && self.rom_addr == profile.rom_addr
+                && self.memory_size == profile.memory_size
+        })
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
 }
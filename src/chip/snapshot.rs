@@ -0,0 +1,10 @@
+/// A copy of the CPU visible state of a `Chip8`, taken at a single point in time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub v: [u8; 16],
+    pub index: u16,
+    pub program_counter: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack: Vec<u16>,
+}
@@ -1,36 +1,45 @@
-use anyhow::Error;
-use anyhow::Result;
+use super::error::ChipError;
 
+use std::iter::{Copied, Rev};
+use std::slice::Iter;
+
+/// The return addresses of the subroutines being executed.
 #[derive(PartialEq, Eq, Debug)]
 pub struct Stack {
-    stack: [u16; 16],
-    stack_ptr: usize,
+    stack: Vec<u16>,
+    size: usize,
 }
 
 impl Stack {
-    pub fn new() -> Self {
+    /// Creates an empty stack holding at most `size` return addresses.
+    pub fn new(size: usize) -> Self {
         Self {
-            stack: [0; 16],
-            stack_ptr: 0,
+            stack: Vec::with_capacity(size),
+            size,
         }
     }
 
-    pub fn pop(&mut self) -> Result<u16> {
-        if self.stack_ptr == 0 {
-            Err(Error::msg("The stack has underflowed."))
-        } else {
-            self.stack_ptr -= 1;
-            Ok(self.stack[self.stack_ptr])
-        }
+    pub fn pop(&mut self) -> Result<u16, ChipError> {
+        self.stack.pop().ok_or(ChipError::StackUnderflow)
     }
 
-    pub fn push(&mut self, byte: u16) -> Result<()> {
-        if self.stack_ptr >= 16 {
-            Err(Error::msg("The stack has overflowed."))
+    pub fn push(&mut self, byte: u16) -> Result<(), ChipError> {
+        if self.stack.len() >= self.size {
+            Err(ChipError::StackOverflow)
         } else {
-            self.stack[self.stack_ptr] = byte;
-            self.stack_ptr += 1;
+            self.stack.push(byte);
             Ok(())
         }
     }
+
+    pub fn as_slice(&self) -> &[u16] {
+        &self.stack
+    }
+
+    /// Iterates over the return addresses, from the innermost subroutine out.
+    pub fn iter(&self) -> ReturnAddresses<'_> {
+        self.stack.iter().rev().copied()
+    }
 }
+
+pub type ReturnAddresses<'a> = Copied<Rev<Iter<'a, u16>>>;
@@ -0,0 +1,130 @@
+//! A binary encoding of the complete state of a machine, for save states.
+//!
+//! All words are big endian. The layout is: the magic `CH8S` and a version
+//! byte, `V0` to `VF`, `I`, `PC`, `DT`, `ST`, the stack depth and the stack,
+//! the memory size (32 bit) and the memory, then one byte per pixel of the
+//! frame buffer.
+
+use super::display::FrameBuffer;
+use super::error::ChipError;
+use super::snapshot::Snapshot;
+use crate::{CHIP8_HEIGHT, CHIP8_WIDTH};
+
+use std::convert::TryFrom;
+
+const MAGIC: &[u8; 4] = b"CH8S";
+const VERSION: u8 = 1;
+
+/// Everything stored in a save state.
+pub struct State {
+    pub snapshot: Snapshot,
+    pub memory: Vec<u8>,
+    pub frame_buffer: FrameBuffer,
+}
+
+impl State {
+    pub fn encode(&self) -> Vec<u8> {
+        let snapshot = &self.snapshot;
+        let mut bytes = Vec::with_capacity(self.memory.len() + CHIP8_WIDTH * CHIP8_HEIGHT + 64);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&snapshot.v);
+        bytes.extend_from_slice(&snapshot.index.to_be_bytes());
+        bytes.extend_from_slice(&snapshot.program_counter.to_be_bytes());
+        bytes.push(snapshot.delay_timer);
+        bytes.push(snapshot.sound_timer);
+
+        bytes.push(u8::try_from(snapshot.stack.len()).unwrap_or(u8::MAX));
+        for addr in &snapshot.stack {
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(
+            &u32::try_from(self.memory.len())
+                .unwrap_or(u32::MAX)
+                .to_be_bytes(),
+        );
+        bytes.extend_from_slice(&self.memory);
+        for row in &self.frame_buffer {
+            bytes.extend_from_slice(row);
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ChipError> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(ChipError::InvalidState("not a save state"));
+        }
+
+        if reader.byte()? != VERSION {
+            return Err(ChipError::InvalidState("unsupported version"));
+        }
+
+        let mut v = [0; 16];
+        v.copy_from_slice(reader.take(16)?);
+        let index = reader.word()?;
+        let program_counter = reader.word()?;
+        let delay_timer = reader.byte()?;
+        let sound_timer = reader.byte()?;
+
+        let depth = reader.byte()?;
+        let stack = (0..depth)
+            .map(|_| reader.word())
+            .collect::<Result<Vec<u16>, ChipError>>()?;
+
+        let len = u32::from_be_bytes([
+            reader.byte()?,
+            reader.byte()?,
+            reader.byte()?,
+            reader.byte()?,
+        ]);
+        let memory = reader.take(len as usize)?.to_vec();
+
+        let mut frame_buffer = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        for row in &mut frame_buffer {
+            row.copy_from_slice(reader.take(CHIP8_WIDTH)?);
+        }
+
+        if !reader.0.is_empty() {
+            return Err(ChipError::InvalidState("trailing data"));
+        }
+
+        Ok(Self {
+            snapshot: Snapshot {
+                v,
+                index,
+                program_counter,
+                delay_timer,
+                sound_timer,
+                stack,
+            },
+            memory,
+            frame_buffer,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ChipError> {
+        if self.0.len() < len {
+            return Err(ChipError::InvalidState("truncated"));
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, ChipError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, ChipError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
@@ -0,0 +1,297 @@
+//! Post-processing for the look of a CRT, done on the CPU.
+//!
+//! The effects work on the display scaled up to the window, one RGB pixel
+//! at a time: a dark grid between the pixels, darker scanlines, a glow
+//! around lit pixels, and a bulging screen with rounded edges.
+
+use crate::image::{size, Palette};
+use crate::phosphor::Levels;
+
+/// The brightness of every other line with scanlines.
+const SCANLINE_LEVEL: f32 = 0.6;
+/// The brightness of the gaps between pixels with the grid.
+const GRID_LEVEL: f32 = 0.4;
+/// How much of the blurred image is added with bloom.
+const BLOOM_LEVEL: f32 = 0.5;
+/// How far the edges bend with curvature, relative to the screen size.
+const CURVATURE: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Effect {
+    Scanlines,
+    Grid,
+    Bloom,
+    Curvature,
+}
+
+impl Effect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scanlines" => Some(Self::Scanlines),
+            "grid" => Some(Self::Grid),
+            "bloom" => Some(Self::Bloom),
+            "curvature" => Some(Self::Curvature),
+            _ => None,
+        }
+    }
+}
+
+/// The effects to apply, none by default.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Effects {
+    pub scanlines: bool,
+    pub grid: bool,
+    pub bloom: bool,
+    pub curvature: bool,
+}
+
+impl Effects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn all() -> Self {
+        Self {
+            scanlines: true,
+            grid: true,
+            bloom: true,
+            curvature: true,
+        }
+    }
+
+    pub fn insert(&mut self, effect: Effect) {
+        match effect {
+            Effect::Scanlines => self.scanlines = true,
+            Effect::Grid => self.grid = true,
+            Effect::Bloom => self.bloom = true,
+            Effect::Curvature => self.curvature = true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Renders the levels of a filtered display at `scale` with the effects, as
+/// rows of RGB bytes.
+pub fn render(levels: &Levels, scale: usize, palette: &Palette, effects: Effects) -> Vec<u8> {
+    let (width, height) = size(scale);
+    let mut image: Vec<[f32; 3]> = Vec::with_capacity(width * height);
+    for row in levels {
+        let line: Vec<[f32; 3]> = row
+            .iter()
+            .flat_map(|&level| std::iter::repeat_n(color(*palette, level), scale))
+            .collect();
+        for _ in 0..scale {
+            image.extend_from_slice(&line);
+        }
+    }
+
+    // The glow comes from the pixels themselves, not the darkened gaps
+    let glow = effects
+        .bloom
+        .then(|| blur(&image, width, height, scale / 2));
+
+    // Too small a scale leaves no room for gaps
+    if effects.grid && scale >= 3 {
+        let gap = (scale / 8).max(1);
+        for (i, pixel) in image.iter_mut().enumerate() {
+            let (x, y) = (i % width, i / width);
+            if x % scale >= scale - gap || y % scale >= scale - gap {
+                dim(pixel, GRID_LEVEL);
+            }
+        }
+    }
+
+    if effects.scanlines {
+        for line in image.chunks_mut(width).skip(1).step_by(2) {
+            for pixel in line {
+                dim(pixel, SCANLINE_LEVEL);
+            }
+        }
+    }
+
+    if let Some(glow) = glow {
+        for (pixel, glow) in image.iter_mut().zip(glow) {
+            for (channel, glow) in pixel.iter_mut().zip(glow) {
+                *channel += glow * BLOOM_LEVEL;
+            }
+        }
+    }
+
+    if effects.curvature {
+        image = curve(&image, width, height);
+    }
+
+    image.iter().flat_map(|pixel| pixel.map(to_byte)).collect()
+}
+
+fn color(palette: Palette, level: f32) -> [f32; 3] {
+    let mut color = [0.0; 3];
+    for ((channel, &from), &to) in color
+        .iter_mut()
+        .zip(&palette.background)
+        .zip(&palette.foreground)
+    {
+        let (from, to) = (f32::from(from), f32::from(to));
+        *channel = from + (to - from) * level.clamp(0.0, 1.0);
+    }
+
+    color
+}
+
+fn dim(pixel: &mut [f32; 3], level: f32) {
+    for channel in pixel {
+        *channel *= level;
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_byte(channel: f32) -> u8 {
+    channel.round().clamp(0.0, 255.0) as u8
+}
+
+/// Blurs the image with a box of `2 * radius + 1` pixels, horizontally then
+/// vertically. Outside the image counts as black.
+fn blur(image: &[[f32; 3]], width: usize, height: usize, radius: usize) -> Vec<[f32; 3]> {
+    let mut horizontal = vec![[0.0; 3]; image.len()];
+    for y in 0..height {
+        blur_line(
+            width,
+            radius,
+            |x| image[y * width + x],
+            |x, pixel| {
+                horizontal[y * width + x] = pixel;
+            },
+        );
+    }
+
+    let mut blurred = vec![[0.0; 3]; image.len()];
+    for x in 0..width {
+        blur_line(
+            height,
+            radius,
+            |y| horizontal[y * width + x],
+            |y, pixel| {
+                blurred[y * width + x] = pixel;
+            },
+        );
+    }
+
+    blurred
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn blur_line(
+    len: usize,
+    radius: usize,
+    get: impl Fn(usize) -> [f32; 3],
+    mut set: impl FnMut(usize, [f32; 3]),
+) {
+    let mut sums = vec![[0.0; 3]; len + 1];
+    for i in 0..len {
+        let pixel = get(i);
+        for channel in 0..3 {
+            sums[i + 1][channel] = sums[i][channel] + pixel[channel];
+        }
+    }
+
+    let window = (2 * radius + 1) as f32;
+    for i in 0..len {
+        let (start, end) = (i.saturating_sub(radius), (i + radius + 1).min(len));
+        let mut pixel = [0.0; 3];
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            *value = (sums[end][channel] - sums[start][channel]) / window;
+        }
+        set(i, pixel);
+    }
+}
+
+/// Bends the image as if seen on a bulging screen. What falls outside of
+/// the screen is black.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn curve(image: &[[f32; 3]], width: usize, height: usize) -> Vec<[f32; 3]> {
+    let (right, bottom) = ((width - 1) as f32, (height - 1) as f32);
+    let mut curved = vec![[0.0; 3]; image.len()];
+    for (i, pixel) in curved.iter_mut().enumerate() {
+        // From -1 to 1 across the screen
+        let u = (i % width) as f32 / right * 2.0 - 1.0;
+        let v = (i / width) as f32 / bottom * 2.0 - 1.0;
+        let (u, v) = (u * (1.0 + CURVATURE * v * v), v * (1.0 + CURVATURE * u * u));
+        if u.abs() > 1.0 || v.abs() > 1.0 {
+            continue;
+        }
+
+        let x = (u.mul_add(0.5, 0.5) * right).round() as usize;
+        let y = (v.mul_add(0.5, 0.5) * bottom).round() as usize;
+        *pixel = image[y * width + x];
+    }
+
+    curved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CHIP8_HEIGHT, CHIP8_WIDTH};
+
+    fn pixel(image: &[u8], scale: usize, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * CHIP8_WIDTH * scale + x) * 3;
+        [image[i], image[i + 1], image[i + 2]]
+    }
+
+    #[test]
+    fn effects() {
+        let mut levels = [[0.0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        levels[1][1] = 1.0;
+        let palette = Palette::new();
+
+        let plain = render(&levels, 4, &palette, Effects::new());
+        assert_eq!(plain.len(), CHIP8_WIDTH * CHIP8_HEIGHT * 16 * 3);
+        assert_eq!(pixel(&plain, 4, 4, 4), [255; 3]);
+        assert_eq!(pixel(&plain, 4, 3, 4), [0; 3]);
+
+        let mut effects = Effects::new();
+        effects.insert(Effect::Scanlines);
+        effects.insert(Effect::Grid);
+        let lines = render(&levels, 4, &palette, effects);
+        assert_eq!(pixel(&lines, 4, 4, 4), [255; 3]);
+        assert_eq!(pixel(&lines, 4, 4, 5), [153; 3]);
+        assert_eq!(pixel(&lines, 4, 7, 4), [102; 3]);
+
+        let bloom = render(
+            &levels,
+            4,
+            &palette,
+            Effects {
+                bloom: true,
+                ..Effects::new()
+            },
+        );
+        assert!(pixel(&bloom, 4, 3, 4)[0] > 0);
+        assert_eq!(pixel(&bloom, 4, 20, 20), [0; 3]);
+
+        let curved = render(
+            &[[1.0; CHIP8_WIDTH]; CHIP8_HEIGHT],
+            4,
+            &palette,
+            Effects::all(),
+        );
+        assert_eq!(pixel(&curved, 4, 0, 0), [0; 3]);
+        assert_ne!(pixel(&curved, 4, 128, 64), [0; 3]);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Effect::from_name("bloom"), Some(Effect::Bloom));
+        assert_eq!(Effect::from_name("blur"), None);
+        assert!(Effects::new().is_empty());
+        assert!(!Effects::all().is_empty());
+    }
+}
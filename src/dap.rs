@@ -0,0 +1,975 @@
+//! A Debug Adapter Protocol server, so editors can debug CHIP-8 programs.
+//!
+//! The server listens on a local TCP port for a single client. A `launch`
+//! request takes the path of the ROM as `program`, and optionally the path of
+//! its source map as `sourceMap` (see [`crate::source_map`]), which otherwise
+//! defaults to the ROM path with a `.map` extension when that file exists.
+//! Likewise, `symbols` names a symbol file (see [`crate::symbols`]) and
+//! defaults to a `.sym` file next to the ROM, to label stack frames and
+//! disassembly.
+
+use crate::chip::{Chip8, OpCode};
+use crate::debug::{Breakpoints, StopReason};
+use crate::octo;
+use crate::source_map::{Location, SourceMap};
+use crate::symbols::Symbols;
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+
+/// The only thread of the machine.
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+const STACK_REFERENCE: u64 = 2;
+/// The key under which instruction breakpoints are kept apart from source ones.
+const INSTRUCTIONS: &str = "";
+
+/// A request from the client to change how the machine is run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DapAction {
+    /// Load a new ROM, and stay paused until configuration is done.
+    Launch(Vec<u8>),
+    Continue,
+    Interrupt,
+    /// The client went away, so the program runs on its own again.
+    Disconnect,
+    /// End the debugging session and the emulator with it.
+    Terminate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum StepMode {
+    Instruction,
+    In,
+    Over,
+    Out,
+}
+
+/// A step in progress, which lasts until the program reaches another line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Stepping {
+    mode: StepMode,
+    location: Option<Location>,
+    depth: usize,
+}
+
+pub struct DapServer {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    input: Vec<u8>,
+    seq: u64,
+    /// Events to send once the response to the current request is out.
+    events: Vec<Value>,
+    source_map: SourceMap,
+    /// The directory the files of the source map are relative to.
+    source_root: PathBuf,
+    symbols: Symbols,
+    stop_on_entry: bool,
+    /// Breakpoint ids and addresses, by the source they were set in.
+    sources: BTreeMap<String, Vec<(u64, u16)>>,
+    next_breakpoint_id: u64,
+    stepping: Option<Stepping>,
+}
+
+impl DapServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self::with_listener(listener))
+    }
+
+    fn with_listener(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            stream: None,
+            input: Vec::new(),
+            seq: 1,
+            events: Vec::new(),
+            source_map: SourceMap::default(),
+            source_root: PathBuf::new(),
+            symbols: Symbols::new(),
+            stop_on_entry: false,
+            sources: BTreeMap::new(),
+            next_breakpoint_id: 1,
+            stepping: None,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Accepts a new client and answers all requests received so far. Returns
+    /// the requests to resume or stop the machine, in order.
+    pub fn poll(
+        &mut self,
+        chip8: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+    ) -> io::Result<Vec<DapAction>> {
+        if self.stream.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.stream = Some(stream);
+                    self.input.clear();
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !self.receive()? {
+            self.stream = None;
+            self.stepping = None;
+            return Ok(vec![DapAction::Disconnect]);
+        }
+
+        let mut actions = Vec::new();
+        while let Some(request) = self.next_message() {
+            let command = request["command"].as_str().unwrap_or_default();
+            let result = self.handle_request(
+                command,
+                &request["arguments"],
+                chip8,
+                breakpoints,
+                &mut actions,
+            );
+
+            let mut response = json!({
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+                "success": result.is_ok(),
+            });
+            match result {
+                Ok(body) => response["body"] = body,
+                Err(message) => response["message"] = Value::from(message),
+            }
+
+            self.send(response)?;
+            for event in std::mem::take(&mut self.events) {
+                self.send(event)?;
+            }
+        }
+
+        Ok(actions)
+    }
+
+    /// Tells the client that the machine has stopped.
+    pub fn report_stop(&mut self, reason: &StopReason) -> io::Result<()> {
+        self.stepping = None;
+
+        let (reason, text) = match reason {
+            StopReason::Breakpoint(_) => ("breakpoint", None),
+            StopReason::Watchpoint { .. } => ("data breakpoint", None),
+            StopReason::Step => ("step", None),
+            StopReason::Interrupt => ("pause", None),
+            StopReason::Pause(fault) | StopReason::Fault(fault) => {
+                ("exception", Some(self.symbols.describe(fault)))
+            }
+            StopReason::Suspicious(suspicion) => ("exception", Some(suspicion.to_string())),
+        };
+
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["description"] = Value::from(text.clone());
+            body["text"] = Value::from(text);
+        }
+
+        self.event("stopped", body)
+    }
+
+    /// Forgets the breakpoints set by the client, after they were removed
+    /// from `Breakpoints`, and tells it they no longer hold.
+    pub fn clear_breakpoints(&mut self, reason: &str) -> io::Result<()> {
+        let sources = std::mem::take(&mut self.sources);
+        for (id, _) in sources.into_values().flatten() {
+            let breakpoint = json!({ "id": id, "verified": false, "message": reason });
+            self.event(
+                "breakpoint",
+                json!({ "reason": "changed", "breakpoint": breakpoint }),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Tells the client that the program is done.
+    pub fn terminate(&mut self) -> io::Result<()> {
+        self.event("exited", json!({ "exitCode": 0 }))?;
+        self.event("terminated", json!({}))
+    }
+
+    /// Returns whether a step requested by the client has completed, after
+    /// an instruction was executed.
+    pub fn step_done(&self, chip8: &Chip8) -> bool {
+        let Some(stepping) = &self.stepping else {
+            return false;
+        };
+
+        let depth = chip8.snapshot().stack.len();
+        let new_line = || {
+            self.source_map
+                .location(chip8.program_counter())
+                .is_some_and(|location| Some(location) != stepping.location.as_ref())
+        };
+
+        match stepping.mode {
+            StepMode::Instruction => true,
+            StepMode::In => new_line(),
+            StepMode::Over => depth <= stepping.depth && new_line(),
+            StepMode::Out => depth < stepping.depth,
+        }
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        if self.stream.is_none() {
+            return Ok(());
+        }
+
+        let mut message = json!({ "type": "event", "event": event });
+        message["body"] = body;
+        self.send(message)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn handle_request(
+        &mut self,
+        command: &str,
+        args: &Value,
+        chip8: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+        actions: &mut Vec<DapAction>,
+    ) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsSteppingGranularity": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => {
+                let program = args["program"]
+                    .as_str()
+                    .ok_or("The launch configuration needs a 'program'")?;
+                let rom = octo::read_rom(Path::new(program))
+                    .map_err(|e| format!("Could not read '{program}': {e}"))?;
+                // Fail the launch, rather than the emulator, if the ROM does not load
+                Chip8::with_settings(chip8.settings().clone())
+                    .and_then(|mut chip8| chip8.load_rom(&rom))
+                    .map_err(|e| format!("Could not load '{program}': {e}"))?;
+                self.load_source_map(Path::new(program), args["sourceMap"].as_str())?;
+                self.load_symbols(Path::new(program), args["symbols"].as_str())?;
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+                actions.push(DapAction::Launch(rom));
+                self.events
+                    .push(json!({ "type": "event", "event": "initialized" }));
+                Ok(Value::Null)
+            }
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.events.push(json!({
+                        "type": "event",
+                        "event": "stopped",
+                        "body": { "reason": "entry", "threadId": THREAD_ID, "allThreadsStopped": true },
+                    }));
+                } else {
+                    actions.push(DapAction::Continue);
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let path = args["source"]["path"]
+                    .as_str()
+                    .ok_or("The source has no path")?;
+                let lines: Vec<u64> = args["breakpoints"]
+                    .as_array()
+                    .map(|list| list.iter().filter_map(|b| b["line"].as_u64()).collect())
+                    .unwrap_or_default();
+
+                let mut addrs = Vec::new();
+                let mut verified = Vec::new();
+                for line in lines {
+                    let found = u32::try_from(line)
+                        .ok()
+                        .and_then(|line| self.source_map.find(Path::new(path), line));
+                    verified.push(match found {
+                        Some((addr, actual)) => {
+                            let id = self.breakpoint_id();
+                            addrs.push((id, addr));
+                            json!({ "id": id, "verified": true, "line": actual, "instructionReference": Self::reference(addr) })
+                        }
+                        None => json!({ "verified": false, "line": line, "message": "No code at this line" }),
+                    });
+                }
+
+                self.replace_breakpoints(path, addrs, breakpoints);
+                Ok(json!({ "breakpoints": verified }))
+            }
+            "setInstructionBreakpoints" => {
+                let mut addrs = Vec::new();
+                let mut verified = Vec::new();
+                for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+                    let addr = Self::parse_reference(&breakpoint["instructionReference"]).and_then(
+                        |addr| {
+                            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+                            u16::try_from(i64::from(addr) + offset).ok()
+                        },
+                    );
+                    match addr {
+                        Some(addr) => {
+                            let id = self.breakpoint_id();
+                            addrs.push((id, addr));
+                            verified.push(json!({ "id": id, "verified": true }));
+                        }
+                        None => verified.push(json!({ "verified": false })),
+                    }
+                }
+
+                self.replace_breakpoints(INSTRUCTIONS, addrs, breakpoints);
+                Ok(json!({ "breakpoints": verified }))
+            }
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => {
+                // Each return address points right after the call that pushed it
+                let frames: Vec<Value> = std::iter::once(chip8.program_counter())
+                    .chain(chip8.return_addresses().map(|addr| addr.wrapping_sub(2)))
+                    .enumerate()
+                    .map(|(id, addr)| self.stack_frame(id, addr))
+                    .collect();
+
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REFERENCE, "expensive": false },
+            ] })),
+            "variables" => match args["variablesReference"].as_u64() {
+                Some(REGISTERS_REFERENCE) => Ok(json!({ "variables": Self::registers(chip8) })),
+                Some(STACK_REFERENCE) => {
+                    let variables: Vec<Value> = chip8
+                        .snapshot()
+                        .stack
+                        .iter()
+                        .enumerate()
+                        .map(|(i, addr)| {
+                            Self::variable(&i.to_string(), format!("0x{addr:03X}"), Some(*addr))
+                        })
+                        .collect();
+                    Ok(json!({ "variables": variables }))
+                }
+                _ => Err(String::from("Unknown variables reference")),
+            },
+            "setVariable" => {
+                let name = args["name"].as_str().unwrap_or_default();
+                let value = args["value"]
+                    .as_str()
+                    .and_then(Self::parse_value)
+                    .ok_or("Expected a number, like 0x1F or 31")?;
+                Self::set_register(chip8, name, value)?;
+
+                let variable = Self::registers(chip8)
+                    .into_iter()
+                    .find(|variable| variable["name"] == name)
+                    .unwrap_or_default();
+                Ok(
+                    json!({ "value": variable["value"], "memoryReference": variable["memoryReference"] }),
+                )
+            }
+            "readMemory" => {
+                let start = Self::memory_address(args)?;
+                let count = args["count"].as_u64().unwrap_or(0);
+                let bytes: Vec<u8> = (0..count)
+                    .map_while(|offset| {
+                        let addr = u16::try_from(u64::from(start) + offset).ok()?;
+                        chip8.read_byte(addr).ok()
+                    })
+                    .collect();
+
+                let mut body =
+                    json!({ "address": Self::reference(start), "data": base64_encode(&bytes) });
+                if (bytes.len() as u64) < count {
+                    body["unreadableBytes"] = Value::from(count - bytes.len() as u64);
+                }
+                Ok(body)
+            }
+            "writeMemory" => {
+                let start = Self::memory_address(args)?;
+                let bytes = args["data"]
+                    .as_str()
+                    .and_then(base64_decode)
+                    .ok_or("The data is not valid base64")?;
+
+                for (&byte, offset) in bytes.iter().zip(0..) {
+                    start
+                        .checked_add(offset)
+                        .and_then(|addr| chip8.write_byte(addr, byte).ok())
+                        .ok_or("The memory range is out of bounds")?;
+                }
+                Ok(json!({ "bytesWritten": bytes.len() }))
+            }
+            "disassemble" => {
+                let start = Self::parse_reference(&args["memoryReference"])
+                    .ok_or("Invalid memory reference")?;
+                let offset = args["offset"].as_i64().unwrap_or(0)
+                    + args["instructionOffset"].as_i64().unwrap_or(0) * 2;
+                let count = args["instructionCount"].as_i64().unwrap_or(0);
+
+                let instructions: Vec<Value> = (0..count)
+                    .map(|i| self.instruction(chip8, i64::from(start) + offset + i * 2))
+                    .collect();
+                Ok(json!({ "instructions": instructions }))
+            }
+            "continue" => {
+                actions.push(DapAction::Continue);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let mode = match command {
+                    _ if args["granularity"] == "instruction" || self.source_map.is_empty() => {
+                        StepMode::Instruction
+                    }
+                    "next" => StepMode::Over,
+                    "stepIn" => StepMode::In,
+                    _ => StepMode::Out,
+                };
+                self.stepping = Some(Stepping {
+                    mode,
+                    location: self.source_map.location(chip8.program_counter()).cloned(),
+                    depth: chip8.snapshot().stack.len(),
+                });
+
+                actions.push(DapAction::Continue);
+                Ok(Value::Null)
+            }
+            "pause" => {
+                actions.push(DapAction::Interrupt);
+                Ok(Value::Null)
+            }
+            "disconnect" => {
+                let terminate = args["terminateDebuggee"].as_bool().unwrap_or(true);
+                actions.push(if terminate {
+                    DapAction::Terminate
+                } else {
+                    DapAction::Disconnect
+                });
+                Ok(Value::Null)
+            }
+            "terminate" => {
+                actions.push(DapAction::Terminate);
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request '{command}'")),
+        }
+    }
+
+    fn load_source_map(&mut self, program: &Path, path: Option<&str>) -> Result<(), String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => program.with_extension("map"),
+        };
+
+        self.source_map = match std::fs::read_to_string(&path) {
+            Ok(text) => SourceMap::parse(&text).map_err(|e| e.to_string())?,
+            // The default source map is optional
+            Err(_) if !path.exists() && program.with_extension("map") == path => {
+                SourceMap::default()
+            }
+            Err(e) => return Err(format!("Could not read '{}': {e}", path.display())),
+        };
+        self.source_root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        Ok(())
+    }
+
+    fn load_symbols(&mut self, program: &Path, path: Option<&str>) -> Result<(), String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => program.with_extension("sym"),
+        };
+
+        self.symbols = match std::fs::read_to_string(&path) {
+            Ok(text) => Symbols::parse(&text).map_err(|e| e.to_string())?,
+            // The default symbol file is optional
+            Err(_) if !path.exists() && program.with_extension("sym") == path => Symbols::new(),
+            Err(e) => return Err(format!("Could not read '{}': {e}", path.display())),
+        };
+
+        Ok(())
+    }
+
+    fn replace_breakpoints(
+        &mut self,
+        source: &str,
+        addrs: Vec<(u64, u16)>,
+        breakpoints: &mut Breakpoints,
+    ) {
+        for (_, addr) in self.sources.remove(source).unwrap_or_default() {
+            breakpoints.remove_breakpoint(addr);
+        }
+
+        for &(_, addr) in &addrs {
+            breakpoints.add_breakpoint(addr);
+        }
+        self.sources.insert(String::from(source), addrs);
+    }
+
+    fn breakpoint_id(&mut self) -> u64 {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        id
+    }
+
+    fn source(&self, location: &Location) -> Value {
+        let path = self.source_root.join(&location.file);
+        json!({
+            "name": path.file_name().map(|name| name.to_string_lossy()),
+            "path": path.to_string_lossy(),
+        })
+    }
+
+    fn stack_frame(&self, id: usize, addr: u16) -> Value {
+        let mut frame = json!({
+            "id": id,
+            "name": self.symbols.format(addr),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": Self::reference(addr),
+        });
+        if let Some(location) = self.source_map.location(addr) {
+            frame["source"] = self.source(location);
+            frame["line"] = Value::from(location.line);
+            frame["column"] = Value::from(1);
+        }
+
+        frame
+    }
+
+    fn instruction(&self, chip8: &Chip8, addr: i64) -> Value {
+        let Some(word) = u16::try_from(addr)
+            .ok()
+            .and_then(|addr| Some((addr, chip8.peek_word(addr).ok()?)))
+        else {
+            return json!({
+                "address": format!("0x{:03X}", addr.max(0)),
+                "instruction": "",
+                "presentationHint": "invalid",
+            });
+        };
+
+        let (addr, word) = word;
+        let mut instruction = json!({
+            "address": Self::reference(addr),
+            "instructionBytes": format!("{word:04X}"),
+            "instruction": OpCode::try_from(word).map_or_else(|_| String::from("???"), |op| self.symbols.disassemble(op)),
+        });
+        if let Some((label, 0)) = self.symbols.lookup(addr) {
+            instruction["symbol"] = Value::from(label);
+        }
+        if let Some(location) = self.source_map.location(addr) {
+            instruction["location"] = self.source(location);
+            instruction["line"] = Value::from(location.line);
+        }
+
+        instruction
+    }
+
+    fn registers(chip8: &Chip8) -> Vec<Value> {
+        let mut variables: Vec<Value> = (0..16)
+            .map(|x| {
+                Self::variable(
+                    &format!("V{x:X}"),
+                    format!("0x{:02X}", chip8.register(x)),
+                    None,
+                )
+            })
+            .collect();
+
+        variables.push(Self::variable(
+            "I",
+            format!("0x{:03X}", chip8.index()),
+            Some(chip8.index()),
+        ));
+        variables.push(Self::variable(
+            "PC",
+            format!("0x{:03X}", chip8.program_counter()),
+            Some(chip8.program_counter()),
+        ));
+        variables.push(Self::variable("DT", chip8.delay_timer().to_string(), None));
+        variables.push(Self::variable("ST", chip8.sound_timer().to_string(), None));
+        variables
+    }
+
+    fn variable(name: &str, value: String, memory: Option<u16>) -> Value {
+        let mut variable = json!({ "name": name, "variablesReference": 0 });
+        variable["value"] = Value::from(value);
+        if let Some(addr) = memory {
+            variable["memoryReference"] = Value::from(Self::reference(addr));
+        }
+
+        variable
+    }
+
+    fn set_register(chip8: &mut Chip8, name: &str, value: u16) -> Result<(), String> {
+        let byte = u8::try_from(value).map_err(|_| format!("{name} only holds a byte"));
+        match name {
+            "I" => chip8.set_index(value),
+            "PC" => chip8.set_program_counter(value),
+            "DT" => chip8.set_delay_timer(byte?),
+            "ST" => chip8.set_sound_timer(byte?),
+            _ => {
+                let x = name
+                    .strip_prefix('V')
+                    .and_then(|x| u8::from_str_radix(x, 16).ok())
+                    .filter(|&x| x < 16)
+                    .ok_or_else(|| format!("Unknown register '{name}'"))?;
+                chip8.set_register(x, byte?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn memory_address(args: &Value) -> Result<u16, String> {
+        let start =
+            Self::parse_reference(&args["memoryReference"]).ok_or("Invalid memory reference")?;
+        u16::try_from(i64::from(start) + args["offset"].as_i64().unwrap_or(0))
+            .map_err(|_| String::from("The address is out of bounds"))
+    }
+
+    fn reference(addr: u16) -> String {
+        format!("0x{addr:03X}")
+    }
+
+    fn parse_reference(reference: &Value) -> Option<u16> {
+        let digits = reference.as_str()?.trim_start_matches("0x");
+        u16::from_str_radix(digits, 16).ok()
+    }
+
+    /// Parses a number typed in by the user, in hexadecimal with a `0x` prefix
+    /// or in decimal.
+    fn parse_value(value: &str) -> Option<u16> {
+        let value = value.trim();
+        match value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+        {
+            Some(digits) => u16::from_str_radix(digits, 16).ok(),
+            None => value.parse().ok(),
+        }
+    }
+
+    /// Reads everything available from the client, returning `false` once the
+    /// connection is closed.
+    fn receive(&mut self) -> io::Result<bool> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(true);
+        };
+
+        let mut buffer = [0; 1024];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Takes the next complete message out of the input. Messages are JSON,
+    /// preceded by a `Content-Length` header.
+    fn next_message(&mut self) -> Option<Value> {
+        loop {
+            let end = self.input.windows(4).position(|w| w == b"\r\n\r\n")?;
+            let header = String::from_utf8_lossy(&self.input[..end]).into_owned();
+            let len = header.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("Content-Length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            });
+
+            let Some(len) = len else {
+                // Skip a malformed header
+                self.input.drain(..end + 4);
+                continue;
+            };
+
+            if self.input.len() < end + 4 + len {
+                return None;
+            }
+
+            let message: Vec<u8> = self.input.drain(..end + 4 + len).skip(end + 4).collect();
+            if let Ok(message) = serde_json::from_slice(&message) {
+                return Some(message);
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(());
+        };
+
+        message["seq"] = Value::from(self.seq);
+        self.seq += 1;
+
+        let body = message.to_string();
+        let mut bytes = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        bytes.extend_from_slice(body.as_bytes());
+
+        // The stream is non-blocking, so keep trying until everything is sent
+        let mut written = 0;
+        while written < bytes.len() {
+            match stream.write(&bytes[written..]) {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(char::from(BASE64[(bits >> (18 - 6 * i)) as usize & 0x3F]));
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let value = BASE64.iter().position(|&b| b == c)?;
+        bits = bits << 6 | u32::try_from(value).ok()?;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push(u8::try_from(bits >> count & 0xFF).ok()?);
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> DapServer {
+        let mut server = DapServer::with_listener(TcpListener::bind(("127.0.0.1", 0)).unwrap());
+        server.source_map =
+            SourceMap::parse("200 main.8o:1\n202 main.8o:3\n204 main.8o:3\n").unwrap();
+        server.source_root = PathBuf::from("/game");
+        server
+    }
+
+    fn request(
+        server: &mut DapServer,
+        command: &str,
+        args: &Value,
+        chip8: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+    ) -> Result<Value, String> {
+        server.handle_request(command, args, chip8, breakpoints, &mut Vec::new())
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut server = server();
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+
+        let body = request(
+            &mut server,
+            "setBreakpoints",
+            &json!({ "source": { "path": "/game/main.8o" }, "breakpoints": [{ "line": 2 }, { "line": 9 }] }),
+            &mut chip8,
+            &mut breakpoints,
+        )
+        .unwrap();
+        assert_eq!(body["breakpoints"][0]["verified"], true);
+        assert_eq!(body["breakpoints"][0]["line"], 3);
+        assert_eq!(body["breakpoints"][1]["verified"], false);
+        assert!(breakpoints.is_breakpoint(0x202));
+
+        request(
+            &mut server,
+            "setBreakpoints",
+            &json!({ "source": { "path": "/game/main.8o" }, "breakpoints": [] }),
+            &mut chip8,
+            &mut breakpoints,
+        )
+        .unwrap();
+        assert!(!breakpoints.is_breakpoint(0x202));
+    }
+
+    #[test]
+    fn launch_fails_for_roms_that_do_not_load() {
+        let mut server = server();
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+        let path = std::env::temp_dir().join("chipper-dap-too-large.ch8");
+        std::fs::write(&path, vec![0; 0x1000]).unwrap();
+
+        let mut actions = Vec::new();
+        let result = server.handle_request(
+            "launch",
+            &json!({ "program": path.to_string_lossy() }),
+            &mut chip8,
+            &mut breakpoints,
+            &mut actions,
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.unwrap_err().starts_with("Could not load"));
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn clear_breakpoints() {
+        let mut server = server();
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+
+        let body = request(
+            &mut server,
+            "setInstructionBreakpoints",
+            &json!({ "breakpoints": [{ "instructionReference": "0x204" }] }),
+            &mut chip8,
+            &mut breakpoints,
+        )
+        .unwrap();
+        assert_eq!(body["breakpoints"][0]["id"], 1);
+
+        server.clear_breakpoints("The ROM was reloaded").unwrap();
+        assert!(server.sources.is_empty());
+    }
+
+    #[test]
+    fn state() {
+        let mut server = server();
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+        chip8
+            .load_rom(&[0x22, 0x04, 0x00, 0xE0, 0x00, 0xEE])
+            .unwrap();
+        chip8.cycle().unwrap();
+
+        let body = request(
+            &mut server,
+            "stackTrace",
+            &json!({}),
+            &mut chip8,
+            &mut breakpoints,
+        )
+        .unwrap();
+        assert_eq!(body["totalFrames"], 2);
+        assert_eq!(body["stackFrames"][0]["line"], 3);
+        assert_eq!(body["stackFrames"][0]["source"]["path"], "/game/main.8o");
+        assert_eq!(
+            body["stackFrames"][1]["instructionPointerReference"],
+            "0x200"
+        );
+
+        request(
+            &mut server,
+            "setVariable",
+            &json!({ "variablesReference": REGISTERS_REFERENCE, "name": "VA", "value": "0x1F" }),
+            &mut chip8,
+            &mut breakpoints,
+        )
+        .unwrap();
+        assert_eq!(chip8.register(0xA), 0x1F);
+
+        let body = request(
+            &mut server,
+            "readMemory",
+            &json!({ "memoryReference": "0x200", "count": 4 }),
+            &mut chip8,
+            &mut breakpoints,
+        )
+        .unwrap();
+        assert_eq!(body["data"], "IgQA4A==");
+        assert_eq!(
+            base64_decode("IgQA4A=="),
+            Some(vec![0x22, 0x04, 0x00, 0xE0])
+        );
+    }
+
+    #[test]
+    fn stepping() {
+        let mut server = server();
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+        chip8
+            .load_rom(&[0x22, 0x04, 0x00, 0xE0, 0x00, 0xEE])
+            .unwrap();
+
+        let mut actions = Vec::new();
+        server
+            .handle_request(
+                "stepIn",
+                &json!({}),
+                &mut chip8,
+                &mut breakpoints,
+                &mut actions,
+            )
+            .unwrap();
+        assert_eq!(actions, [DapAction::Continue]);
+
+        chip8.cycle().unwrap();
+        assert!(server.step_done(&chip8));
+    }
+
+    #[test]
+    fn messages() {
+        let mut server = server();
+        server.input =
+            b"Content-Length: 12\r\n\r\n{\"seq\":1}   Content-Length: 2\r\n\r\n{}".to_vec();
+
+        assert_eq!(server.next_message(), Some(json!({ "seq": 1 })));
+        assert_eq!(server.next_message(), Some(json!({})));
+        assert_eq!(server.next_message(), None);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::chip::{Fault, MemAccess};
+use crate::detector::Suspicion;
+use crate::symbols::Symbols;
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Range;
+
+/// The kind of memory access a watchpoint reacts to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch {
+    fn matches(self, access: MemAccess) -> bool {
+        matches!(
+            (self, access),
+            (Self::Access, _) | (Self::Read, MemAccess::Read) | (Self::Write, MemAccess::Write)
+        )
+    }
+}
+
+/// Why execution stopped, as reported to a debugger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program counter reached a breakpoint.
+    Breakpoint(u16),
+    /// The last instruction accessed a watched address.
+    Watchpoint { addr: u16, watch: Watch },
+    /// A single instruction was executed on request.
+    Step,
+    /// The user asked for execution to stop.
+    Interrupt,
+    /// An instruction was skipped under `OpPolicy::Pause`.
+    Pause(Fault),
+    /// An instruction could not be executed.
+    Fault(Fault),
+    /// The detector caught the next instruction doing something suspicious.
+    Suspicious(Suspicion),
+}
+
+impl StopReason {
+    /// Describes the reason, with addresses shown as `label+offset`.
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        match self {
+            Self::Breakpoint(addr) => format!("Breakpoint at {}", symbols.format(*addr)),
+            Self::Watchpoint { addr, watch } => {
+                format!("Watchpoint ({watch:?}) at {}", symbols.format(*addr))
+            }
+            Self::Step => String::from("Step"),
+            Self::Interrupt => String::from("Interrupted"),
+            Self::Pause(fault) => format!("Paused: {}", symbols.describe(fault)),
+            Self::Fault(fault) => format!("Fault: {}", symbols.describe(fault)),
+            Self::Suspicious(suspicion) => format!("Suspicious: {suspicion}"),
+        }
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.describe(&Symbols::new()))
+    }
+}
+
+/// Lists the frames of a call stack, innermost first: the instruction at
+/// `program_counter`, then the `CALL` each subroutine was entered from. The
+/// stack holds return addresses from the outermost frame in, as in a
+/// `Snapshot`.
+pub fn backtrace(program_counter: u16, stack: &[u16], symbols: &Symbols) -> Vec<String> {
+    let call_sites = stack.iter().rev().map(|addr| addr.wrapping_sub(2));
+    std::iter::once(program_counter)
+        .chain(call_sites)
+        .enumerate()
+        .map(|(depth, addr)| match symbols.label(addr) {
+            Some(label) => format!("#{depth:<2} 0x{addr:03X}  {label}"),
+            None => format!("#{depth:<2} 0x{addr:03X}"),
+        })
+        .collect()
+}
+
+/// The breakpoints and watchpoints shared by all debugger frontends.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Breakpoints {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(Range<u16>, Watch)>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a breakpoint, returning `false` if there already was one at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Removes a breakpoint, returning `false` if there was none at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, range: Range<u16>, watch: Watch) {
+        self.watchpoints.push((range, watch));
+    }
+
+    /// Removes a watchpoint, returning `false` if there was no such watchpoint.
+    pub fn remove_watchpoint(&mut self, range: &Range<u16>, watch: Watch) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints
+            .retain(|(other, kind)| other != range || *kind != watch);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = &(Range<u16>, Watch)> + '_ {
+        self.watchpoints.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    /// Returns the first watched address touched by the given access, if any.
+    pub fn check_access(&self, access: MemAccess, range: &Range<u16>) -> Option<StopReason> {
+        self.watchpoints
+            .iter()
+            .filter(|(_, watch)| watch.matches(access))
+            .find_map(|(watched, watch)| {
+                let addr = range.start.max(watched.start);
+                (addr < range.end.min(watched.end)).then_some(StopReason::Watchpoint {
+                    addr,
+                    watch: *watch,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backtrace_lists_call_sites() {
+        let symbols = Symbols::parse("main = 200\nsub = 210").unwrap();
+        assert_eq!(
+            backtrace(0x212, &[0x204, 0x216], &symbols),
+            [
+                "#0  0x212  sub+0x2",
+                "#1  0x214  sub+0x4",
+                "#2  0x202  main+0x2"
+            ]
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut breakpoints = Breakpoints::new();
+        breakpoints.add_watchpoint(0x300..0x302, Watch::Write);
+
+        assert_eq!(
+            breakpoints.check_access(MemAccess::Write, &(0x2FF..0x302)),
+            Some(StopReason::Watchpoint {
+                addr: 0x300,
+                watch: Watch::Write
+            })
+        );
+        assert_eq!(
+            breakpoints.check_access(MemAccess::Read, &(0x300..0x301)),
+            None
+        );
+        assert_eq!(
+            breakpoints.check_access(MemAccess::Write, &(0x302..0x305)),
+            None
+        );
+
+        assert!(breakpoints.remove_watchpoint(&(0x300..0x302), Watch::Write));
+        assert_eq!(
+            breakpoints.check_access(MemAccess::Write, &(0x300..0x301)),
+            None
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! A runtime detector for self-modifying code and other suspicious behaviour.
+//!
+//! As the program runs, every byte of memory is tagged by how it is used:
+//! executed bytes become code, bytes read or written through the index
+//! register become data. Writes over code, over the font or into the
+//! interpreter area below the load address, and jumps into data, are reported
+//! once per instruction.
+
+use crate::chip::{Chip8, MemAccess, OpCode};
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tag {
+    Unknown,
+    Code,
+    Data,
+    Font,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SuspicionKind {
+    WriteOverCode,
+    WriteOverFont,
+    WriteIntoInterpreter,
+    JumpIntoData,
+}
+
+/// Something the program did that it most likely should not have.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suspicion {
+    pub kind: SuspicionKind,
+    /// Address of the offending instruction.
+    pub program_counter: u16,
+    /// The memory written, or the data executed.
+    pub target: Range<u16>,
+}
+
+impl fmt::Display for Suspicion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.kind {
+            SuspicionKind::WriteOverCode => "Write over executed code",
+            SuspicionKind::WriteOverFont => "Write over the font",
+            SuspicionKind::WriteIntoInterpreter => "Write into the interpreter area",
+            SuspicionKind::JumpIntoData => "Execution of data",
+        };
+        write!(
+            f,
+            "{what} at 0x{:03X}-0x{:03X} (PC: 0x{:03X})",
+            self.target.start,
+            self.target.end.wrapping_sub(1),
+            self.program_counter
+        )
+    }
+}
+
+pub struct Detector {
+    tags: Vec<Tag>,
+    load_addr: u16,
+    font: Range<u16>,
+    reported: BTreeSet<(SuspicionKind, u16)>,
+}
+
+impl Detector {
+    /// Creates a detector for roms loaded at `load_addr`, with the font at
+    /// the addresses of `font`.
+    pub fn new(load_addr: u16, font: Range<u16>) -> Self {
+        Self {
+            tags: Vec::new(),
+            load_addr,
+            font,
+            reported: BTreeSet::new(),
+        }
+    }
+
+    pub fn tag(&self, addr: u16) -> Tag {
+        if self.font.contains(&addr) {
+            return Tag::Font;
+        }
+
+        self.tags
+            .get(usize::from(addr))
+            .copied()
+            .unwrap_or(Tag::Unknown)
+    }
+
+    /// Checks the instruction about to be executed, and tags the memory it
+    /// uses. Must be called before every `Chip8::cycle`.
+    pub fn observe(&mut self, chip8: &Chip8) -> Vec<Suspicion> {
+        self.tags.resize(chip8.memory_size(), Tag::Unknown);
+
+        let pc = chip8.program_counter();
+        let code = pc..pc.saturating_add(2);
+        let mut found = Vec::new();
+        if code.clone().any(|addr| self.tag(addr) == Tag::Data) {
+            found.push((SuspicionKind::JumpIntoData, code.clone()));
+        }
+        self.mark(code, Tag::Code);
+
+        let access = chip8
+            .peek_word(pc)
+            .ok()
+            .and_then(|word| OpCode::try_from(word).ok())
+            .and_then(|op| op.memory_access(chip8.index()));
+        match access {
+            Some((MemAccess::Write, target)) => {
+                let touches = |tag| target.clone().any(|addr| self.tag(addr) == tag);
+                if touches(Tag::Code) {
+                    found.push((SuspicionKind::WriteOverCode, target.clone()));
+                }
+                if touches(Tag::Font) {
+                    found.push((SuspicionKind::WriteOverFont, target.clone()));
+                } else if target.start < self.load_addr {
+                    found.push((SuspicionKind::WriteIntoInterpreter, target.clone()));
+                }
+
+                self.mark(target, Tag::Data);
+            }
+            Some((MemAccess::Read, target)) => self.mark(target, Tag::Data),
+            None => {}
+        }
+
+        found
+            .into_iter()
+            .filter(|(kind, _)| self.reported.insert((*kind, pc)))
+            .map(|(kind, target)| Suspicion {
+                kind,
+                program_counter: pc,
+                target,
+            })
+            .collect()
+    }
+
+    /// Tags the given bytes, without ever turning code back into data.
+    fn mark(&mut self, range: Range<u16>, tag: Tag) {
+        for addr in range {
+            if let Some(current) = self.tags.get_mut(usize::from(addr)) {
+                if *current != Tag::Code {
+                    *current = tag;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chip::Settings;
+
+    fn run(chip8: &mut Chip8, detector: &mut Detector, cycles: usize) -> Vec<SuspicionKind> {
+        let mut kinds = Vec::new();
+        for _ in 0..cycles {
+            kinds.extend(detector.observe(chip8).into_iter().map(|s| s.kind));
+            chip8.cycle().unwrap();
+        }
+        kinds
+    }
+
+    #[test]
+    fn suspicious_writes() {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[
+                0xA2, 0x00, // LD   I, 0x200
+                0xF0, 0x55, // LD   [I], V0
+                0xA0, 0x50, // LD   I, 0x050
+                0xF0, 0x33, // LD   B, V0
+                0xA1, 0x80, // LD   I, 0x180
+                0xF0, 0x55, // LD   [I], V0
+            ])
+            .unwrap();
+        let mut detector = Detector::new(0x200, Settings::new().font_range());
+
+        assert_eq!(
+            run(&mut chip8, &mut detector, 6),
+            [
+                SuspicionKind::WriteOverCode,
+                SuspicionKind::WriteOverFont,
+                SuspicionKind::WriteIntoInterpreter
+            ]
+        );
+        assert_eq!(detector.tag(0x201), Tag::Code);
+        assert_eq!(detector.tag(0x180), Tag::Data);
+    }
+
+    #[test]
+    fn jump_into_data() {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[
+                0xA2, 0x06, // LD   I, 0x206
+                0xD0, 0x01, // DRW  V0, V0, 1
+                0x12, 0x06, // JP   0x206
+                0x12, 0x06, // sprite, and a jump to itself
+            ])
+            .unwrap();
+        let mut detector = Detector::new(0x200, Settings::new().font_range());
+
+        assert_eq!(
+            run(&mut chip8, &mut detector, 5),
+            [SuspicionKind::JumpIntoData]
+        );
+    }
+}
@@ -0,0 +1,970 @@
+use super::memory_viewer::MemoryViewer;
+use super::osd::Osd;
+use super::rom_picker::{PickerAction, RomPicker};
+use super::sdl2_wrapper::Sdl2Wrapper;
+use super::SCALE;
+
+use chipper::audio::{Mixer, Tone};
+use chipper::capture::Capture;
+use chipper::chip::Chip8;
+use chipper::chip::ChipError;
+use chipper::chip::Clock;
+use chipper::chip::Flow;
+use chipper::chip::OpCode;
+use chipper::chip::OpPolicy;
+use chipper::chip::{RomWarning, Settings};
+use chipper::dap::{DapAction, DapServer};
+use chipper::debug;
+use chipper::debug::{Breakpoints, StopReason};
+use chipper::detector::Detector;
+use chipper::gdb::{GdbAction, GdbServer};
+use chipper::image;
+use chipper::library;
+use chipper::monitor::{Monitor, MonitorAction};
+use chipper::octo;
+use chipper::phosphor::{Filter, Phosphor, DEFAULT_DECAY};
+use chipper::profile::Profiler;
+use chipper::symbols::Symbols;
+use chipper::trace::Tracer;
+use chipper::wav::WavWriter;
+
+use anyhow::Error;
+use anyhow::Result;
+use sdl2::event::Event;
+use sdl2::event::WindowEvent;
+use sdl2::keyboard::Scancode;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How often the timers count down, which is also the length of an audio frame.
+pub const TIMER_FREQ: f64 = 60.0;
+/// How often a watched ROM file is checked for changes.
+const WATCH_FREQ: f64 = 4.0;
+/// How many times faster than the settings give the machine runs when
+/// fast-forwarding.
+const FAST_FORWARD: f64 = 2.0;
+
+/// A ROM file to reload whenever it changes.
+struct Watch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// Whether reloading keeps the breakpoints and the memory viewer.
+    keep_debugger: bool,
+}
+
+pub struct Emulator {
+    chip8: Chip8,
+    /// Paces the instructions at the speed the settings give.
+    cpu_clock: Clock,
+    delay_clock: Clock,
+    sound_clock: Clock,
+    fast_forward: bool,
+    /// The program running, to start it over on reset.
+    rom: Vec<u8>,
+    /// The state saved by the player, to go back to.
+    saved_state: Option<Vec<u8>>,
+    watch: Option<Watch>,
+    sdl: Sdl2Wrapper,
+    /// Smooths the display, without touching the frame buffer.
+    phosphor: Phosphor,
+    osd: Osd,
+    picker: Option<RomPicker>,
+    /// Whether the ROM picker is shown instead of running the machine.
+    picking: bool,
+    paused: bool,
+    mixer: Mixer,
+    /// Samples of the current audio frame.
+    samples: Vec<f32>,
+    /// Records the sound separately, so muting does not affect it.
+    recorder: Option<(Mixer, WavWriter<BufWriter<File>>)>,
+    /// The video capture in progress, and where it goes.
+    capture: Option<(Capture, PathBuf)>,
+    tracer: Option<Tracer<Box<dyn Write>>>,
+    profiler: Option<Profiler>,
+    memory_viewer: Option<MemoryViewer>,
+    /// The detector, and how to react to what it finds.
+    detector: Option<(Detector, OpPolicy)>,
+    breakpoints: Breakpoints,
+    /// Address of a breakpoint to execute instead of stopping at, after resuming.
+    resume_from: Option<u16>,
+    gdb: Option<GdbServer>,
+    dap: Option<DapServer>,
+    monitor: Monitor,
+    symbols: Symbols,
+}
+
+impl Emulator {
+    pub fn new(chip8: Chip8, sdl: Sdl2Wrapper) -> Self {
+        Self {
+            cpu_clock: cpu_clock(&chip8, 1.0),
+            delay_clock: Clock::new(TIMER_FREQ),
+            sound_clock: Clock::new(TIMER_FREQ),
+            fast_forward: false,
+            chip8,
+            rom: Vec::new(),
+            saved_state: None,
+            watch: None,
+            mixer: Mixer::new(Tone::new(), sdl.audio_sample_rate()),
+            samples: Vec::new(),
+            recorder: None,
+            capture: None,
+            sdl,
+            phosphor: Phosphor::new(Filter::None, DEFAULT_DECAY),
+            osd: Osd::new(),
+            picker: None,
+            picking: false,
+            paused: false,
+            tracer: None,
+            profiler: None,
+            memory_viewer: None,
+            detector: None,
+            breakpoints: Breakpoints::new(),
+            resume_from: None,
+            gdb: None,
+            dap: None,
+            monitor: Monitor::new(),
+            symbols: Symbols::new(),
+        }
+    }
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.mixer = Mixer::new(tone, self.sdl.audio_sample_rate());
+    }
+
+    pub fn set_phosphor(&mut self, phosphor: Phosphor) {
+        self.phosphor = phosphor;
+    }
+
+    /// Shows the frames and instructions per second over the display.
+    pub fn show_counter(&mut self, show: bool) {
+        self.osd.set_show_counter(show);
+    }
+
+    /// Sets the program `chip8` was loaded with, to start it over on reset.
+    pub fn set_rom(&mut self, rom: Vec<u8>) {
+        self.rom = rom;
+    }
+
+    /// Reloads the ROM from `path` whenever the file changes. Breakpoints are
+    /// cleared and the memory viewer closed, unless `keep_debugger` is set.
+    pub fn watch(&mut self, path: PathBuf, keep_debugger: bool) {
+        self.watch = Some(Watch {
+            modified: modified(&path),
+            path,
+            keep_debugger,
+        });
+    }
+
+    /// Shows the ROM picker until a ROM is picked. Until then, leaving the
+    /// picker quits.
+    pub fn open_picker(&mut self, picker: RomPicker) {
+        self.picker = Some(picker);
+        self.picking = true;
+    }
+
+    /// The sample rate of the audio device.
+    pub fn sample_rate(&self) -> u32 {
+        self.mixer.sample_rate()
+    }
+
+    /// Records the sound to a WAV file, with the current tone.
+    pub fn set_recorder(&mut self, wav: WavWriter<BufWriter<File>>) {
+        let mixer = Mixer::new(self.mixer.tone(), self.mixer.sample_rate());
+        self.recorder = Some((mixer, wav));
+    }
+
+    /// Completes the WAV file being recorded, if any.
+    pub fn finish_recording(&mut self) -> Result<()> {
+        if let Some((_, wav)) = self.recorder.take() {
+            wav.finish()?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts capturing the display to `path`, see `Capture::create`.
+    pub fn start_capture(&mut self, path: PathBuf) -> Result<()> {
+        let capture = Capture::create(&path, SCALE, self.sdl.palette())?;
+        self.capture = Some((capture, path));
+        Ok(())
+    }
+
+    /// Saves the display to `path`, see `image::save`.
+    pub fn screenshot(&mut self, path: &Path) -> Result<()> {
+        image::save(
+            path,
+            self.chip8.get_frame_buffer(),
+            SCALE,
+            &self.sdl.palette(),
+        )?;
+        self.notify(format!("Saved a screenshot to {}", path.display()));
+        Ok(())
+    }
+
+    /// Completes the video capture in progress, if any.
+    pub fn finish_capture(&mut self) -> Result<()> {
+        if let Some((capture, path)) = self.capture.take() {
+            capture.finish()?;
+            self.notify(format!("Saved the capture to {}", path.display()));
+        }
+
+        Ok(())
+    }
+
+    /// Hands control over execution to a GDB server. The emulator starts out
+    /// paused, waiting for the debugger to resume it.
+    pub fn set_gdb(&mut self, gdb: GdbServer) {
+        self.gdb = Some(gdb);
+        self.paused = true;
+    }
+
+    /// Hands control over execution to a Debug Adapter Protocol server. The
+    /// emulator stays paused until the client has launched a program.
+    pub fn set_dap(&mut self, dap: DapServer) {
+        self.dap = Some(dap);
+        self.paused = true;
+    }
+
+    /// Shows addresses as `label+offset` in messages, the monitor and the
+    /// memory viewer.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.monitor.set_symbols(symbols.clone());
+        if let Some(viewer) = &mut self.memory_viewer {
+            viewer.set_symbols(symbols.clone());
+        }
+        self.symbols = symbols;
+    }
+
+    /// Pauses the machine and reads monitor commands from the terminal until
+    /// told to leave. Returns whether the emulator should keep running.
+    pub fn enter_monitor(&mut self) -> Result<bool> {
+        self.paused = true;
+        self.fade_out()?;
+        self.draw()?;
+        println!(
+            "Monitor at {}, type h for help.",
+            self.symbols.format(self.chip8.program_counter())
+        );
+
+        let mut line = String::new();
+        loop {
+            print!("> ");
+            io::stdout().flush()?;
+
+            line.clear();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(true);
+            }
+
+            let action = self.monitor.execute(
+                &line,
+                &mut self.chip8,
+                &mut self.breakpoints,
+                io::stdout(),
+            )?;
+            self.draw()?;
+
+            match action {
+                MonitorAction::Stay => {}
+                MonitorAction::Continue => {
+                    self.resume();
+                    return Ok(true);
+                }
+                MonitorAction::Leave => return Ok(true),
+                MonitorAction::Quit => return Ok(false),
+            }
+        }
+    }
+
+    pub fn toggle_memory_viewer(&mut self) -> Result<()> {
+        if self.memory_viewer.take().is_none() {
+            let mut viewer = MemoryViewer::new(&self.sdl)?;
+            viewer.set_symbols(self.symbols.clone());
+            self.memory_viewer = Some(viewer);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_detector(&mut self, detector: Detector, policy: OpPolicy) {
+        self.detector = Some((detector, policy));
+    }
+
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer<Box<dyn Write>>) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        let mut viewer_clock = Clock::new(30.0);
+        let mut watch_clock = Clock::new(WATCH_FREQ);
+
+        loop {
+            if let Some(event) = self.sdl.poll_event() {
+                if self.handle_event(&event)? {
+                    continue;
+                }
+
+                break;
+            }
+
+            if !self.poll_gdb()? || !self.poll_dap()? {
+                break;
+            }
+
+            if watch_clock.tick() {
+                self.poll_watch()?;
+            }
+
+            if self.paused || self.picking {
+                // Keep the clocks from building up a backlog of ticks while paused
+                self.cpu_clock.reset();
+                self.delay_clock.reset();
+                self.sound_clock.reset();
+                self.fade_out()?;
+            } else {
+                if self.delay_clock.tick() {
+                    self.chip8.tick_delay_timer();
+                    self.osd.count_frame();
+                    self.phosphor.end_frame(self.chip8.get_frame_buffer());
+                    if let Some((capture, _)) = &mut self.capture {
+                        capture.add_frame(self.chip8.get_frame_buffer())?;
+                    }
+                }
+
+                if self.sound_clock.tick() {
+                    let active = self.chip8.should_beep();
+                    self.chip8.tick_sound_timer();
+                    self.play_frame(active)?;
+                }
+
+                if self.cpu_clock.tick() {
+                    self.chip8.set_keys(self.sdl.poll_input());
+                    if let Some(reason) = self.step()? {
+                        self.stop(&reason)?;
+                    }
+                }
+            }
+
+            if viewer_clock.tick() {
+                // Nothing else draws while paused, but messages come and go
+                if self.paused || self.picking {
+                    self.draw()?;
+                }
+
+                if let Some(viewer) = &mut self.memory_viewer {
+                    viewer.draw(&self.chip8)?;
+                }
+            }
+        }
+
+        if let Some(dap) = &mut self.dap {
+            dap.terminate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reacts to a single event, returning whether the emulator should keep running.
+    fn handle_event(&mut self, event: &Event) -> Result<bool> {
+        if let Event::KeyDown {
+            scancode: Some(scancode),
+            ..
+        } = *event
+        {
+            if self.picking {
+                return self.handle_picker_key(scancode);
+            }
+
+            if self.handle_hotkey(scancode)? {
+                return Ok(true);
+            }
+        }
+
+        let viewer_id = self.memory_viewer.as_ref().map(MemoryViewer::window_id);
+
+        match *event {
+            Event::Quit { .. } => {
+                return Ok(false);
+            }
+            Event::Window {
+                window_id,
+                win_event: WindowEvent::Close,
+                ..
+            } => {
+                if Some(window_id) != viewer_id {
+                    return Ok(false);
+                }
+
+                self.memory_viewer = None;
+            }
+            Event::KeyDown {
+                scancode: Some(Scancode::F2),
+                ..
+            } => {
+                self.toggle_memory_viewer()?;
+            }
+            Event::KeyDown {
+                scancode: Some(Scancode::F5),
+                ..
+            } => {
+                if self.paused {
+                    self.resume();
+                } else {
+                    self.stop(&StopReason::Interrupt)?;
+                }
+            }
+            Event::KeyDown {
+                scancode: Some(Scancode::F8),
+                ..
+            } => {
+                return self.enter_monitor();
+            }
+            Event::KeyDown {
+                scancode: Some(Scancode::F10),
+                ..
+            } if self.paused => {
+                self.single_step()?;
+            }
+            Event::KeyDown {
+                window_id,
+                scancode: Some(scancode),
+                ..
+            } if Some(window_id) == viewer_id => {
+                if let Some(viewer) = &mut self.memory_viewer {
+                    viewer.handle_key(&mut self.chip8, scancode, self.paused);
+                    viewer.draw(&self.chip8)?;
+                }
+            }
+            Event::KeyDown { scancode, .. } => {
+                self.chip8
+                    .press_key(scancode.and_then(Sdl2Wrapper::translate_scancode));
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    /// Handles the keys controlling the frontend, such as sound and display
+    /// settings, returning whether `scancode` was one of them.
+    fn handle_hotkey(&mut self, scancode: Scancode) -> Result<bool> {
+        match scancode {
+            Scancode::M => {
+                let muted = !self.mixer.is_muted();
+                self.mixer.set_muted(muted);
+                self.notify(if muted {
+                    "Sound muted"
+                } else {
+                    "Sound unmuted"
+                });
+            }
+            Scancode::F6 => {
+                if self.capture.is_some() {
+                    self.finish_capture()?;
+                } else {
+                    self.start_capture(timestamped("chipper", "gif"))?;
+                    self.notify("Capturing, press F6 again to stop.");
+                }
+            }
+            Scancode::F7 => {
+                let filter = self.phosphor.filter().next();
+                self.phosphor.set_filter(filter);
+                self.notify(format!("Display filter: {}", filter.name()));
+                self.draw()?;
+            }
+            Scancode::F3 => {
+                let show = !self.osd.show_counter();
+                self.osd.set_show_counter(show);
+                self.draw()?;
+            }
+            Scancode::F12 => {
+                self.screenshot(&timestamped("chipper", "png"))?;
+            }
+            Scancode::F9 => {
+                self.reset()?;
+            }
+            // Not Tab, which switches the panes of the memory viewer
+            Scancode::Space => {
+                self.fast_forward = !self.fast_forward;
+                self.set_clocks();
+                self.notify(if self.fast_forward {
+                    "Speed 2x"
+                } else {
+                    "Speed 1x"
+                });
+            }
+            Scancode::F1 => {
+                self.saved_state = Some(self.chip8.save_state());
+                self.notify("State saved");
+            }
+            Scancode::F11 => {
+                self.load_state()?;
+            }
+            Scancode::F4 if self.picker.is_some() => {
+                self.picking = true;
+                self.fade_out()?;
+                self.draw()?;
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Moves through the ROM picker, returning whether the emulator should
+    /// keep running.
+    fn handle_picker_key(&mut self, scancode: Scancode) -> Result<bool> {
+        let (action, current) = match &mut self.picker {
+            Some(picker) => (picker.handle_key(scancode), picker.current()),
+            None => (None, None),
+        };
+
+        match action {
+            Some(PickerAction::Load(index)) => self.load_picked(index),
+            Some(PickerAction::Cancel) if current.is_none() => return Ok(false),
+            Some(PickerAction::Cancel) => self.picking = false,
+            None => {}
+        }
+
+        self.draw()?;
+        Ok(true)
+    }
+
+    /// Runs the ROM at `index` of the picker on a fresh machine, with the
+    /// quirks of its platform.
+    fn load_picked(&mut self, index: usize) {
+        let Some(picker) = &mut self.picker else {
+            return;
+        };
+
+        let name = picker.library().names()[index].clone();
+        let mut settings = self.chip8.settings().clone();
+        if picker.match_platform() {
+            if let Some(platform) = library::platform(&name) {
+                settings.apply_platform(platform);
+            }
+        }
+
+        let read = picker.library().read(index);
+        let loaded = read
+            .map_err(Error::from)
+            .and_then(|rom| Ok(self.restart(settings, rom)?));
+        let warning = match loaded {
+            Ok(warning) => warning,
+            Err(err) => {
+                self.notify(format!("Could not load {name}: {err}"));
+                return;
+            }
+        };
+
+        if let Some(picker) = &mut self.picker {
+            picker.set_current(index);
+        }
+        self.picking = false;
+        self.paused = false;
+        match warning {
+            Some(warning) => self.notify(format!("Loaded {name}: {warning}")),
+            None => self.notify(format!("Loaded {name}")),
+        }
+    }
+
+    /// Queues one frame of sound, with the buzzer on if `active`.
+    fn play_frame(&mut self, active: bool) -> Result<()> {
+        self.samples.clear();
+        self.mixer
+            .render_frame(active, TIMER_FREQ, &mut self.samples);
+        self.sdl.queue_audio(&self.samples)?;
+
+        if let Some((mixer, wav)) = &mut self.recorder {
+            self.samples.clear();
+            mixer.render_frame(active, TIMER_FREQ, &mut self.samples);
+            wav.write_samples(&self.samples)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets a tone that is still playing fade out, rather than cutting it off.
+    fn fade_out(&mut self) -> Result<()> {
+        if self.mixer.is_silent() {
+            return Ok(());
+        }
+
+        self.play_frame(false)
+    }
+
+    /// Answers the debugger, returning `false` if it asked to end the session.
+    fn poll_gdb(&mut self) -> Result<bool> {
+        let Some(gdb) = &mut self.gdb else {
+            return Ok(true);
+        };
+
+        match gdb.poll(&mut self.chip8, &mut self.breakpoints)? {
+            Some(GdbAction::Continue | GdbAction::Detach) => self.resume(),
+            Some(GdbAction::Step) => self.single_step()?,
+            Some(GdbAction::Interrupt) => self.stop(&StopReason::Interrupt)?,
+            Some(GdbAction::Kill) => return Ok(false),
+            None => {}
+        }
+
+        Ok(true)
+    }
+
+    /// Answers the debug adapter client, returning `false` if it asked to end
+    /// the session.
+    fn poll_dap(&mut self) -> Result<bool> {
+        let Some(dap) = &mut self.dap else {
+            return Ok(true);
+        };
+
+        for action in dap.poll(&mut self.chip8, &mut self.breakpoints)? {
+            match action {
+                DapAction::Launch(rom) => self.load(&rom)?,
+                DapAction::Continue | DapAction::Disconnect => self.resume(),
+                DapAction::Interrupt => self.stop(&StopReason::Interrupt)?,
+                DapAction::Terminate => return Ok(false),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Replaces the running program, keeping the settings. The old one keeps
+    /// going if the new one does not load.
+    fn load(&mut self, rom: &[u8]) -> Result<()> {
+        let settings = self.chip8.settings().clone();
+        match self.restart(settings, rom.to_vec()) {
+            Ok(Some(warning)) => self.notify(warning.to_string()),
+            Ok(None) => {}
+            Err(err) => self.notify(format!("Could not load the ROM: {err}")),
+        }
+
+        self.paused = true;
+        self.draw()
+    }
+
+    /// Starts the program over on a fresh machine with the same settings.
+    /// It keeps running, unless a debugger is attached.
+    fn reset(&mut self) -> Result<()> {
+        if self.picking {
+            return Ok(());
+        }
+
+        let settings = self.chip8.settings().clone();
+        self.restart(settings, self.rom.clone())?;
+        self.notify("Reset");
+        self.pause_for_debugger()?;
+        self.draw()
+    }
+
+    /// Reloads the watched ROM if the file changed since it was last loaded.
+    fn poll_watch(&mut self) -> Result<()> {
+        let Some(watch) = &mut self.watch else {
+            return Ok(());
+        };
+
+        let modified = modified(&watch.path);
+        if modified == watch.modified {
+            return Ok(());
+        }
+        watch.modified = modified;
+
+        let path = watch.path.clone();
+        let keep_debugger = watch.keep_debugger;
+        let rom = match octo::read_rom(&path) {
+            Ok(rom) => rom,
+            // The file may be missing while it is being rebuilt, the next
+            // change brings it back
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                self.notify(format!("Could not reload {}: {err}", path.display()));
+                return Ok(());
+            }
+        };
+        if rom == self.rom {
+            return Ok(());
+        }
+
+        let settings = self.chip8.settings().clone();
+        let warning = match self.restart(settings, rom) {
+            Ok(warning) => warning,
+            Err(err) => {
+                self.notify(format!("Could not reload {}: {err}", path.display()));
+                return Ok(());
+            }
+        };
+
+        if !keep_debugger {
+            self.breakpoints = Breakpoints::new();
+            self.memory_viewer = None;
+            if let Some(dap) = &mut self.dap {
+                dap.clear_breakpoints("The ROM was reloaded")?;
+            }
+        }
+        match warning {
+            Some(warning) => self.notify(format!("Reloaded {}: {warning}", path.display())),
+            None => self.notify(format!("Reloaded {}", path.display())),
+        }
+        self.pause_for_debugger()?;
+        self.draw()
+    }
+
+    /// Keeps a program that was started over paused when a debugger is
+    /// attached, telling it so, and runs it otherwise.
+    fn pause_for_debugger(&mut self) -> Result<()> {
+        if self.debugger_attached() {
+            self.stop(&StopReason::Interrupt)
+        } else {
+            self.paused = false;
+            Ok(())
+        }
+    }
+
+    /// Replaces the machine with a fresh one running `rom`, unless it does
+    /// not load. The tools watching the program start over with it.
+    fn restart(
+        &mut self,
+        settings: Settings,
+        rom: Vec<u8>,
+    ) -> Result<Option<RomWarning>, ChipError> {
+        let mut chip8 = Chip8::with_settings(settings)?;
+        let warning = chip8.load_rom(&rom)?;
+
+        if let Some((detector, _)) = &mut self.detector {
+            let settings = chip8.settings();
+            *detector = Detector::new(settings.rom_addr, settings.font_range());
+        }
+        if rom != self.rom {
+            self.saved_state = None;
+        }
+        self.chip8 = chip8;
+        self.set_clocks();
+        self.rom = rom;
+        self.resume_from = None;
+        self.phosphor.set_filter(self.phosphor.filter());
+        Ok(warning)
+    }
+
+    /// Paces the machine at the speed the settings give, or faster when
+    /// fast-forwarding.
+    fn set_clocks(&mut self) {
+        let speed = if self.fast_forward { FAST_FORWARD } else { 1.0 };
+        self.cpu_clock = cpu_clock(&self.chip8, speed);
+        self.delay_clock = Clock::new(TIMER_FREQ * speed);
+        self.sound_clock = Clock::new(TIMER_FREQ * speed);
+    }
+
+    /// Goes back to the state the player saved, if any.
+    fn load_state(&mut self) -> Result<()> {
+        let Some(state) = &self.saved_state else {
+            self.notify("No state saved, press F1 to save one.");
+            return Ok(());
+        };
+
+        match self.chip8.load_state(state) {
+            Ok(()) => {
+                self.resume_from = None;
+                self.notify("State loaded");
+            }
+            Err(err) => self.notify(format!("Could not load the state: {err}")),
+        }
+        self.draw()
+    }
+
+    /// Shows the frame buffer through the display filter, with the on-screen
+    /// display over it.
+    fn draw(&mut self) -> Result<()> {
+        if self.picking {
+            let overlay = self.osd.lines(None, "");
+            if let Some(picker) = &mut self.picker {
+                return self.sdl.draw_picker(picker, &overlay);
+            }
+        }
+
+        let levels = self.phosphor.render(self.chip8.get_frame_buffer());
+        let platform = self
+            .chip8
+            .settings()
+            .platform()
+            .map_or("custom", |platform| platform.name());
+        let status = self.paused.then_some("Paused");
+        let overlay = self.osd.lines(status, platform);
+        self.sdl.draw_on_canvas(&levels, &overlay)
+    }
+
+    /// Shows a message on screen, and in the terminal.
+    fn notify(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        eprintln!("{message}");
+        self.osd.show(message);
+    }
+
+    fn debugger_attached(&self) -> bool {
+        self.gdb.as_ref().is_some_and(GdbServer::is_connected)
+            || self.dap.as_ref().is_some_and(DapServer::is_connected)
+    }
+
+    /// Executes a single instruction, even if there is a breakpoint on it.
+    fn single_step(&mut self) -> Result<()> {
+        self.resume_from = Some(self.chip8.program_counter());
+        let reason = self.step()?.unwrap_or(StopReason::Step);
+        self.stop(&reason)
+    }
+
+    /// Executes the next instruction, returning why execution should stop, if
+    /// it should. Reaching a breakpoint stops before the instruction is executed,
+    /// while watchpoints stop right after the access.
+    fn step(&mut self) -> Result<Option<StopReason>> {
+        let pc = self.chip8.program_counter();
+        if self.resume_from.take() != Some(pc) && self.breakpoints.is_breakpoint(pc) {
+            return Ok(Some(StopReason::Breakpoint(pc)));
+        }
+
+        let access = self
+            .chip8
+            .peek_word(pc)
+            .ok()
+            .and_then(|word| OpCode::try_from(word).ok())
+            .and_then(|op| op.memory_access(self.chip8.index()));
+
+        if let Some((detector, policy)) = &mut self.detector {
+            let mut stop = None;
+            for suspicion in detector.observe(&self.chip8) {
+                match policy {
+                    OpPolicy::Error => {
+                        if let Some(tracer) = &mut self.tracer {
+                            tracer.dump()?;
+                        }
+                        return Err(Error::msg(suspicion.to_string()));
+                    }
+                    OpPolicy::Pause if stop.is_none() => stop = Some(suspicion),
+                    _ => eprintln!("Warning: {suspicion}"),
+                }
+            }
+
+            // Stop before the instruction runs, it is only reported once
+            if let Some(suspicion) = stop {
+                return Ok(Some(StopReason::Suspicious(suspicion)));
+            }
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(&self.chip8);
+        }
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.before_cycle(&self.chip8);
+        }
+
+        if let Some(viewer) = &mut self.memory_viewer {
+            viewer.observe(&self.chip8);
+        }
+
+        let result = self.chip8.cycle();
+        self.osd.count_instruction();
+        if let Some(tracer) = &mut self.tracer {
+            tracer.after_cycle(&self.chip8, result.as_ref().err())?;
+        }
+
+        let mut reason = match result {
+            Ok(Flow::Continue) => None,
+            Ok(Flow::Warning(fault)) => {
+                eprintln!("Warning: {}", self.symbols.describe(&fault));
+                None
+            }
+            Ok(Flow::Pause(fault)) => Some(StopReason::Pause(fault)),
+            // With a debugger attached, the fault can be inspected instead
+            Err(fault) if self.debugger_attached() => Some(StopReason::Fault(fault)),
+            Err(fault) => return Err(fault.into()),
+        };
+
+        if let Some((access, range)) = access {
+            reason = reason.or_else(|| self.breakpoints.check_access(access, &range));
+        }
+
+        if let Some(dap) = &self.dap {
+            if reason.is_none() && dap.step_done(&self.chip8) {
+                reason = Some(StopReason::Step);
+            }
+        }
+
+        self.draw()?;
+        Ok(reason)
+    }
+
+    fn stop(&mut self, reason: &StopReason) -> Result<()> {
+        match reason {
+            StopReason::Step => {}
+            StopReason::Interrupt => eprintln!(
+                "Paused at {}. Press F5 to continue or F10 to step.",
+                self.symbols.format(self.chip8.program_counter())
+            ),
+            _ => self.notify(reason.describe(&self.symbols)),
+        }
+
+        if let StopReason::Fault(fault) = reason {
+            if matches!(
+                fault.error,
+                ChipError::StackOverflow | ChipError::StackUnderflow
+            ) {
+                let frames =
+                    debug::backtrace(fault.program_counter, &fault.snapshot.stack, &self.symbols);
+                for frame in frames {
+                    eprintln!("    {frame}");
+                }
+            }
+        }
+
+        self.paused = true;
+        if let Some(gdb) = &mut self.gdb {
+            gdb.report_stop(reason)?;
+        }
+
+        if let Some(dap) = &mut self.dap {
+            dap.report_stop(reason)?;
+        }
+
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.resume_from = Some(self.chip8.program_counter());
+    }
+}
+
+/// Returns a clock ticking at `speed` times the speed of `chip8`.
+fn cpu_clock(chip8: &Chip8, speed: f64) -> Clock {
+    Clock::new(f64::from(chip8.settings().cpu_freq) * speed)
+}
+
+/// Returns when the file at `path` was last modified, if it can be told.
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Returns a file name in the current directory, made unique by the time.
+fn timestamped(prefix: &str, extension: &str) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    PathBuf::from(format!("{prefix}-{millis}.{extension}"))
+}
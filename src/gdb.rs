@@ -0,0 +1,636 @@
+//! A GDB remote serial protocol stub for the CHIP-8 core.
+//!
+//! The register file exposed to the debugger is, in order: `V0` to `VF` (8 bit),
+//! `I` and `PC` (16 bit, big endian), `DT`, `ST` and `SP` (8 bit). It is also
+//! described by the `target.xml` served through `qXfer:features:read`.
+
+use crate::chip::{Chip8, ChipError};
+use crate::debug::{Breakpoints, StopReason, Watch};
+
+use std::fmt::Write as _;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chipper.chip8">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+    <reg name="sp" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// Number of registers in the register file.
+const REGISTERS: usize = 21;
+
+/// A request from the debugger to change how the machine is run.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GdbAction {
+    Continue,
+    Step,
+    Interrupt,
+    Detach,
+    Kill,
+}
+
+/// The answer to a single packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Reply {
+    Packet(String),
+    /// The packet is answered with a stop reply once the action completes.
+    Action(GdbAction),
+    /// The action is taken, and the packet answered right away.
+    ActionWithPacket(GdbAction, String),
+}
+
+/// A message received from the debugger.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Input {
+    Packet(String),
+    /// The out-of-band request to stop the running program (Ctrl-C).
+    Interrupt,
+}
+
+/// A GDB server listening on a local TCP port, serving one debugger at a time.
+pub struct GdbServer {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+    input: Vec<u8>,
+    no_ack: bool,
+}
+
+impl GdbServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            stream: None,
+            input: Vec::new(),
+            no_ack: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Accepts a new debugger and answers all packets received so far. Returns
+    /// the last request to resume or stop the machine, if there was one.
+    pub fn poll(
+        &mut self,
+        chip8: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+    ) -> io::Result<Option<GdbAction>> {
+        if self.stream.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.stream = Some(stream);
+                    self.input.clear();
+                    self.no_ack = false;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !self.receive()? {
+            // The debugger went away, so let the program run on its own again
+            self.stream = None;
+            return Ok(Some(GdbAction::Detach));
+        }
+
+        let mut action = None;
+        while let Some(packet) = self.next_packet()? {
+            let reply = match packet {
+                Input::Interrupt => Reply::Action(GdbAction::Interrupt),
+                Input::Packet(packet) => Self::handle_packet(&packet, chip8, breakpoints),
+            };
+
+            match reply {
+                Reply::Packet(packet) => self.send(&packet)?,
+                Reply::Action(next) => action = Some(next),
+                Reply::ActionWithPacket(next, packet) => {
+                    self.send(&packet)?;
+                    action = Some(next);
+                }
+            }
+        }
+
+        if matches!(action, Some(GdbAction::Detach | GdbAction::Kill)) {
+            self.stream = None;
+        }
+
+        Ok(action)
+    }
+
+    /// Tells the debugger that the machine has stopped.
+    pub fn report_stop(&mut self, reason: &StopReason) -> io::Result<()> {
+        if self.stream.is_none() {
+            return Ok(());
+        }
+
+        let reply = match reason {
+            StopReason::Breakpoint(_)
+            | StopReason::Step
+            | StopReason::Pause(_)
+            | StopReason::Suspicious(_) => String::from("S05"),
+            StopReason::Watchpoint { addr, watch } => {
+                let kind = match watch {
+                    Watch::Write => "watch",
+                    Watch::Read => "rwatch",
+                    Watch::Access => "awatch",
+                };
+                format!("T05{kind}:{addr:x};")
+            }
+            StopReason::Interrupt => String::from("S02"),
+            StopReason::Fault(fault) => match fault.error {
+                ChipError::InvalidOpCode(_) | ChipError::UnsupportedSysAddr(_) => {
+                    String::from("S04")
+                }
+                _ => String::from("S0b"),
+            },
+        };
+
+        self.send(&reply)
+    }
+
+    /// Reads everything available from the debugger, returning `false` once
+    /// the connection is closed.
+    fn receive(&mut self) -> io::Result<bool> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(true);
+        };
+
+        let mut buffer = [0; 1024];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Takes the next complete packet or interrupt request out of the input.
+    fn next_packet(&mut self) -> io::Result<Option<Input>> {
+        loop {
+            let Some(&first) = self.input.first() else {
+                return Ok(None);
+            };
+
+            match first {
+                0x03 => {
+                    self.input.remove(0);
+                    return Ok(Some(Input::Interrupt));
+                }
+                b'$' => {
+                    let Some(end) = self.input.iter().position(|&b| b == b'#') else {
+                        return Ok(None);
+                    };
+
+                    if self.input.len() < end + 3 {
+                        return Ok(None);
+                    }
+
+                    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+                    let valid = checksum == Some(Self::checksum(data));
+                    if !self.no_ack {
+                        self.write_raw(if valid { b"+" } else { b"-" })?;
+                    }
+
+                    if valid {
+                        let data = String::from_utf8_lossy(data).into_owned();
+                        if data == "QStartNoAckMode" {
+                            self.send("OK")?;
+                            self.no_ack = true;
+                            continue;
+                        }
+
+                        return Ok(Some(Input::Packet(data)));
+                    }
+                }
+                _ => {
+                    // Acknowledgements and line noise
+                    self.input.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", Self::checksum(data.as_bytes()));
+        self.write_raw(packet.as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(());
+        };
+
+        // The stream is non-blocking, so keep trying until everything is sent
+        let mut written = 0;
+        while written < bytes.len() {
+            match stream.write(&bytes[written..]) {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn checksum(data: &[u8]) -> u8 {
+        data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+    }
+
+    fn handle_packet(packet: &str, chip8: &mut Chip8, breakpoints: &mut Breakpoints) -> Reply {
+        let error = || Reply::Packet(String::from("E01"));
+        let ok = || Reply::Packet(String::from("OK"));
+
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        match command {
+            "?" => Reply::Packet(String::from("S05")),
+            "g" => Reply::Packet(Self::read_registers(chip8)),
+            "G" => match Self::decode_hex(args) {
+                Some(bytes) if bytes.len() == Self::register_file_len() => {
+                    Self::write_registers(chip8, &bytes);
+                    ok()
+                }
+                _ => error(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS => Reply::Packet(Self::read_register(chip8, n)),
+                _ => error(),
+            },
+            "P" => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    Some((usize::from_str_radix(n, 16).ok()?, Self::decode_hex(value)?))
+                });
+                match register {
+                    Some((n, value)) if n < REGISTERS && value.len() == Self::width(n) => {
+                        Self::write_register(chip8, n, &value);
+                        ok()
+                    }
+                    _ => error(),
+                }
+            }
+            "m" => Self::parse_addr_len(args)
+                .and_then(|(addr, len)| {
+                    (0..len)
+                        .map(|offset| chip8.read_byte(addr.checked_add(offset)?).ok())
+                        .collect::<Option<Vec<u8>>>()
+                })
+                .map_or_else(error, |bytes| Reply::Packet(Self::encode_hex(&bytes))),
+            "M" => {
+                let write = args.split_once(':').and_then(|(target, data)| {
+                    let (addr, len) = Self::parse_addr_len(target)?;
+                    let bytes = Self::decode_hex(data)?;
+                    (bytes.len() == len as usize).then_some((addr, bytes))
+                });
+                let Some((addr, bytes)) = write else {
+                    return error();
+                };
+
+                let written = bytes.iter().zip(0..).all(|(&byte, offset)| {
+                    addr.checked_add(offset)
+                        .is_some_and(|addr| chip8.write_byte(addr, byte).is_ok())
+                });
+                if written {
+                    ok()
+                } else {
+                    error()
+                }
+            }
+            "c" => Self::resume(chip8, args, GdbAction::Continue),
+            "s" => Self::resume(chip8, args, GdbAction::Step),
+            "Z" | "z" => Self::handle_breakpoint(command == "Z", args, breakpoints),
+            "H" | "T" => ok(),
+            "D" => Reply::ActionWithPacket(GdbAction::Detach, String::from("OK")),
+            "k" => Reply::Action(GdbAction::Kill),
+            "q" => Self::handle_query(args),
+            "v" => Self::handle_v(args),
+            _ => Reply::Packet(String::new()),
+        }
+    }
+
+    fn handle_query(query: &str) -> Reply {
+        let reply = match query {
+            "C" => String::from("QC1"),
+            "Attached" => String::from("1"),
+            "fThreadInfo" => String::from("m1"),
+            "sThreadInfo" => String::from("l"),
+            _ if query.starts_with("Supported") => {
+                String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+")
+            }
+            _ if query.starts_with("Xfer:features:read:target.xml:") => {
+                let range = query.trim_start_matches("Xfer:features:read:target.xml:");
+                match Self::parse_addr_len(range) {
+                    Some((offset, len)) => {
+                        let start = (offset as usize).min(TARGET_XML.len());
+                        let end = (start + len as usize).min(TARGET_XML.len());
+                        let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+                        format!("{marker}{}", &TARGET_XML[start..end])
+                    }
+                    None => String::from("E01"),
+                }
+            }
+            _ => String::new(),
+        };
+
+        Reply::Packet(reply)
+    }
+
+    fn handle_v(command: &str) -> Reply {
+        match command {
+            "Cont?" => Reply::Packet(String::from("vCont;c;s")),
+            _ if command.starts_with("Cont;c") => Reply::Action(GdbAction::Continue),
+            _ if command.starts_with("Cont;s") => Reply::Action(GdbAction::Step),
+            _ if command.starts_with("Kill") => {
+                Reply::ActionWithPacket(GdbAction::Kill, String::from("OK"))
+            }
+            _ => Reply::Packet(String::new()),
+        }
+    }
+
+    fn handle_breakpoint(insert: bool, args: &str, breakpoints: &mut Breakpoints) -> Reply {
+        let mut parts = args.split(',');
+        let kind = parts.next();
+        let addr = parts
+            .next()
+            .and_then(|addr| u16::from_str_radix(addr, 16).ok());
+        let len = parts
+            .next()
+            .and_then(|len| u16::from_str_radix(len, 16).ok())
+            .unwrap_or(1)
+            .max(1);
+
+        let Some(addr) = addr else {
+            return Reply::Packet(String::from("E01"));
+        };
+
+        let watch = match kind {
+            Some("0" | "1") => {
+                if insert {
+                    breakpoints.add_breakpoint(addr);
+                } else {
+                    breakpoints.remove_breakpoint(addr);
+                }
+                return Reply::Packet(String::from("OK"));
+            }
+            Some("2") => Watch::Write,
+            Some("3") => Watch::Read,
+            Some("4") => Watch::Access,
+            _ => return Reply::Packet(String::new()),
+        };
+
+        let range = addr..addr.saturating_add(len);
+        if insert {
+            breakpoints.add_watchpoint(range, watch);
+        } else {
+            breakpoints.remove_watchpoint(&range, watch);
+        }
+
+        Reply::Packet(String::from("OK"))
+    }
+
+    fn resume(chip8: &mut Chip8, addr: &str, action: GdbAction) -> Reply {
+        if let Ok(addr) = u16::from_str_radix(addr, 16) {
+            chip8.set_program_counter(addr);
+        }
+
+        Reply::Action(action)
+    }
+
+    /// Number of bytes used by register `n`.
+    fn width(n: usize) -> usize {
+        if n == 16 || n == 17 {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn register_file_len() -> usize {
+        (0..REGISTERS).map(Self::width).sum()
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn register_value(chip8: &Chip8, n: usize) -> u16 {
+        match n {
+            0..=15 => u16::from(chip8.register(n as u8)),
+            16 => chip8.index(),
+            17 => chip8.program_counter(),
+            18 => u16::from(chip8.delay_timer()),
+            19 => u16::from(chip8.sound_timer()),
+            _ => chip8.snapshot().stack.len() as u16,
+        }
+    }
+
+    fn read_register(chip8: &Chip8, n: usize) -> String {
+        let value = Self::register_value(chip8, n);
+        if Self::width(n) == 2 {
+            format!("{value:04x}")
+        } else {
+            format!("{value:02x}")
+        }
+    }
+
+    fn read_registers(chip8: &Chip8) -> String {
+        (0..REGISTERS)
+            .map(|n| Self::read_register(chip8, n))
+            .collect()
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn write_register(chip8: &mut Chip8, n: usize, bytes: &[u8]) {
+        let value = bytes
+            .iter()
+            .fold(0_u16, |value, &byte| (value << 8) | u16::from(byte));
+
+        match n {
+            0..=15 => chip8.set_register(n as u8, value as u8),
+            16 => chip8.set_index(value),
+            17 => chip8.set_program_counter(value),
+            18 => chip8.set_delay_timer(value as u8),
+            19 => chip8.set_sound_timer(value as u8),
+            // The stack pointer is only changed by calls and returns
+            _ => {}
+        }
+    }
+
+    fn write_registers(chip8: &mut Chip8, bytes: &[u8]) {
+        let mut offset = 0;
+        for n in 0..REGISTERS {
+            let width = Self::width(n);
+            Self::write_register(chip8, n, &bytes[offset..offset + width]);
+            offset += width;
+        }
+    }
+
+    fn parse_addr_len(args: &str) -> Option<(u16, u16)> {
+        let (addr, len) = args.split_once(',')?;
+        Some((
+            u16::from_str_radix(addr, 16).ok()?,
+            u16::from_str_radix(len, 16).ok()?,
+        ))
+    }
+
+    fn encode_hex(bytes: &[u8]) -> String {
+        bytes.iter().fold(String::new(), |mut hex, byte| {
+            // Writing to a `String` cannot fail
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+    }
+
+    fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(packet: &str, chip8: &mut Chip8, breakpoints: &mut Breakpoints) -> Reply {
+        GdbServer::handle_packet(packet, chip8, breakpoints)
+    }
+
+    fn packet(reply: &str) -> Reply {
+        Reply::Packet(String::from(reply))
+    }
+
+    #[test]
+    fn registers() {
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+        chip8.set_register(0x1, 0xAB);
+        chip8.set_index(0x0300);
+
+        let Reply::Packet(registers) = handle("g", &mut chip8, &mut breakpoints) else {
+            panic!("expected the register file");
+        };
+        assert_eq!(registers.len(), GdbServer::register_file_len() * 2);
+        assert_eq!(&registers[2..4], "ab");
+        assert_eq!(&registers[32..40], "03000200");
+
+        assert_eq!(
+            handle("P11=0400", &mut chip8, &mut breakpoints),
+            packet("OK")
+        );
+        assert_eq!(chip8.program_counter(), 0x400);
+        assert_eq!(handle("p11", &mut chip8, &mut breakpoints), packet("0400"));
+    }
+
+    #[test]
+    fn memory() {
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+
+        assert_eq!(
+            handle("M300,2:beef", &mut chip8, &mut breakpoints),
+            packet("OK")
+        );
+        assert_eq!(
+            handle("m2ff,4", &mut chip8, &mut breakpoints),
+            packet("00beef00")
+        );
+        assert_eq!(
+            handle("mfff,2", &mut chip8, &mut breakpoints),
+            packet("E01")
+        );
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+
+        assert_eq!(
+            handle("Z0,204,2", &mut chip8, &mut breakpoints),
+            packet("OK")
+        );
+        assert!(breakpoints.is_breakpoint(0x204));
+        assert_eq!(
+            handle("z0,204,2", &mut chip8, &mut breakpoints),
+            packet("OK")
+        );
+        assert!(!breakpoints.is_breakpoint(0x204));
+
+        assert_eq!(
+            handle("Z2,300,1", &mut chip8, &mut breakpoints),
+            packet("OK")
+        );
+        assert_eq!(breakpoints.watchpoints().count(), 1);
+        assert_eq!(
+            handle("c", &mut chip8, &mut breakpoints),
+            Reply::Action(GdbAction::Continue)
+        );
+    }
+
+    #[test]
+    fn packets() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut server = GdbServer {
+            listener,
+            stream: None,
+            input: b"+$m200,2#5d\x03$?#3f".to_vec(),
+            no_ack: true,
+        };
+
+        assert_eq!(
+            server.next_packet().unwrap(),
+            Some(Input::Packet(String::from("m200,2")))
+        );
+        assert_eq!(server.next_packet().unwrap(), Some(Input::Interrupt));
+        assert_eq!(
+            server.next_packet().unwrap(),
+            Some(Input::Packet(String::from("?")))
+        );
+        assert_eq!(server.next_packet().unwrap(), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Running a ROM for a fixed number of frames, without a window or an audio
+//! device.
+
+use super::emulator::TIMER_FREQ;
+
+use chipper::audio::Mixer;
+use chipper::capture::Capture;
+use chipper::chip::{Chip8, Flow};
+use chipper::wav::WavWriter;
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::BufWriter;
+
+pub struct Headless {
+    chip8: Chip8,
+    recorder: Option<(Mixer, WavWriter<BufWriter<File>>)>,
+    capture: Option<Capture>,
+    samples: Vec<f32>,
+}
+
+impl Headless {
+    pub fn new(chip8: Chip8) -> Self {
+        Self {
+            chip8,
+            recorder: None,
+            capture: None,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_recorder(&mut self, mixer: Mixer, wav: WavWriter<BufWriter<File>>) {
+        self.recorder = Some((mixer, wav));
+    }
+
+    pub fn set_capture(&mut self, capture: Capture) {
+        self.capture = Some(capture);
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    /// Runs `frames` frames of 1/60 s as fast as possible, stopping at the
+    /// first fault. Machine code calls and invalid instructions skipped by a
+    /// policy are reported, but do not pause.
+    pub fn run(&mut self, frames: u64) -> Result<()> {
+        let cycles_per_frame = f64::from(self.chip8.settings().cpu_freq) / TIMER_FREQ;
+        let mut cycles = 0.0;
+
+        for _ in 0..frames {
+            cycles += cycles_per_frame;
+            while cycles >= 1.0 {
+                cycles -= 1.0;
+                match self.chip8.cycle()? {
+                    Flow::Continue => {}
+                    Flow::Warning(fault) | Flow::Pause(fault) => eprintln!("Warning: {fault}"),
+                }
+            }
+
+            self.chip8.tick_delay_timer();
+            if let Some(capture) = &mut self.capture {
+                capture.add_frame(self.chip8.get_frame_buffer())?;
+            }
+
+            let active = self.chip8.should_beep();
+            self.chip8.tick_sound_timer();
+
+            if let Some((mixer, wav)) = &mut self.recorder {
+                self.samples.clear();
+                mixer.render_frame(active, TIMER_FREQ, &mut self.samples);
+                wav.write_samples(&self.samples)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Completes the WAV file and video capture being recorded, if any.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some((_, wav)) = self.recorder.take() {
+            wav.finish()?;
+        }
+
+        if let Some(capture) = self.capture.take() {
+            capture.finish()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+//! Turning frame buffers into images: PNG with any palette, PBM, and text
+//! grids, all at any integer scale.
+
+use crate::chip::FrameBuffer;
+use crate::{CHIP8_HEIGHT, CHIP8_WIDTH};
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The characters of unlit and lit pixels in text grids.
+pub const TEXT_PIXELS: [char; 2] = ['.', '#'];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Pbm,
+    Text,
+}
+
+impl ImageFormat {
+    /// Picks the format from the extension of `path`: `.pbm` and `.txt` are
+    /// recognised, anything else is PNG.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("pbm") => Self::Pbm,
+            Some("txt") => Self::Text,
+            _ => Self::Png,
+        }
+    }
+}
+
+/// The colors of unlit and lit pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Self {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xFF, 0xFF, 0xFF],
+        }
+    }
+
+    /// Parses two hexadecimal colors separated by a comma, as in
+    /// `000000,FFFFFF`, background first.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (background, foreground) = text.split_once(',')?;
+        Some(Self {
+            background: parse_color(background)?,
+            foreground: parse_color(foreground)?,
+        })
+    }
+
+    /// Returns both colors one after the other, as used by indexed images.
+    pub fn to_bytes(&self) -> [u8; 6] {
+        let [r0, g0, b0] = self.background;
+        let [r1, g1, b1] = self.foreground;
+        [r0, g0, b0, r1, g1, b1]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses a hexadecimal color such as `FFA000`, with an optional `#`.
+pub fn parse_color(text: &str) -> Option<[u8; 3]> {
+    let text = text.trim().trim_start_matches('#');
+    if text.len() != 6 || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let rgb = u32::from_str_radix(text, 16).ok()?.to_be_bytes();
+    Some([rgb[1], rgb[2], rgb[3]])
+}
+
+/// Returns the size of an image of a frame buffer at `scale`.
+pub fn size(scale: usize) -> (usize, usize) {
+    (CHIP8_WIDTH * scale, CHIP8_HEIGHT * scale)
+}
+
+/// Returns one byte per pixel of the scaled image: 0 where the pixel is
+/// unlit, 1 where it is lit.
+pub fn indexed(frame_buffer: &FrameBuffer, scale: usize) -> Vec<u8> {
+    let (width, height) = size(scale);
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in frame_buffer {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|&pixel| std::iter::repeat_n(u8::from(pixel != 0), scale))
+            .collect();
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+
+    pixels
+}
+
+/// Writes the frame buffer in the given format. PBM has no colors, so the
+/// palette only applies to PNG.
+pub fn write_image(
+    mut w: impl Write,
+    format: ImageFormat,
+    frame_buffer: &FrameBuffer,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::Png => write_png(w, frame_buffer, scale, palette),
+        ImageFormat::Pbm => write_pbm(w, frame_buffer, scale),
+        ImageFormat::Text => w.write_all(to_text(frame_buffer, scale, TEXT_PIXELS).as_bytes()),
+    }
+}
+
+/// Saves the frame buffer to `path`, in the format its extension picks.
+pub fn save(
+    path: &Path,
+    frame_buffer: &FrameBuffer,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let output = BufWriter::new(File::create(path)?);
+    write_image(
+        output,
+        ImageFormat::from_path(path),
+        frame_buffer,
+        scale,
+        palette,
+    )
+}
+
+/// Writes the frame buffer as a binary PBM image, with lit pixels in black.
+pub fn write_pbm(mut w: impl Write, frame_buffer: &FrameBuffer, scale: usize) -> io::Result<()> {
+    let (width, height) = size(scale);
+    writeln!(w, "P4\n{width} {height}")?;
+    for row in indexed(frame_buffer, scale).chunks(width) {
+        let packed: Vec<u8> = row
+            .chunks(8)
+            .map(|bits| {
+                bits.iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, &bit)| byte | bit << (7 - i))
+            })
+            .collect();
+        w.write_all(&packed)?;
+    }
+
+    w.flush()
+}
+
+/// Returns the frame buffer as lines of text, with one of `pixels` for every
+/// unlit and lit pixel.
+pub fn to_text(frame_buffer: &FrameBuffer, scale: usize, pixels: [char; 2]) -> String {
+    let (width, _) = size(scale);
+    let mut text = String::new();
+    for row in indexed(frame_buffer, scale).chunks(width) {
+        text.extend(row.iter().map(|&pixel| pixels[usize::from(pixel)]));
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Writes the frame buffer as a PNG image.
+#[allow(clippy::cast_possible_truncation)]
+pub fn write_png(
+    w: impl Write,
+    frame_buffer: &FrameBuffer,
+    scale: usize,
+    palette: &Palette,
+) -> io::Result<()> {
+    let (width, height) = size(scale);
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.to_bytes().to_vec());
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&indexed(frame_buffer, scale)))
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes() {
+        assert_eq!(
+            Palette::parse("#102030,FFA000"),
+            Some(Palette {
+                background: [0x10, 0x20, 0x30],
+                foreground: [0xFF, 0xA0, 0x00],
+            })
+        );
+        assert_eq!(Palette::parse("102030"), None);
+        assert_eq!(Palette::parse("10203,FFA000"), None);
+    }
+
+    #[test]
+    fn exports() {
+        let mut frame_buffer = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        frame_buffer[0][0] = 1;
+        frame_buffer[1][9] = 1;
+
+        let text = to_text(&frame_buffer, 1, TEXT_PIXELS);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), CHIP8_HEIGHT);
+        assert_eq!(&lines[1][..10], ".........#");
+
+        let mut pbm = Vec::new();
+        write_pbm(&mut pbm, &frame_buffer, 1).unwrap();
+        let header = b"P4\n64 32\n";
+        assert_eq!(&pbm[..header.len()], header);
+        assert_eq!(pbm.len(), header.len() + 8 * CHIP8_HEIGHT);
+        assert_eq!(pbm[header.len()], 0x80);
+        assert_eq!(pbm[header.len() + 9], 0x40);
+
+        assert_eq!(
+            ImageFormat::from_path(Path::new("shot.PBM")),
+            ImageFormat::Pbm
+        );
+    }
+
+    #[test]
+    fn scales_pixels() {
+        let mut frame_buffer = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        frame_buffer[0][1] = 1;
+
+        let pixels = indexed(&frame_buffer, 2);
+        assert_eq!(pixels.len(), CHIP8_WIDTH * CHIP8_HEIGHT * 4);
+        assert_eq!(pixels[..4], [0, 0, 1, 1]);
+        assert_eq!(pixels[CHIP8_WIDTH * 2..CHIP8_WIDTH * 2 + 4], [0, 0, 1, 1]);
+    }
+}
@@ -0,0 +1,27 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::module_name_repetitions)]
+#![allow(clippy::must_use_candidate)]
+#![allow(clippy::missing_errors_doc)]
+
+pub mod analysis;
+pub mod audio;
+pub mod capture;
+pub mod chip;
+pub mod crt;
+pub mod dap;
+pub mod debug;
+pub mod detector;
+pub mod gdb;
+pub mod image;
+pub mod library;
+pub mod monitor;
+pub mod octo;
+pub mod phosphor;
+pub mod profile;
+pub mod source_map;
+pub mod symbols;
+pub mod trace;
+pub mod wav;
+
+pub const CHIP8_HEIGHT: usize = 32;
+pub const CHIP8_WIDTH: usize = 64;
@@ -0,0 +1,163 @@
+//! Collections of ROMs, from a directory or a ZIP archive.
+//!
+//! Only files with a ROM extension are listed, and the extension also tells
+//! which platform a ROM was written for.
+
+use crate::chip::Platform;
+
+use std::fs;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// The extensions of ROM files, in lowercase.
+pub const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8", "sc8", "xo8"];
+
+enum Source {
+    Directory(PathBuf),
+    Zip(ZipArchive<Cursor<Vec<u8>>>),
+}
+
+pub struct RomLibrary {
+    source: Source,
+    /// Paths relative to the directory, or names within the archive, sorted.
+    names: Vec<String>,
+}
+
+impl RomLibrary {
+    /// Returns whether `path` is a collection rather than a single ROM.
+    pub fn is_collection(path: &Path) -> bool {
+        path.is_dir() || has_extension(path, "zip")
+    }
+
+    /// Lists the ROMs of a directory, including its subdirectories, or of a
+    /// ZIP archive.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if !path.is_dir() {
+            return Self::zip(fs::read(path)?);
+        }
+
+        let mut names = Vec::new();
+        list_directory(path, Path::new(""), &mut names)?;
+        names.sort();
+        Ok(Self {
+            source: Source::Directory(path.to_path_buf()),
+            names,
+        })
+    }
+
+    /// Lists the ROMs of a ZIP archive held in memory.
+    pub fn zip(bytes: Vec<u8>) -> io::Result<Self> {
+        let archive = ZipArchive::new(Cursor::new(bytes)).map_err(io::Error::other)?;
+        let mut names: Vec<String> = archive
+            .file_names()
+            .filter(|name| is_rom(Path::new(name)))
+            .map(String::from)
+            .collect();
+        names.sort();
+
+        Ok(Self {
+            source: Source::Zip(archive),
+            names,
+        })
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Reads the ROM at `index` in `names`.
+    pub fn read(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let name = self
+            .names
+            .get(index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such ROM"))?;
+        match &mut self.source {
+            Source::Directory(path) => fs::read(path.join(name)),
+            Source::Zip(archive) => {
+                let mut file = archive.by_name(name).map_err(io::Error::other)?;
+                let mut rom = Vec::new();
+                file.read_to_end(&mut rom)?;
+                Ok(rom)
+            }
+        }
+    }
+}
+
+/// Returns the platform ROMs named like `name` are written for: `.sc8` for
+/// SUPER-CHIP, `.xo8` for modern interpreters, `.ch8` and `.c8` for the
+/// original.
+pub fn platform(name: &str) -> Option<Platform> {
+    let path = Path::new(name);
+    if has_extension(path, "sc8") {
+        Some(Platform::Schip)
+    } else if has_extension(path, "xo8") {
+        Some(Platform::Modern)
+    } else if has_extension(path, "ch8") || has_extension(path, "c8") {
+        Some(Platform::Vip)
+    } else {
+        None
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|other| other.eq_ignore_ascii_case(extension))
+}
+
+fn is_rom(path: &Path) -> bool {
+    ROM_EXTENSIONS
+        .iter()
+        .any(|extension| has_extension(path, extension))
+}
+
+fn list_directory(root: &Path, dir: &Path, names: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(dir))? {
+        let entry = entry?;
+        let relative = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_directory(root, &relative, names)?;
+        } else if is_rom(&relative) {
+            names.push(relative.to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    #[test]
+    fn lists_roms() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, rom) in &[
+            ("readme.txt", &b"hello"[..]),
+            ("games/pong.ch8", &[0x00, 0xE0][..]),
+            ("BLINKY.SC8", &[0x12, 0x00][..]),
+        ] {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(rom).unwrap();
+        }
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let mut library = RomLibrary::zip(bytes).unwrap();
+        assert_eq!(library.names(), ["BLINKY.SC8", "games/pong.ch8"]);
+        assert_eq!(library.read(1).unwrap(), [0x00, 0xE0]);
+        assert!(library.read(2).is_err());
+
+        assert_eq!(platform("BLINKY.SC8"), Some(Platform::Schip));
+        assert_eq!(platform("games/pong.ch8"), Some(Platform::Vip));
+        assert_eq!(platform("readme.txt"), None);
+    }
+}
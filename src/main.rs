@@ -1,29 +1,324 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
 
-mod chip;
+mod emulator;
+mod font;
+mod headless;
+mod memory_viewer;
+mod options;
+mod osd;
+mod report;
+mod rom_picker;
 mod sdl2_wrapper;
 
-use chip::Chip8;
+use chipper::analysis::Analysis;
+use chipper::audio::{Mixer, SAMPLE_RATE};
+use chipper::capture::Capture;
+use chipper::chip::Chip8;
+use chipper::chip::Fault;
+use chipper::dap::DapServer;
+use chipper::detector::Detector;
+use chipper::gdb::GdbServer;
+use chipper::image;
+use chipper::library::RomLibrary;
+use chipper::octo;
+use chipper::octo::{Cartridge, OctoOptions};
+use chipper::phosphor::Phosphor;
+use chipper::profile::Profiler;
+use chipper::symbols::Symbols;
+use chipper::trace::Tracer;
+use chipper::wav::WavWriter;
+use emulator::Emulator;
+use headless::Headless;
+use options::Options;
+use rom_picker::RomPicker;
 
 use anyhow::Error;
 use anyhow::Result;
 use sdl2_wrapper::Sdl2Wrapper;
+use std::fs::File;
+use std::io::Write;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 
-const CHIP8_HEIGHT: usize = 32;
-const CHIP8_WIDTH: usize = 64;
 const SCALE: usize = 20;
+const HEATMAP_SCALE: u32 = 8;
 
 fn main() -> Result<()> {
-    let path = std::env::args()
-        .nth(1)
-        .ok_or_else(|| Error::msg("No rom path provided. Exiting."))?;
-    let rom = std::fs::read(path)?;
+    let mut options = Options::parse(std::env::args().skip(1))?;
+    if apply_octo_options(&mut options)? {
+        return Ok(());
+    }
+
+    if let (Some(path), Some(rom_path)) = (&options.cfg, &options.rom_path) {
+        return export_cfg(rom_path, path, options.settings.rom_addr);
+    }
+
+    let symbols = load_symbols(&options)?;
+    let mut chip8 = Chip8::with_settings(options.settings.clone())
+        .map_err(|e| Error::msg(format!("{e} Exiting.")))?;
+    let source = open_rom(&options, &mut chip8)?;
+
+    if let Some(frames) = options.headless {
+        return run_headless(&options, chip8, frames, &symbols);
+    }
 
     let mut sdl = Sdl2Wrapper::new()?;
-    let mut chip8 = Chip8::new();
-    chip8.load_rom(&rom)?;
-    chip8.run(&mut sdl)?;
+    sdl.set_palette(options.palette);
+    sdl.set_effects(options.effects);
+    let mut emulator = Emulator::new(chip8, sdl);
+    emulator.set_tone(options.tone);
+    emulator.set_phosphor(Phosphor::new(options.filter, options.decay));
+    emulator.show_counter(options.fps);
+    if let Some(path) = &options.capture {
+        emulator.start_capture(PathBuf::from(path))?;
+    }
+    if let Some(path) = &options.wav {
+        let file = BufWriter::new(File::create(path)?);
+        emulator.set_recorder(WavWriter::new(file, emulator.sample_rate())?);
+    }
+    emulator.set_symbols(symbols.clone());
+    match source {
+        Some(RomSource::File(rom)) => emulator.set_rom(rom),
+        Some(RomSource::Collection(picker)) => emulator.open_picker(picker),
+        None => {}
+    }
+    if let (Some(watch), Some(path)) = (&options.watch, &options.rom_path) {
+        emulator.watch(PathBuf::from(path), watch.keep_breakpoints);
+    }
+    if let Some(trace) = options.trace {
+        let output: Box<dyn Write> = if trace.path == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(&trace.path)?))
+        };
+
+        let mut tracer = Tracer::new(output)
+            .with_filter(trace.filter)
+            .with_symbols(symbols.clone());
+        if let Some(len) = trace.ring {
+            tracer = tracer.with_ring_buffer(len);
+        }
+        emulator.set_tracer(tracer);
+    }
+
+    if let Some(port) = options.gdb_port {
+        let gdb = GdbServer::bind(port)?;
+        eprintln!("Waiting for GDB on {}", gdb.local_addr()?);
+        emulator.set_gdb(gdb);
+    }
+
+    if let Some(port) = options.dap_port {
+        let dap = DapServer::bind(port)?;
+        eprintln!(
+            "Waiting for a debug adapter client on {}",
+            dap.local_addr()?
+        );
+        emulator.set_dap(dap);
+    }
+
+    if let Some(policy) = options.detect {
+        let settings = emulator.chip8().settings();
+        let detector = Detector::new(settings.rom_addr, settings.font_range());
+        emulator.set_detector(detector, policy);
+    }
+
+    if options.memory_viewer {
+        emulator.toggle_memory_viewer()?;
+    }
+
+    if options.profile.is_some() || options.heatmap.is_some() {
+        emulator.set_profiler(Profiler::new());
+    }
+
+    let result = if !options.monitor || emulator.enter_monitor()? {
+        emulator.run()
+    } else {
+        Ok(())
+    };
+    if let Some(profiler) = emulator.profiler() {
+        if let Some(path) = &options.profile {
+            profiler.write_report(emulator.chip8(), BufWriter::new(File::create(path)?))?;
+        }
+
+        if let Some(path) = &options.heatmap {
+            profiler.write_heatmap(BufWriter::new(File::create(path)?), HEATMAP_SCALE)?;
+        }
+    }
+
+    if let Some(path) = &options.screenshot {
+        emulator.screenshot(Path::new(path))?;
+    }
+    emulator.finish_recording()?;
+    emulator.finish_capture()?;
+
+    check_crash(result, emulator.chip8(), &symbols)
+}
+
+/// Applies the options of the Octo cartridge and options file given, the
+/// latter taking precedence, and writes out the source code or options asked
+/// for. Returns whether that was all there was to do. A ROM that is itself a
+/// cartridge gives the options, unless `--cartridge` does.
+fn apply_octo_options(options: &mut Options) -> Result<bool> {
+    let read_error = |path: &str, e| Error::msg(format!("Could not read '{path}': {e}. Exiting."));
+
+    let mut octo = OctoOptions::default();
+    let rom_cartridge = options
+        .rom_path
+        .as_ref()
+        .filter(|path| octo::is_cartridge(Path::new(path)));
+    if rom_cartridge.is_some() && options.settings.rom_addr != octo::PROGRAM_START {
+        return Err(Error::msg(format!(
+            "Octo cartridges are assembled to run at 0x{:03X}, not at 0x{:03X}. Exiting.",
+            octo::PROGRAM_START,
+            options.settings.rom_addr
+        )));
+    }
+    if let Some(path) = options.cartridge.as_ref().or(rom_cartridge) {
+        let cartridge = File::open(path)
+            .and_then(|file| Cartridge::decode(BufReader::new(file)))
+            .map_err(|e| read_error(path, e))?;
+        if let Some(source) = &options.cart_source {
+            std::fs::write(source, cartridge.program)?;
+            return Ok(true);
+        }
+        octo = cartridge.options;
+        octo.apply(&mut options.settings);
+        options.palette = octo.palette().unwrap_or(options.palette);
+    }
+    if let Some(path) = &options.octo_json {
+        let json = std::fs::read(path)
+            .and_then(|json| serde_json::from_slice(&json).map_err(std::io::Error::other))
+            .map_err(|e| read_error(path, e))?;
+        let file = OctoOptions::from_json(&json);
+        file.apply(&mut options.settings);
+        options.palette = file.palette().unwrap_or(options.palette);
+        // The others are all taken from the settings when saving
+        octo.other.extend(file.other);
+    }
+
+    let unsupported = octo.unsupported();
+    if !unsupported.is_empty() {
+        eprintln!(
+            "Ignoring Octo options chipper does not support: {}",
+            unsupported.join(", ")
+        );
+    }
+
+    if let Some(path) = &options.save_octo_json {
+        octo.update(&options.settings, &options.palette);
+        let json = serde_json::to_string_pretty(&octo.to_json())?;
+        std::fs::write(path, json + "\n")?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// What the ROM path points to.
+enum RomSource {
+    /// A single ROM, loaded into the machine.
+    File(Vec<u8>),
+    /// A directory or archive to pick the ROM from.
+    Collection(RomPicker),
+}
+
+/// Loads the ROM file into `chip8`, or returns a picker for the ROMs of a
+/// directory or archive.
+fn open_rom(options: &Options, chip8: &mut Chip8) -> Result<Option<RomSource>> {
+    let path = match &options.rom_path {
+        Some(path) => Path::new(path),
+        None => return Ok(None),
+    };
+
+    if RomLibrary::is_collection(path) {
+        let library = RomLibrary::open(path)?;
+        let picker = RomPicker::new(library, options.platform.is_none());
+        return Ok(Some(RomSource::Collection(picker)));
+    }
+
+    let rom = octo::read_rom(path).map_err(|e| {
+        Error::msg(format!(
+            "Could not read '{}': {e}. Exiting.",
+            path.display()
+        ))
+    })?;
+    if let Some(warning) = chip8.load_rom(&rom)? {
+        eprintln!("{warning}");
+    }
+    Ok(Some(RomSource::File(rom)))
+}
+
+/// Runs `frames` frames without a window, recording what the options ask for.
+fn run_headless(options: &Options, chip8: Chip8, frames: u64, symbols: &Symbols) -> Result<()> {
+    let mut headless = Headless::new(chip8);
+    if let Some(path) = &options.wav {
+        let wav = WavWriter::new(BufWriter::new(File::create(path)?), SAMPLE_RATE)?;
+        headless.set_recorder(Mixer::new(options.tone, SAMPLE_RATE), wav);
+    }
+    if let Some(path) = &options.capture {
+        headless.set_capture(Capture::create(Path::new(path), SCALE, options.palette)?);
+    }
+
+    let result = headless.run(frames);
+    headless.finish()?;
+    if let Some(path) = &options.screenshot {
+        let frame_buffer = headless.chip8().get_frame_buffer();
+        image::save(Path::new(path), frame_buffer, SCALE, &options.palette)?;
+    }
+    check_crash(result, headless.chip8(), symbols)
+}
+
+/// Prints a crash report if the program stopped because of a fault.
+fn check_crash(result: Result<()>, chip8: &Chip8, symbols: &Symbols) -> Result<()> {
+    if let Err(err) = &result {
+        if let Some(fault) = err.downcast_ref::<Fault>() {
+            eprintln!("{}", report::crash_report(chip8, fault, symbols));
+        }
+    }
+
+    result
+}
+
+/// Reads the symbol file given with `--symbols`, or the one next to the ROM
+/// if there is one.
+fn load_symbols(options: &Options) -> Result<Symbols> {
+    let path = match (&options.symbols, &options.rom_path) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, Some(rom_path)) => Path::new(rom_path).with_extension("sym"),
+        (None, None) => return Ok(Symbols::new()),
+    };
+
+    if options.symbols.is_none() && !path.exists() {
+        return Ok(Symbols::new());
+    }
+
+    let text = std::fs::read_to_string(&path).map_err(|e| {
+        Error::msg(format!(
+            "Could not read '{}': {e}. Exiting.",
+            path.display()
+        ))
+    })?;
+    Ok(Symbols::parse(&text)?)
+}
+
+/// Writes the control-flow graph of a ROM, as DOT or JSON depending on the
+/// extension of `path`.
+fn export_cfg(rom_path: &str, path: &str, load_addr: u16) -> Result<()> {
+    let rom = octo::read_rom(Path::new(rom_path))
+        .map_err(|e| Error::msg(format!("Could not read '{rom_path}': {e}. Exiting.")))?;
+    let analysis = Analysis::new(&rom, load_addr);
+    let mut output = BufWriter::new(File::create(path)?);
+
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    if matches!(extension.as_deref(), Some("dot" | "gv")) {
+        analysis.write_dot(output)?;
+    } else {
+        serde_json::to_writer_pretty(&mut output, &analysis.to_json())?;
+        output.flush()?;
+    }
 
     Ok(())
 }
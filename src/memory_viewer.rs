@@ -0,0 +1,354 @@
+#![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_possible_wrap)]
+
+use super::font;
+use super::sdl2_wrapper::Sdl2Wrapper;
+
+use chipper::chip::{Chip8, MemAccess, OpCode};
+use chipper::symbols::Symbols;
+
+use anyhow::Error;
+use anyhow::Result;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::convert::TryFrom;
+use std::ops::Range;
+
+const SCALE: u32 = 2;
+const CELL_WIDTH: u32 = font::ADVANCE * SCALE;
+const CELL_HEIGHT: u32 = font::LINE_HEIGHT * SCALE;
+const BYTES_PER_ROW: usize = 16;
+/// Number of rows of memory visible at once.
+const ROWS: usize = 40;
+/// Column at which the register panel starts.
+const PANEL: u32 = 58;
+const COLUMNS: u32 = PANEL + 16;
+/// Room left for a label next to a stack entry.
+const LABEL_LEN: usize = 8;
+/// Number of editable registers: V0 to VF, I, PC, DT and ST.
+const REGISTERS: usize = 20;
+/// How much the highlight of a written byte fades per redraw.
+const WRITE_DECAY: u8 = 12;
+
+const BACKGROUND: Color = Color::RGB(0x10, 0x10, 0x18);
+const TEXT: Color = Color::RGB(0xAA, 0xAA, 0xAA);
+const DIM: Color = Color::RGB(0x5A, 0x5A, 0x64);
+const PC: Color = Color::RGB(0x00, 0x6E, 0x00);
+const INDEX: Color = Color::RGB(0x00, 0x3C, 0x8C);
+const SPRITE: Color = Color::RGB(0xF0, 0xC8, 0x3C);
+const CURSOR: Color = Color::RGB(0xC8, 0xC8, 0xC8);
+
+/// A second window showing a hex dump of all of memory next to the registers.
+///
+/// Memory and registers can be edited while the emulator is paused, by moving
+/// the cursor to them and typing hexadecimal digits, which are shifted in from
+/// the right.
+pub struct MemoryViewer {
+    canvas: Canvas<Window>,
+    address: usize,
+    register: usize,
+    in_registers: bool,
+    follow_pc: bool,
+    top: usize,
+    sprite: Option<Range<u16>>,
+    writes: Vec<u8>,
+    symbols: Symbols,
+}
+
+impl MemoryViewer {
+    pub fn new(sdl: &Sdl2Wrapper) -> Result<Self> {
+        let canvas = sdl.create_window(
+            "Chipper: Memory",
+            COLUMNS * CELL_WIDTH,
+            (ROWS as u32 + 4) * CELL_HEIGHT,
+        )?;
+
+        Ok(Self {
+            canvas,
+            address: 0,
+            register: 0,
+            in_registers: false,
+            follow_pc: true,
+            top: 0,
+            sprite: None,
+            writes: Vec::new(),
+            symbols: Symbols::new(),
+        })
+    }
+
+    /// Labels the return addresses on the stack.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    /// Records the memory accessed by the instruction about to be executed.
+    pub fn observe(&mut self, chip8: &Chip8) {
+        let op = chip8
+            .peek_word(chip8.program_counter())
+            .ok()
+            .and_then(|word| OpCode::try_from(word).ok());
+
+        match op.and_then(|op| op.memory_access(chip8.index())) {
+            Some((MemAccess::Read, range)) if matches!(op, Some(OpCode::Draw(..))) => {
+                self.sprite = Some(range);
+            }
+            Some((MemAccess::Write, range)) => {
+                self.writes.resize(chip8.memory_size(), 0);
+                for addr in range {
+                    if let Some(heat) = self.writes.get_mut(addr as usize) {
+                        *heat = u8::MAX;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_key(&mut self, chip8: &mut Chip8, scancode: Scancode, paused: bool) {
+        let size = chip8.memory_size();
+        match scancode {
+            Scancode::Tab => self.in_registers = !self.in_registers,
+            Scancode::Home => {
+                self.address = chip8.program_counter() as usize;
+                self.follow_pc = true;
+            }
+            Scancode::Up if self.in_registers => self.register = self.register.saturating_sub(1),
+            Scancode::Down if self.in_registers => {
+                self.register = (self.register + 1).min(REGISTERS - 1);
+            }
+            Scancode::Up => self.move_cursor(-(BYTES_PER_ROW as isize), size),
+            Scancode::Down => self.move_cursor(BYTES_PER_ROW as isize, size),
+            Scancode::Left => self.move_cursor(-1, size),
+            Scancode::Right => self.move_cursor(1, size),
+            Scancode::PageUp => self.move_cursor(-((BYTES_PER_ROW * ROWS) as isize), size),
+            Scancode::PageDown => self.move_cursor((BYTES_PER_ROW * ROWS) as isize, size),
+            _ => {
+                if let Some(digit) = Self::hex_digit(scancode).filter(|_| paused) {
+                    self.edit(chip8, digit);
+                }
+            }
+        }
+    }
+
+    pub fn draw(&mut self, chip8: &Chip8) -> Result<()> {
+        let size = chip8.memory_size();
+        let rows = size.div_ceil(BYTES_PER_ROW);
+        let pc = chip8.program_counter() as usize;
+        let index = chip8.index() as usize;
+
+        self.writes.resize(size, 0);
+        for heat in &mut self.writes {
+            *heat = heat.saturating_sub(WRITE_DECAY);
+        }
+
+        let focus = if self.follow_pc { pc } else { self.address };
+        let row = focus / BYTES_PER_ROW;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + ROWS {
+            self.top = row + 1 - ROWS;
+        }
+        self.top = self.top.min(rows.saturating_sub(ROWS));
+
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+
+        self.text(
+            &format!("MEMORY  {size} BYTES  PC {pc:04X}  I {index:04X}"),
+            (0, 0),
+            TEXT,
+        )?;
+
+        for line in 0..ROWS.min(rows) {
+            let start = (self.top + line) * BYTES_PER_ROW;
+            let y = line as u32 + 2;
+            self.text(&format!("{start:04X}"), (0, y), DIM)?;
+
+            for offset in 0..BYTES_PER_ROW.min(size - start) {
+                let addr = start + offset;
+                let x = 6 + offset as u32 * 3;
+
+                let cursor = !self.in_registers && addr == self.address;
+                let heat = self.writes[addr];
+                let background = if cursor {
+                    Some(CURSOR)
+                } else if addr == pc || addr == pc + 1 {
+                    Some(PC)
+                } else if addr == index {
+                    Some(INDEX)
+                } else if heat > 0 {
+                    Some(Color::RGB(heat, 0, 0))
+                } else {
+                    None
+                };
+
+                if let Some(color) = background {
+                    self.highlight((x, y), 2, color)?;
+                }
+
+                let in_sprite = self
+                    .sprite
+                    .as_ref()
+                    .is_some_and(|sprite| sprite.contains(&(addr as u16)));
+                let color = if cursor {
+                    BACKGROUND
+                } else if in_sprite {
+                    SPRITE
+                } else {
+                    TEXT
+                };
+                let byte = chip8.read_byte(addr as u16).unwrap_or(0);
+                self.text(&format!("{byte:02X}"), (x, y), color)?;
+            }
+        }
+
+        self.draw_registers(chip8)?;
+        self.text(
+            "F2 CLOSE  TAB SWITCH  ARROWS MOVE  HOME PC  0-F EDIT WHILE PAUSED",
+            (0, ROWS as u32 + 3),
+            DIM,
+        )?;
+
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn draw_registers(&mut self, chip8: &Chip8) -> Result<()> {
+        for register in 0..REGISTERS {
+            let (name, value) = Self::register_text(chip8, register);
+            let y = register as u32 + 2;
+            let cursor = self.in_registers && register == self.register;
+
+            self.text(&name, (PANEL, y), DIM)?;
+            if cursor {
+                self.highlight((PANEL + 3, y), value.len() as u32, CURSOR)?;
+            }
+            self.text(
+                &value,
+                (PANEL + 3, y),
+                if cursor { BACKGROUND } else { TEXT },
+            )?;
+        }
+
+        let y = REGISTERS as u32 + 3;
+        self.text(
+            &format!("SP {}", chip8.return_addresses().len()),
+            (PANEL, y),
+            DIM,
+        )?;
+        // As many return addresses as fit above the help line
+        for (i, addr) in chip8
+            .return_addresses()
+            .take(ROWS - REGISTERS - 1)
+            .enumerate()
+        {
+            let label: String = self
+                .symbols
+                .label(addr)
+                .unwrap_or_default()
+                .chars()
+                .take(LABEL_LEN)
+                .collect();
+            self.text(
+                &format!("{addr:04X} {label}"),
+                (PANEL + 3, y + 1 + i as u32),
+                TEXT,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn register_text(chip8: &Chip8, register: usize) -> (String, String) {
+        match register {
+            0..=15 => (
+                format!("V{register:X}"),
+                format!("{:02X}", chip8.register(register as u8)),
+            ),
+            16 => (String::from("I"), format!("{:04X}", chip8.index())),
+            17 => (
+                String::from("PC"),
+                format!("{:04X}", chip8.program_counter()),
+            ),
+            18 => (String::from("DT"), format!("{:02X}", chip8.delay_timer())),
+            _ => (String::from("ST"), format!("{:02X}", chip8.sound_timer())),
+        }
+    }
+    fn edit(&mut self, chip8: &mut Chip8, digit: u8) {
+        if !self.in_registers {
+            let addr = self.address as u16;
+            if let Ok(byte) = chip8.read_byte(addr) {
+                let _ = chip8.write_byte(addr, (byte << 4) | digit);
+            }
+            return;
+        }
+
+        let shift = |value: u16| (value << 4) | u16::from(digit);
+        match self.register {
+            x @ 0..=15 => {
+                let x = x as u8;
+                chip8.set_register(x, (chip8.register(x) << 4) | digit);
+            }
+            16 => chip8.set_index(shift(chip8.index())),
+            17 => chip8.set_program_counter(shift(chip8.program_counter())),
+            18 => chip8.set_delay_timer((chip8.delay_timer() << 4) | digit),
+            _ => chip8.set_sound_timer((chip8.sound_timer() << 4) | digit),
+        }
+    }
+
+    fn move_cursor(&mut self, delta: isize, size: usize) {
+        self.follow_pc = false;
+        self.address = self
+            .address
+            .saturating_add_signed(delta)
+            .min(size.saturating_sub(1));
+    }
+
+    fn hex_digit(scancode: Scancode) -> Option<u8> {
+        Some(match scancode {
+            Scancode::Num0 | Scancode::Kp0 => 0x0,
+            Scancode::Num1 | Scancode::Kp1 => 0x1,
+            Scancode::Num2 | Scancode::Kp2 => 0x2,
+            Scancode::Num3 | Scancode::Kp3 => 0x3,
+            Scancode::Num4 | Scancode::Kp4 => 0x4,
+            Scancode::Num5 | Scancode::Kp5 => 0x5,
+            Scancode::Num6 | Scancode::Kp6 => 0x6,
+            Scancode::Num7 | Scancode::Kp7 => 0x7,
+            Scancode::Num8 | Scancode::Kp8 => 0x8,
+            Scancode::Num9 | Scancode::Kp9 => 0x9,
+            Scancode::A => 0xA,
+            Scancode::B => 0xB,
+            Scancode::C => 0xC,
+            Scancode::D => 0xD,
+            Scancode::E => 0xE,
+            Scancode::F => 0xF,
+            _ => return None,
+        })
+    }
+
+    /// Draws `text` starting at the given cell.
+    fn text(&mut self, text: &str, (column, row): (u32, u32), color: Color) -> Result<()> {
+        let position = ((column * CELL_WIDTH) as i32, (row * CELL_HEIGHT) as i32);
+        font::draw_text(&mut self.canvas, text, position, SCALE, color)
+    }
+
+    /// Fills the background of `len` cells starting at the given cell.
+    fn highlight(&mut self, (column, row): (u32, u32), len: u32, color: Color) -> Result<()> {
+        let rect = Rect::new(
+            (column * CELL_WIDTH) as i32 - SCALE as i32,
+            (row * CELL_HEIGHT) as i32 - SCALE as i32,
+            len * CELL_WIDTH + SCALE,
+            CELL_HEIGHT,
+        );
+
+        self.canvas.set_draw_color(color);
+        self.canvas.fill_rect(rect).map_err(Error::msg)
+    }
+}
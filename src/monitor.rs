@@ -0,0 +1,527 @@
+//! A text monitor for inspecting and driving the machine, in the spirit of
+//! classic machine language monitors.
+//!
+//! Addresses and values are hexadecimal, with an optional `0x` prefix, while
+//! counts are decimal. With symbols loaded, labels can be used wherever an
+//! address is expected, and are shown in disassembly. Instructions executed
+//! from the monitor run without ticking the timers.
+
+use crate::chip::{Chip8, Flow, OpCode};
+use crate::debug;
+use crate::debug::Breakpoints;
+use crate::symbols::Symbols;
+
+use std::convert::TryFrom;
+use std::io;
+use std::io::Write;
+
+const HELP: &str = "\
+r                  show registers
+r <reg> <value>    set V0-VF, I, PC, DT or ST
+m [addr] [len]     dump memory
+e <addr> <byte>..  write bytes to memory
+d [addr] [count]   disassemble instructions
+b [addr]           list breakpoints, or add one
+bc [addr]          clear a breakpoint, or all of them
+s [n]              step n instructions
+u                  run until the current subroutine returns
+bt                 show the call stack
+k [key]..          hold down keypad keys (0-F), none to release all
+save <file>        save the machine state
+load <file>        load a machine state
+g                  leave the monitor and continue
+x                  leave the monitor, staying paused
+q                  quit";
+
+/// Dump length and disassembly count when none is given.
+const DEFAULT_LEN: u16 = 0x80;
+const DEFAULT_COUNT: u16 = 16;
+/// The most instructions `u` executes before giving up.
+const MAX_CYCLES: usize = 1_000_000;
+
+/// What the frontend should do after a command.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MonitorAction {
+    /// Keep reading commands.
+    Stay,
+    Continue,
+    Leave,
+    Quit,
+}
+
+/// The state of a monitor session: where dumps and disassembly continue from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Monitor {
+    dump_addr: Option<u16>,
+    disassemble_addr: Option<u16>,
+    symbols: Symbols,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Runs a single command line, writing its output to `out`. Mistakes in
+    /// the command are reported to `out` as well.
+    pub fn execute(
+        &mut self,
+        line: &str,
+        chip8: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+        mut out: impl Write,
+    ) -> io::Result<MonitorAction> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(MonitorAction::Stay);
+        };
+        let args: Vec<&str> = words.collect();
+
+        let result = match command {
+            "r" => self.registers(&args, chip8, &mut out),
+            "m" => self.dump(&args, chip8, &mut out),
+            "e" => self.edit(&args, chip8),
+            "d" => self.disassemble(&args, chip8, breakpoints, &mut out),
+            "b" => self.breakpoint(&args, breakpoints, &mut out),
+            "bc" => {
+                if let Some(addr) = args.first() {
+                    self.parse_addr(addr).and_then(|addr| {
+                        breakpoints
+                            .remove_breakpoint(addr)
+                            .then_some(())
+                            .ok_or_else(|| {
+                                format!("No breakpoint at {}", self.symbols.format(addr))
+                            })
+                    })
+                } else {
+                    breakpoints.clear();
+                    Ok(())
+                }
+            }
+            "s" => args
+                .first()
+                .map_or(Ok(1), |count| {
+                    count
+                        .parse()
+                        .map_err(|_| format!("'{count}' is not a number"))
+                })
+                .and_then(|count| self.run(chip8, breakpoints, &mut out, count, |_| false)),
+            "u" => {
+                let depth = chip8.return_addresses().len();
+                if depth == 0 {
+                    Err(String::from("Not inside a subroutine"))
+                } else {
+                    self.run(chip8, breakpoints, &mut out, MAX_CYCLES, |chip8| {
+                        chip8.return_addresses().len() < depth
+                    })
+                }
+            }
+            "bt" => {
+                let stack = chip8.snapshot().stack;
+                debug::backtrace(chip8.program_counter(), &stack, &self.symbols)
+                    .iter()
+                    .try_for_each(|frame| writeln!(out, "{frame}"))
+                    .map_err(|e| e.to_string())
+            }
+            "k" => Self::keys(&args, chip8, &mut out),
+            "save" => Self::file(&args).and_then(|path| {
+                std::fs::write(path, chip8.save_state())
+                    .map_err(|e| format!("Could not write '{path}': {e}"))
+            }),
+            "load" => Self::file(&args).and_then(|path| {
+                let state =
+                    std::fs::read(path).map_err(|e| format!("Could not read '{path}': {e}"))?;
+                chip8.load_state(&state).map_err(|e| e.to_string())
+            }),
+            "h" | "?" => writeln!(out, "{HELP}").map_err(|e| e.to_string()),
+            "g" => return Ok(MonitorAction::Continue),
+            "x" => return Ok(MonitorAction::Leave),
+            "q" => return Ok(MonitorAction::Quit),
+            _ => Err(format!("Unknown command '{command}', type h for help")),
+        };
+
+        if let Err(message) = result {
+            writeln!(out, "{message}")?;
+        }
+
+        Ok(MonitorAction::Stay)
+    }
+
+    fn registers(
+        &self,
+        args: &[&str],
+        chip8: &mut Chip8,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        if let [register, value] = args {
+            let value = self.parse_addr(value)?;
+            let byte = u8::try_from(value).map_err(|_| format!("{register} only holds a byte"));
+            match register.to_ascii_uppercase().as_str() {
+                "I" => chip8.set_index(value),
+                "PC" => chip8.set_program_counter(value),
+                "DT" => chip8.set_delay_timer(byte?),
+                "ST" => chip8.set_sound_timer(byte?),
+                name => {
+                    let x = name
+                        .strip_prefix('V')
+                        .and_then(|x| u8::from_str_radix(x, 16).ok())
+                        .filter(|&x| x < 16)
+                        .ok_or_else(|| format!("Unknown register '{register}'"))?;
+                    chip8.set_register(x, byte?);
+                }
+            }
+        } else if !args.is_empty() {
+            return Err(String::from("Usage: r [<reg> <value>]"));
+        }
+
+        let snapshot = chip8.snapshot();
+        let v: Vec<String> = (0..)
+            .zip(&snapshot.v)
+            .map(|(x, value): (u8, _)| format!("V{x:X}={value:02X}"))
+            .collect();
+        writeln!(
+            out,
+            "PC={:03X} I={:03X} DT={:02X} ST={:02X} SP={:X}\n{}\n{}",
+            snapshot.program_counter,
+            snapshot.index,
+            snapshot.delay_timer,
+            snapshot.sound_timer,
+            snapshot.stack.len(),
+            v[..8].join(" "),
+            v[8..].join(" ")
+        )
+        .map_err(|e| e.to_string())?;
+
+        if snapshot.stack.is_empty() {
+            return Ok(());
+        }
+        let stack: Vec<String> = snapshot
+            .stack
+            .iter()
+            .rev()
+            .map(|&addr| self.symbols.format(addr))
+            .collect();
+        writeln!(out, "Stack: {}", stack.join(" ")).map_err(|e| e.to_string())
+    }
+
+    fn dump(&mut self, args: &[&str], chip8: &Chip8, out: &mut impl Write) -> Result<(), String> {
+        let start = match args.first() {
+            Some(addr) => self.parse_addr(addr)?,
+            None => self.dump_addr.unwrap_or(chip8.index()),
+        };
+        let len = args
+            .get(1)
+            .map_or(Ok(DEFAULT_LEN), |len| self.parse_addr(len))?;
+
+        let end = start.saturating_add(len);
+        for row in (start..end).step_by(16) {
+            let bytes: Vec<u8> = (row..end.min(row.saturating_add(16)))
+                .map_while(|addr| chip8.read_byte(addr).ok())
+                .collect();
+            if bytes.is_empty() {
+                break;
+            }
+
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() {
+                        char::from(byte)
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(out, "{row:04X}  {:<47}  {text}", hex.join(" ")).map_err(|e| e.to_string())?;
+        }
+
+        self.dump_addr = Some(end);
+        Ok(())
+    }
+
+    fn edit(&self, args: &[&str], chip8: &mut Chip8) -> Result<(), String> {
+        let [addr, bytes @ ..] = args else {
+            return Err(String::from("Usage: e <addr> <byte>.."));
+        };
+        if bytes.is_empty() {
+            return Err(String::from("Usage: e <addr> <byte>.."));
+        }
+
+        let addr = self.parse_addr(addr)?;
+        for (offset, byte) in (0..).zip(bytes) {
+            let byte = u8::from_str_radix(byte.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("'{byte}' is not a byte"))?;
+            addr.checked_add(offset)
+                .and_then(|addr| chip8.write_byte(addr, byte).ok())
+                .ok_or_else(|| String::from("Out of bounds"))?;
+        }
+
+        Ok(())
+    }
+
+    fn disassemble(
+        &mut self,
+        args: &[&str],
+        chip8: &Chip8,
+        breakpoints: &Breakpoints,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let mut addr = match args.first() {
+            Some(addr) => self.parse_addr(addr)?,
+            None => self.disassemble_addr.unwrap_or(chip8.program_counter()),
+        };
+        let count = match args.get(1) {
+            Some(count) => count
+                .parse()
+                .map_err(|_| format!("'{count}' is not a number"))?,
+            None => DEFAULT_COUNT,
+        };
+
+        for _ in 0..count {
+            let Ok(word) = chip8.peek_word(addr) else {
+                break;
+            };
+
+            let marker = match (
+                addr == chip8.program_counter(),
+                breakpoints.is_breakpoint(addr),
+            ) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            if let Some((label, 0)) = self.symbols.lookup(addr) {
+                writeln!(out, "{label}:").map_err(|e| e.to_string())?;
+            }
+            let op = OpCode::try_from(word)
+                .map_or_else(|_| String::from("???"), |op| self.symbols.disassemble(op));
+            writeln!(out, "{marker} {addr:03X}  {word:04X}  {op}").map_err(|e| e.to_string())?;
+            addr = addr.wrapping_add(2);
+        }
+
+        self.disassemble_addr = Some(addr);
+        Ok(())
+    }
+
+    fn breakpoint(
+        &self,
+        args: &[&str],
+        breakpoints: &mut Breakpoints,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        if let Some(addr) = args.first() {
+            breakpoints.add_breakpoint(self.parse_addr(addr)?);
+            return Ok(());
+        }
+
+        let list: Vec<String> = breakpoints
+            .breakpoints()
+            .map(|addr| match self.symbols.label(addr) {
+                Some(label) => format!("{addr:03X} ({label})"),
+                None => format!("{addr:03X}"),
+            })
+            .collect();
+        if list.is_empty() {
+            writeln!(out, "No breakpoints")
+        } else {
+            writeln!(out, "{}", list.join(" "))
+        }
+        .map_err(|e| e.to_string())
+    }
+
+    /// Executes up to `count` instructions, stopping early at breakpoints,
+    /// errors, or once `done` returns `true`.
+    fn run(
+        &self,
+        chip8: &mut Chip8,
+        breakpoints: &Breakpoints,
+        out: &mut impl Write,
+        count: usize,
+        done: impl Fn(&Chip8) -> bool,
+    ) -> Result<(), String> {
+        for i in 0..count {
+            let pc = chip8.program_counter();
+            if i > 0 && breakpoints.is_breakpoint(pc) {
+                writeln!(out, "Breakpoint at {}", self.symbols.format(pc))
+                    .map_err(|e| e.to_string())?;
+                break;
+            }
+
+            match chip8.cycle() {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Warning(fault)) => {
+                    writeln!(out, "Warning: {}", self.symbols.describe(&fault))
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(Flow::Pause(fault)) => {
+                    return Err(format!("Paused: {}", self.symbols.describe(&fault)))
+                }
+                Err(fault) => return Err(self.symbols.describe(&fault)),
+            }
+
+            if done(chip8) {
+                break;
+            }
+        }
+
+        let pc = chip8.program_counter();
+        let op = chip8
+            .peek_word(pc)
+            .ok()
+            .and_then(|word| OpCode::try_from(word).ok())
+            .map_or_else(|| String::from("???"), |op| self.symbols.disassemble(op));
+        let pc = self
+            .symbols
+            .label(pc)
+            .unwrap_or_else(|| format!("{pc:03X}"));
+        writeln!(out, "=> {pc}  {op}").map_err(|e| e.to_string())
+    }
+
+    fn keys(args: &[&str], chip8: &mut Chip8, out: &mut impl Write) -> Result<(), String> {
+        let mut keys = [false; 16];
+        for key in args {
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| format!("'{key}' is not a key"))?;
+            keys[key as usize] = true;
+            // Also completes an instruction waiting for a key press
+            chip8.press_key(Some(key));
+        }
+        chip8.set_keys(keys);
+
+        let held: Vec<String> = (0..16u8)
+            .filter(|&key| chip8.keys()[key as usize])
+            .map(|key| format!("{key:X}"))
+            .collect();
+        writeln!(
+            out,
+            "Keys held: {}",
+            if held.is_empty() {
+                String::from("none")
+            } else {
+                held.join(" ")
+            }
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /// Parses a label, or a hexadecimal number with an optional `0x` prefix.
+    fn parse_addr(&self, addr: &str) -> Result<u16, String> {
+        if let Some(addr) = self.symbols.address(addr) {
+            return Ok(addr);
+        }
+
+        let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(digits, 16)
+            .map_err(|_| format!("'{addr}' is neither a label nor a hexadecimal number"))
+    }
+
+    fn file<'a>(args: &[&'a str]) -> Result<&'a str, String> {
+        match args {
+            [path] => Ok(path),
+            _ => Err(String::from("Expected a file name")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(
+        monitor: &mut Monitor,
+        line: &str,
+        chip8: &mut Chip8,
+        breakpoints: &mut Breakpoints,
+    ) -> String {
+        let mut out = Vec::new();
+        assert_eq!(
+            monitor.execute(line, chip8, breakpoints, &mut out).unwrap(),
+            MonitorAction::Stay
+        );
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn commands() {
+        let mut monitor = Monitor::new();
+        let mut chip8 = Chip8::new();
+        let mut breakpoints = Breakpoints::new();
+
+        run(
+            &mut monitor,
+            "e 200 22 06 61 01 00 00 62 02 00 EE",
+            &mut chip8,
+            &mut breakpoints,
+        );
+        assert_eq!(
+            run(&mut monitor, "m 200 4", &mut chip8, &mut breakpoints),
+            format!("0200  {:<47}  \".a.\n", "22 06 61 01")
+        );
+        assert_eq!(
+            run(&mut monitor, "d 200 2", &mut chip8, &mut breakpoints),
+            "=> 200  2206  CALL 0x206\n   202  6101  LD   V1, 0x01\n"
+        );
+
+        run(&mut monitor, "r v3 1f", &mut chip8, &mut breakpoints);
+        assert_eq!(chip8.register(3), 0x1F);
+
+        run(&mut monitor, "s", &mut chip8, &mut breakpoints);
+        assert_eq!(chip8.program_counter(), 0x206);
+        assert_eq!(
+            run(&mut monitor, "u", &mut chip8, &mut breakpoints),
+            "=> 202  LD   V1, 0x01\n"
+        );
+        assert_eq!(chip8.register(2), 0x02);
+
+        run(&mut monitor, "b 204", &mut chip8, &mut breakpoints);
+        assert!(breakpoints.is_breakpoint(0x204));
+        run(&mut monitor, "s 5", &mut chip8, &mut breakpoints);
+        assert_eq!(chip8.program_counter(), 0x204);
+
+        run(&mut monitor, "k 5 a", &mut chip8, &mut breakpoints);
+        assert!(chip8.keys()[0xA]);
+
+        let mut out = Vec::new();
+        assert_eq!(
+            monitor
+                .execute("g", &mut chip8, &mut breakpoints, &mut out)
+                .unwrap(),
+            MonitorAction::Continue
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let mut monitor = Monitor::new();
+        monitor.set_symbols(Symbols::parse("main = 200\nsub = 204").unwrap());
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE])
+            .unwrap();
+        let mut breakpoints = Breakpoints::new();
+
+        assert_eq!(
+            run(&mut monitor, "d main 1", &mut chip8, &mut breakpoints),
+            "main:\n=> 200  2204  CALL sub\n"
+        );
+
+        run(&mut monitor, "b sub", &mut chip8, &mut breakpoints);
+        assert!(breakpoints.is_breakpoint(0x204));
+        assert_eq!(
+            run(&mut monitor, "s", &mut chip8, &mut breakpoints),
+            "=> sub  RET\n"
+        );
+        assert!(run(&mut monitor, "r", &mut chip8, &mut breakpoints).ends_with("Stack: main+0x2\n"));
+        assert_eq!(
+            run(&mut monitor, "bt", &mut chip8, &mut breakpoints),
+            "#0  0x204  sub\n#1  0x200  main\n"
+        );
+    }
+}
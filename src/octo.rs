@@ -0,0 +1,372 @@
+//! Octo cartridges and options.
+//!
+//! An Octo cartridge is a GIF image with a program hidden in it: the lowest
+//! two bits of the palette index of every pixel, frame after frame, make up
+//! a byte stream four pixels per byte, most significant bits first. It
+//! starts with the length of the payload as a 32 bit big endian number,
+//! followed by the payload itself, a JSON object such as
+//! `{"program": "...", "options": {...}}`.
+//!
+//! The program is Octo source code, which is assembled to run it, while the
+//! options describe how: speed, quirks and colors. Octo and other emulators
+//! also keep these options in JSON files of their own.
+
+mod assembler;
+
+pub use assembler::{assemble, AssembleError, PROGRAM_START};
+
+use crate::chip::Settings;
+use crate::image::{parse_color, Palette};
+
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+/// Octo counts the speed in instructions per 60 Hz frame.
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// Tells whether an option has the value matching what chipper does.
+type IsDefault = fn(&Value) -> bool;
+
+/// Options chipper cannot follow, with the value that matches how it runs
+/// programs anyway.
+const UNSUPPORTED: [(&str, IsDefault); 4] = [
+    // Chipper sets vF before writing the result, as Octo does with the quirk
+    ("vfOrderQuirks", |value| value == &json!(true)),
+    ("logicQuirks", |value| value == &json!(false)),
+    ("vBlankQuirks", |value| value == &json!(false)),
+    ("screenRotation", |value| value.as_u64() == Some(0)),
+];
+
+/// The options of an Octo program. Those chipper can follow are read into
+/// fields, and missing ones keep the current settings. The others are kept
+/// as they are, so they can be written back.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OctoOptions {
+    /// Instructions per frame.
+    pub tickrate: Option<u32>,
+    /// Shifts ignore `vy`.
+    pub shift_quirks: Option<bool>,
+    /// Loading and storing registers leaves `i` unchanged.
+    pub load_store_quirks: Option<bool>,
+    /// `jump0` adds the register named by the top digit of the address.
+    pub jump_quirks: Option<bool>,
+    /// Sprites are clipped at the bottom of the screen instead of wrapping.
+    pub clip_quirks: Option<bool>,
+    pub background_color: Option<[u8; 3]>,
+    pub fill_color: Option<[u8; 3]>,
+    /// The options chipper has no use for.
+    pub other: Map<String, Value>,
+}
+
+impl OctoOptions {
+    /// Reads the options from an Octo options object.
+    pub fn from_json(json: &Value) -> Self {
+        let flag = |name: &str| json[name].as_bool();
+        let color = |name: &str| json[name].as_str().and_then(parse_color);
+        let mut other = json.as_object().cloned().unwrap_or_default();
+        for name in &Self::FIELDS {
+            other.remove(*name);
+        }
+
+        Self {
+            tickrate: json["tickrate"]
+                .as_u64()
+                .and_then(|rate| u32::try_from(rate).ok()),
+            shift_quirks: flag("shiftQuirks"),
+            load_store_quirks: flag("loadStoreQuirks"),
+            jump_quirks: flag("jumpQuirks"),
+            clip_quirks: flag("clipQuirks"),
+            background_color: color("backgroundColor"),
+            fill_color: color("fillColor"),
+            other,
+        }
+    }
+
+    /// The names of the options read into fields.
+    const FIELDS: [&'static str; 7] = [
+        "tickrate",
+        "shiftQuirks",
+        "loadStoreQuirks",
+        "jumpQuirks",
+        "clipQuirks",
+        "backgroundColor",
+        "fillColor",
+    ];
+
+    /// Returns the options as an Octo options object.
+    pub fn to_json(&self) -> Value {
+        let color = |[red, green, blue]: [u8; 3]| format!("#{red:02X}{green:02X}{blue:02X}");
+        let fields = [
+            ("tickrate", self.tickrate.map(Value::from)),
+            ("shiftQuirks", self.shift_quirks.map(Value::from)),
+            ("loadStoreQuirks", self.load_store_quirks.map(Value::from)),
+            ("jumpQuirks", self.jump_quirks.map(Value::from)),
+            ("clipQuirks", self.clip_quirks.map(Value::from)),
+            (
+                "backgroundColor",
+                self.background_color.map(color).map(Value::from),
+            ),
+            ("fillColor", self.fill_color.map(color).map(Value::from)),
+        ];
+
+        let mut json = self.other.clone();
+        for (name, value) in &fields {
+            if let Some(value) = value {
+                json.insert(String::from(*name), value.clone());
+            }
+        }
+
+        Value::Object(json)
+    }
+
+    /// Sets every option chipper follows from the settings and colors it
+    /// runs with, keeping the others.
+    pub fn update(&mut self, settings: &Settings, palette: &Palette) {
+        let rate = (u32::from(settings.cpu_freq) + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND;
+        self.tickrate = Some(rate.max(1));
+        self.shift_quirks = Some(!settings.shift_quirk);
+        self.load_store_quirks = Some(!settings.load_store_quirk);
+        self.jump_quirks = Some(settings.jump_quirk);
+        self.clip_quirks = Some(!settings.vertical_wrap);
+        self.background_color = Some(palette.background);
+        self.fill_color = Some(palette.foreground);
+    }
+
+    /// Returns the names of the options asking for something chipper does
+    /// not do.
+    pub fn unsupported(&self) -> Vec<&'static str> {
+        UNSUPPORTED
+            .iter()
+            .filter(|(name, is_default)| {
+                self.other
+                    .get(*name)
+                    .is_some_and(|value| !is_default(value))
+            })
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Changes the settings the options are given for.
+    pub fn apply(&self, settings: &mut Settings) {
+        if let Some(rate) = self.tickrate {
+            let freq = rate.saturating_mul(FRAMES_PER_SECOND);
+            settings.cpu_freq = u16::try_from(freq).unwrap_or(u16::MAX).max(1);
+        }
+        // Octo names the behavior of SUPER-CHIP as the quirk, chipper that
+        // of the VIP
+        if let Some(quirk) = self.shift_quirks {
+            settings.shift_quirk = !quirk;
+        }
+        if let Some(quirk) = self.load_store_quirks {
+            settings.load_store_quirk = !quirk;
+        }
+        if let Some(quirk) = self.jump_quirks {
+            settings.jump_quirk = quirk;
+        }
+        if let Some(quirk) = self.clip_quirks {
+            settings.vertical_wrap = !quirk;
+        }
+    }
+
+    /// Returns the colors, if both are given.
+    pub fn palette(&self) -> Option<Palette> {
+        Some(Palette {
+            background: self.background_color?,
+            foreground: self.fill_color?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cartridge {
+    /// The Octo source code of the program.
+    pub program: String,
+    pub options: OctoOptions,
+}
+
+impl Cartridge {
+    /// Extracts the program and options from the image of a cartridge.
+    pub fn decode(r: impl Read) -> io::Result<Self> {
+        let payload = read_payload(r)?;
+        let json: Value = serde_json::from_slice(&payload).map_err(invalid)?;
+        let program = json["program"]
+            .as_str()
+            .ok_or_else(|| invalid("the cartridge holds no program"))?;
+
+        Ok(Self {
+            program: String::from(program),
+            options: OctoOptions::from_json(&json["options"]),
+        })
+    }
+
+    /// Assembles the program into a ROM loaded at `PROGRAM_START`.
+    pub fn assemble(&self) -> Result<Vec<u8>, AssembleError> {
+        assemble(&self.program)
+    }
+}
+
+/// Returns whether `path` names an Octo cartridge rather than a ROM.
+pub fn is_cartridge(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+}
+
+/// Reads the ROM at `path`, assembling the program of a cartridge.
+pub fn read_rom(path: &Path) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    if !is_cartridge(path) {
+        return Ok(bytes);
+    }
+
+    Cartridge::decode(&bytes[..])?.assemble().map_err(invalid)
+}
+
+/// Reads the bytes stored in the pixels of all frames, and returns those
+/// the length at the start counts.
+fn read_payload(r: impl Read) -> io::Result<Vec<u8>> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(r).map_err(invalid)?;
+
+    let mut bytes = Vec::new();
+    let (mut byte, mut bits) = (0u8, 0);
+    while let Some(frame) = decoder.read_next_frame().map_err(invalid)? {
+        for &pixel in frame.buffer.iter() {
+            byte = (byte << 2) | (pixel & 0x3);
+            bits += 2;
+            if bits == 8 {
+                bytes.push(byte);
+                byte = 0;
+                bits = 0;
+            }
+        }
+    }
+
+    let (len, payload) = bytes
+        .split_first_chunk::<4>()
+        .ok_or_else(|| invalid("the cartridge is empty"))?;
+    let len = usize::try_from(u32::from_be_bytes(*len)).map_err(invalid)?;
+    payload
+        .get(..len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| invalid("the cartridge is truncated"))
+}
+
+fn invalid(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    /// Hides `payload` in a GIF the way Octo does.
+    fn cartridge(payload: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(payload.len()).unwrap();
+        let mut pixels = Vec::new();
+        for byte in len.to_be_bytes().iter().chain(payload) {
+            for shift in &[6, 4, 2, 0] {
+                // The upper bits pick the visible color
+                pixels.push(0x4 | (byte >> shift) & 0x3);
+            }
+        }
+        pixels.resize(128 * 64, 0);
+
+        let mut gif = Vec::new();
+        let palette = [0u8; 3 * 8];
+        let mut encoder = gif::Encoder::new(&mut gif, 128, 64, &palette).unwrap();
+        let frame = gif::Frame {
+            width: 128,
+            height: 64,
+            buffer: pixels.into(),
+            ..gif::Frame::default()
+        };
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+        gif
+    }
+
+    #[test]
+    fn options_json() {
+        let json = json!({
+            "tickrate": 7,
+            "clipQuirks": false,
+            "fillColor": "#FFAA00",
+            "vfOrderQuirks": false,
+            "screenRotation": 0,
+            "fontStyle": "octo"
+        });
+        let mut options = OctoOptions::from_json(&json);
+        assert_eq!(options.tickrate, Some(7));
+        assert_eq!(options.shift_quirks, None);
+        assert_eq!(options.unsupported(), ["vfOrderQuirks"]);
+        assert_eq!(options.to_json(), json);
+
+        let mut settings = Settings::for_platform(crate::chip::Platform::Schip);
+        settings.cpu_freq = 600;
+        options.update(&settings, &Palette::new());
+        let json = options.to_json();
+        assert_eq!(json["tickrate"], 10);
+        assert_eq!(json["shiftQuirks"], true);
+        assert_eq!(json["loadStoreQuirks"], true);
+        assert_eq!(json["jumpQuirks"], true);
+        assert_eq!(json["clipQuirks"], true);
+        assert_eq!(json["fillColor"], "#FFFFFF");
+        assert_eq!(json["fontStyle"], "octo");
+
+        let mut restored = Settings::new();
+        OctoOptions::from_json(&json).apply(&mut restored);
+        assert_eq!(
+            restored,
+            Settings {
+                vertical_wrap: false,
+                font: restored.font.clone(),
+                ..settings
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_cartridges() {
+        let payload = json!({
+            "program": ": main\n  loop again\n",
+            "options": {
+                "tickrate": 20,
+                "shiftQuirks": true,
+                "loadStoreQuirks": false,
+                "clipQuirks": true,
+                "backgroundColor": "#996600",
+                "fillColor": "#FFCC00",
+                "screenRotation": 0
+            }
+        });
+        let gif = cartridge(payload.to_string().as_bytes());
+
+        let cart = Cartridge::decode(&gif[..]).unwrap();
+        assert_eq!(cart.program, ": main\n  loop again\n");
+        assert_eq!(cart.assemble().unwrap(), [0x12, 0x00]);
+        assert_eq!(
+            cart.options.palette(),
+            Some(Palette {
+                background: [0x99, 0x66, 0x00],
+                foreground: [0xFF, 0xCC, 0x00],
+            })
+        );
+
+        let mut settings = Settings::new();
+        cart.options.apply(&mut settings);
+        assert_eq!(settings.cpu_freq, 1200);
+        assert!(!settings.shift_quirk);
+        assert!(settings.load_store_quirk);
+        assert!(!settings.vertical_wrap);
+        assert!(!settings.jump_quirk);
+
+        assert!(Cartridge::decode(&cartridge(b"{}")[..]).is_err());
+    }
+}
@@ -0,0 +1,1144 @@
+//! An assembler for the Octo language, to run the programs of cartridges.
+//!
+//! It covers the CHIP-8 instructions, the control structures and the
+//! directives programs rely on: labels, constants, aliases, macros, `:calc`,
+//! `:unpack`, `:next`, `:org` and `:byte`. Names may be used before they are
+//! defined, except in `:calc` expressions. The instructions of SUPER-CHIP
+//! and XO-CHIP, which chipper does not run, are reported as errors.
+//!
+//! As in Octo, the program starts with a jump to the `main` label, left out
+//! when `main` comes first.
+//!
+//! Octo compares with `<`, `>`, `<=` and `>=` by subtracting in vF and
+//! reading the flag left there, which chipper overwrites with the result.
+//! The subtraction goes to vE instead, or to the register aliased as
+//! `compare-temp`, so these comparisons change vE as well as vF.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+/// Where Octo programs are loaded.
+pub const PROGRAM_START: u16 = 0x200;
+
+/// Octo extensions chipper cannot run.
+const UNSUPPORTED: [&str; 15] = [
+    "hires",
+    "lores",
+    "scroll-down",
+    "scroll-up",
+    "scroll-left",
+    "scroll-right",
+    "exit",
+    "saveflags",
+    "loadflags",
+    "plane",
+    "audio",
+    "pitch",
+    "long",
+    "bighex",
+    ":stringmode",
+];
+
+/// A line of Octo source code that could not be assembled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+type Result<T> = std::result::Result<T, AssembleError>;
+
+/// Assembles `source` into a ROM loaded at `PROGRAM_START`.
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let mut assembler = Assembler::new(source);
+    while let Some(token) = assembler.tokens.pop_front() {
+        assembler.line = token.line;
+        assembler.statement(&token.text)?;
+    }
+    assembler.finish()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// Splits the source code into words, leaving out the comments.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (line, text) in (1..).zip(source.lines()) {
+        let code = text.split('#').next().unwrap_or_default();
+        for word in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: String::from(word),
+                line,
+            });
+        }
+    }
+    tokens
+}
+
+/// What to write at an address once a label is defined.
+#[derive(Copy, Clone, Debug)]
+enum Fixup {
+    /// The address in the lower 12 bits of the instruction.
+    Address(u16),
+    /// The address in the two loads of an `:unpack`, after `nibble`.
+    Unpack(u16, u8),
+}
+
+/// A control structure waiting for its end.
+#[derive(Clone, Debug)]
+enum Control {
+    /// The jump over the block of an `if`, or of an `else`.
+    If { jump: u16, line: usize },
+    /// The start of a loop, and the jumps out of it of its `while`s.
+    Loop {
+        start: u16,
+        exits: Vec<u16>,
+        line: usize,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    /// The line of the last token taken.
+    line: usize,
+    /// The bytes from `PROGRAM_START` on, and which of them were written.
+    rom: Vec<u8>,
+    written: Vec<bool>,
+    here: u16,
+    /// Whether the jump to `main` at the start is kept.
+    jump_to_main: bool,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    /// Uses of names not defined yet, with their lines.
+    pending: HashMap<String, Vec<(Fixup, usize)>>,
+    control: Vec<Control>,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        Self {
+            tokens: tokenize(source),
+            line: 1,
+            // Room for the jump to `main`
+            rom: vec![0; 2],
+            written: vec![true; 2],
+            here: PROGRAM_START + 2,
+            jump_to_main: true,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            pending: HashMap::new(),
+            control: Vec::new(),
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        Err(AssembleError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Result<String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("the program ends in the middle of a statement"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<()> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            self.error(format!("expected '{expected}', found '{token}'"))
+        }
+    }
+
+    fn statement(&mut self, token: &str) -> Result<()> {
+        if UNSUPPORTED.contains(&token) {
+            return self.error(format!(
+                "'{token}' is a SUPER-CHIP or XO-CHIP feature, which chipper does not run"
+            ));
+        }
+        if token.starts_with(':') {
+            return self.directive(token);
+        }
+
+        match token {
+            "clear" => self.inst(0x00E0),
+            ";" | "return" => self.inst(0x00EE),
+            "bcd" => self.register_inst(0xF033),
+            "save" => self.memory_inst(0xF055),
+            "load" => self.memory_inst(0xF065),
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.inst(0xD000 | u16::from(x) << 8 | u16::from(y) << 4 | u16::from(n))
+            }
+            "jump" => self.address_inst(0x1000),
+            "jump0" => self.address_inst(0xB000),
+            "native" => self.address_inst(0x0000),
+            "delay" => {
+                self.expect(":=")?;
+                self.register_inst(0xF015)
+            }
+            "buzzer" => {
+                self.expect(":=")?;
+                self.register_inst(0xF018)
+            }
+            "i" => self.index_statement(),
+            "if" => self.if_statement(),
+            "else" => self.else_statement(),
+            "end" => match self.control.pop() {
+                Some(Control::If { jump, .. }) => self.patch(jump, self.here),
+                _ => self.error("'end' without 'begin'"),
+            },
+            "loop" => {
+                self.control.push(Control::Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                    line: self.line,
+                });
+                Ok(())
+            }
+            "while" => self.while_statement(),
+            "again" => self.again_statement(),
+            _ => self.other_statement(token),
+        }
+    }
+
+    /// Handles registers, macros, calls and data.
+    fn other_statement(&mut self, token: &str) -> Result<()> {
+        if let Some(x) = self.parse_register(token) {
+            return self.register_statement(x);
+        }
+        if let Some(mac) = self.macros.get(token).cloned() {
+            return self.expand(&mac);
+        }
+        if let Some(byte) = self.parse_value(token) {
+            return self.byte(byte);
+        }
+        self.address_operand(0x2000, token)
+    }
+
+    fn directive(&mut self, token: &str) -> Result<()> {
+        match token {
+            ":" => {
+                let name = self.name()?;
+                // Octo drops the jump when it would go to the next instruction
+                if name == "main" && self.here == PROGRAM_START + 2 && self.labels.is_empty() {
+                    self.rom.clear();
+                    self.written.clear();
+                    self.here = PROGRAM_START;
+                    self.jump_to_main = false;
+                }
+                self.define(name, self.here)
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.number()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let x = self.register()?;
+                self.aliases.insert(name, x);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":unpack" => self.unpack(),
+            ":next" => {
+                let name = self.name()?;
+                self.define(name, self.here.wrapping_add(1))
+            }
+            ":org" => {
+                let token = self.next()?;
+                self.here = self.address(&token)?;
+                Ok(())
+            }
+            ":byte" => {
+                let token = self.next()?;
+                match self.parse_value(&token) {
+                    Some(byte) => self.byte(byte),
+                    None if token == "{" => {
+                        let value = self.calc()?;
+                        self.byte(integer(value))
+                    }
+                    None => self.error(format!("'{token}' is not a number")),
+                }
+            }
+            ":call" => {
+                let token = self.next()?;
+                self.address_operand(0x2000, &token)
+            }
+            ":breakpoint" | ":proto" => self.next().map(drop),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(drop)
+            }
+            _ => self.error(format!("'{token}' is not a supported directive")),
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<()> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let x = u16::from(x) << 8;
+        if let Some(y) = self.parse_register(&rhs) {
+            let y = u16::from(y) << 4;
+            let code = match op.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xE,
+                _ => return self.error(format!("'{op}' cannot take a register")),
+            };
+            return self.inst(0x8000 | x | y | code);
+        }
+
+        match (op.as_str(), rhs.as_str()) {
+            (":=", "key") => self.inst(0xF00A | x),
+            (":=", "delay") => self.inst(0xF007 | x),
+            (":=", "random") => {
+                let mask = self.byte_value()?;
+                self.inst(0xC000 | x | u16::from(mask))
+            }
+            (":=", _) => {
+                let value = self.byte_operand(&rhs)?;
+                self.inst(0x6000 | x | u16::from(value))
+            }
+            ("+=", _) => {
+                let value = self.byte_operand(&rhs)?;
+                self.inst(0x7000 | x | u16::from(value))
+            }
+            ("-=", _) => {
+                let value = self.byte_operand(&rhs)?;
+                self.inst(0x7000 | x | u16::from(value.wrapping_neg()))
+            }
+            _ => self.error(format!("'{op}' cannot take '{rhs}'")),
+        }
+    }
+
+    fn index_statement(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.as_str() {
+            ":=" => {
+                let token = self.next()?;
+                if UNSUPPORTED.contains(&token.as_str()) {
+                    return self.statement(&token);
+                }
+                if token == "hex" {
+                    return self.register_inst(0xF029);
+                }
+                self.address_operand(0xA000, &token)
+            }
+            "+=" => self.register_inst(0xF01E),
+            _ => self.error(format!("'i {op}' is not an instruction")),
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<()> {
+        // Both forms are tried before anything is emitted, to negate the
+        // condition of `begin`
+        let block = self
+            .tokens
+            .iter()
+            .map(|token| token.text.as_str())
+            .find(|&token| token == "then" || token == "begin")
+            == Some("begin");
+        let line = self.line;
+        self.conditional(block)?;
+        if block {
+            self.expect("begin")?;
+            let jump = self.here;
+            self.inst(0x1000)?;
+            self.control.push(Control::If { jump, line });
+            Ok(())
+        } else {
+            self.expect("then")
+        }
+    }
+
+    fn else_statement(&mut self) -> Result<()> {
+        let Some(Control::If { jump, line }) = self.control.pop() else {
+            return self.error("'else' without 'begin'");
+        };
+        let end = self.here;
+        self.inst(0x1000)?;
+        self.patch(jump, self.here)?;
+        self.control.push(Control::If { jump: end, line });
+        Ok(())
+    }
+
+    fn while_statement(&mut self) -> Result<()> {
+        if !self
+            .control
+            .iter()
+            .any(|control| matches!(control, Control::Loop { .. }))
+        {
+            return self.error("'while' outside of a loop");
+        }
+        self.conditional(true)?;
+        let exit = self.here;
+        self.inst(0x1000)?;
+        for control in self.control.iter_mut().rev() {
+            if let Control::Loop { exits, .. } = control {
+                exits.push(exit);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn again_statement(&mut self) -> Result<()> {
+        let Some(Control::Loop { start, exits, .. }) = self.control.pop() else {
+            return self.error("'again' without 'loop'");
+        };
+        self.inst(0x1000 | start)?;
+        for exit in exits {
+            self.patch(exit, self.here)?;
+        }
+        Ok(())
+    }
+
+    /// Emits the instructions skipping the next one unless the condition
+    /// holds, or if it holds when `negated`.
+    fn conditional(&mut self, negated: bool) -> Result<()> {
+        let x = self.register()?;
+        let mut op = self.next()?;
+        if negated {
+            let negation = match op.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                ">" => "<=",
+                "<" => ">=",
+                ">=" => "<",
+                "<=" => ">",
+                _ => return self.error(format!("'{op}' is not a condition")),
+            };
+            op = String::from(negation);
+        }
+        let vx = u16::from(x) << 8;
+
+        match op.as_str() {
+            "key" => return self.inst(0xE0A1 | vx),
+            "-key" => return self.inst(0xE09E | vx),
+            "==" | "!=" => {
+                let rhs = self.next()?;
+                return match (self.parse_register(&rhs), op == "==") {
+                    (Some(y), true) => self.inst(0x9000 | vx | u16::from(y) << 4),
+                    (Some(y), false) => self.inst(0x5000 | vx | u16::from(y) << 4),
+                    (None, true) => {
+                        let value = self.byte_operand(&rhs)?;
+                        self.inst(0x4000 | vx | u16::from(value))
+                    }
+                    (None, false) => {
+                        let value = self.byte_operand(&rhs)?;
+                        self.inst(0x3000 | vx | u16::from(value))
+                    }
+                };
+            }
+            ">" | "<" | ">=" | "<=" => {}
+            _ => return self.error(format!("'{op}' is not a condition")),
+        }
+
+        // The others subtract in a temporary register, leaving the flag in vF
+        let temp = self.aliases.get("compare-temp").copied().unwrap_or(0xE);
+        if temp == 0xF {
+            return self.error("'compare-temp' cannot be vF, which receives the flag");
+        }
+        if temp == x {
+            return self.error(format!("v{x:X} is the 'compare-temp' register"));
+        }
+        let temp = u16::from(temp) << 8;
+        let rhs = self.next()?;
+        if let Some(y) = self.parse_register(&rhs) {
+            self.inst(0x8000 | temp | u16::from(y) << 4)?;
+        } else {
+            let value = self.byte_operand(&rhs)?;
+            self.inst(0x6000 | temp | u16::from(value))?;
+        }
+        let (subtract, skip) = match op.as_str() {
+            ">" => (0x5, 0x3001),
+            "<" => (0x7, 0x3001),
+            ">=" => (0x7, 0x4001),
+            _ => (0x5, 0x4001),
+        };
+        self.inst(0x8000 | temp | u16::from(x) << 4 | subtract)?;
+        self.inst(skip | 0x0F00)
+    }
+
+    fn define_macro(&mut self) -> Result<()> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.tokens.pop_front();
+            let Some(token) = token else {
+                return self.error(format!("the macro '{name}' has no closing '}}'"));
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    /// Puts the body of `mac` in front of the tokens left, with the
+    /// arguments following the call in place of its parameters.
+    fn expand(&mut self, mac: &Macro) -> Result<()> {
+        let mut args = HashMap::new();
+        for param in &mac.params {
+            let arg = self.next()?;
+            args.insert(param.as_str(), arg);
+        }
+
+        let line = self.line;
+        for token in mac.body.iter().rev() {
+            let text = args.get(token.text.as_str()).unwrap_or(&token.text);
+            self.tokens.push_front(Token {
+                text: text.clone(),
+                line,
+            });
+        }
+        Ok(())
+    }
+
+    fn unpack(&mut self) -> Result<()> {
+        let nibble = self.nibble()?;
+        let token = self.next()?;
+        let hi = u16::from(self.aliases.get("unpack-hi").copied().unwrap_or(0x0));
+        let lo = u16::from(self.aliases.get("unpack-lo").copied().unwrap_or(0x1));
+        let at = self.here;
+        self.inst(0x6000 | hi << 8)?;
+        self.inst(0x6000 | lo << 8)?;
+
+        if let Some(value) = self.resolve(&token) {
+            let addr = self.to_address(value)?;
+            return self.write_unpack(at, nibble, addr);
+        }
+        self.forward(token, Fixup::Unpack(at, nibble));
+        Ok(())
+    }
+
+    /// Emits `op` with the address `token` stands for, which may be
+    /// defined later.
+    fn address_operand(&mut self, op: u16, token: &str) -> Result<()> {
+        if token == "{" {
+            let value = self.calc()?;
+            let addr = self.to_address(integer(value))?;
+            return self.inst(op | addr);
+        }
+        if let Some(value) = self.resolve(token) {
+            let addr = self.to_address(value)?;
+            return self.inst(op | addr);
+        }
+        self.check_name(token)?;
+        let at = self.here;
+        self.inst(op)?;
+        self.forward(String::from(token), Fixup::Address(at));
+        Ok(())
+    }
+
+    /// Returns the value of a number, constant or label.
+    fn resolve(&self, token: &str) -> Option<i64> {
+        self.parse_value(token)
+            .or_else(|| self.labels.get(token).map(|&addr| i64::from(addr)))
+    }
+
+    fn forward(&mut self, name: String, fixup: Fixup) {
+        self.pending
+            .entry(name)
+            .or_default()
+            .push((fixup, self.line));
+    }
+
+    fn define(&mut self, name: String, addr: u16) -> Result<()> {
+        if self.labels.contains_key(&name) {
+            return self.error(format!("'{name}' is already defined"));
+        }
+        for (fixup, line) in self.pending.remove(&name).unwrap_or_default() {
+            self.line = line;
+            match fixup {
+                Fixup::Address(at) => self.patch(at, addr)?,
+                Fixup::Unpack(at, nibble) => self.write_unpack(at, nibble, addr)?,
+            }
+        }
+        self.labels.insert(name, addr);
+        Ok(())
+    }
+
+    /// Sets the address of the instruction at `at`.
+    fn patch(&mut self, at: u16, addr: u16) -> Result<()> {
+        if addr > 0xFFF {
+            return self.error(format!("0x{addr:X} is out of the reach of instructions"));
+        }
+        let [hi, lo] = addr.to_be_bytes();
+        let i = self.offset(at)?;
+        self.rom[i] |= hi;
+        self.rom[i + 1] = lo;
+        Ok(())
+    }
+
+    fn write_unpack(&mut self, at: u16, nibble: u8, addr: u16) -> Result<()> {
+        if addr > 0xFFF {
+            return self.error(format!("0x{addr:X} is out of the reach of ':unpack'"));
+        }
+        let [hi, lo] = addr.to_be_bytes();
+        let i = self.offset(at)?;
+        self.rom[i + 1] = nibble << 4 | hi;
+        self.rom[i + 3] = lo;
+        Ok(())
+    }
+
+    fn offset(&self, addr: u16) -> Result<usize> {
+        match addr.checked_sub(PROGRAM_START) {
+            Some(offset) => Ok(usize::from(offset)),
+            None => self.error(format!(
+                "0x{addr:03X} is before the start of the program at 0x{PROGRAM_START:03X}"
+            )),
+        }
+    }
+
+    fn byte(&mut self, value: i64) -> Result<()> {
+        let byte = self.to_byte(value)?;
+        let i = self.offset(self.here)?;
+        if i >= self.rom.len() {
+            self.rom.resize(i + 1, 0);
+            self.written.resize(i + 1, false);
+        }
+        if self.written[i] {
+            return self.error(format!("overwriting the data at 0x{:03X}", self.here));
+        }
+        self.rom[i] = byte;
+        self.written[i] = true;
+        match self.here.checked_add(1) {
+            Some(here) => {
+                self.here = here;
+                Ok(())
+            }
+            None => self.error("the program does not fit in memory"),
+        }
+    }
+
+    fn inst(&mut self, word: u16) -> Result<()> {
+        let [hi, lo] = word.to_be_bytes();
+        self.byte(i64::from(hi))?;
+        self.byte(i64::from(lo))
+    }
+
+    fn register_inst(&mut self, op: u16) -> Result<()> {
+        let x = self.register()?;
+        self.inst(op | u16::from(x) << 8)
+    }
+
+    /// Emits a `save` or `load`, refusing the ranges of XO-CHIP.
+    fn memory_inst(&mut self, op: u16) -> Result<()> {
+        self.register_inst(op)?;
+        if self.peek() == Some("-") {
+            return self
+                .error("register ranges are an XO-CHIP feature, which chipper does not run");
+        }
+        Ok(())
+    }
+
+    fn address_inst(&mut self, op: u16) -> Result<()> {
+        let token = self.next()?;
+        self.address_operand(op, &token)
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        match self.parse_register(&token) {
+            Some(x) => Ok(x),
+            None => self.error(format!("'{token}' is not a register")),
+        }
+    }
+
+    fn parse_register(&self, token: &str) -> Option<u8> {
+        let mut chars = token.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(digit), None) => digit
+                .to_digit(16)
+                .and_then(|digit| u8::try_from(digit).ok()),
+            _ => self.aliases.get(token).copied(),
+        }
+    }
+
+    /// Takes the name of something being defined.
+    fn name(&mut self) -> Result<String> {
+        let name = self.next()?;
+        self.check_name(&name)?;
+        Ok(name)
+    }
+
+    fn check_name(&self, name: &str) -> Result<()> {
+        if parse_number(name).is_some() || self.parse_register(name).is_some() {
+            return self.error(format!("'{name}' cannot be used as a name"));
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a number or constant.
+    fn parse_value(&self, token: &str) -> Option<i64> {
+        parse_number(token).or_else(|| self.constants.get(token).copied().map(integer))
+    }
+
+    fn byte_value(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        self.byte_operand(&token)
+    }
+
+    fn byte_operand(&mut self, token: &str) -> Result<u8> {
+        let value = if token == "{" {
+            integer(self.calc()?)
+        } else {
+            match self.parse_value(token) {
+                Some(value) => value,
+                None => return self.error(format!("'{token}' is not a number")),
+            }
+        };
+        self.to_byte(value)
+    }
+
+    fn to_byte(&self, value: i64) -> Result<u8> {
+        match value {
+            -128..=-1 => Ok(value.to_le_bytes()[0]),
+            _ => u8::try_from(value)
+                .or_else(|_| self.error(format!("{value} does not fit in a byte"))),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        let byte = self.byte_value()?;
+        if byte > 0xF {
+            return self.error(format!("{byte} does not fit in 4 bits"));
+        }
+        Ok(byte)
+    }
+
+    fn address(&self, token: &str) -> Result<u16> {
+        match self.parse_value(token) {
+            Some(value) => self.to_address(value),
+            None => self.error(format!("'{token}' is not a number")),
+        }
+    }
+
+    fn to_address(&self, value: i64) -> Result<u16> {
+        u16::try_from(value).or_else(|_| self.error(format!("{value} is not an address")))
+    }
+
+    /// Takes a number, constant, label or `:calc` expression.
+    fn number(&mut self) -> Result<f64> {
+        let token = self.next()?;
+        if token == "{" {
+            return self.calc();
+        }
+        match self.resolve(&token) {
+            Some(value) => Ok(float(value)),
+            None => self.error(format!("'{token}' is not defined")),
+        }
+    }
+
+    /// Evaluates the expression up to the closing brace.
+    fn calc(&mut self) -> Result<f64> {
+        let mut expr = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "}" {
+                break;
+            }
+            expr.push(token);
+        }
+
+        let mut pos = 0;
+        let value = self.expression(&expr, &mut pos)?;
+        match expr.get(pos) {
+            Some(token) => self.error(format!("unexpected '{token}' in the expression")),
+            None => Ok(value),
+        }
+    }
+
+    /// Evaluates an expression the way Octo does: from right to left,
+    /// without precedence between the operators.
+    fn expression(&self, expr: &[String], pos: &mut usize) -> Result<f64> {
+        let Some(token) = expr.get(*pos) else {
+            return self.error("the expression ends too early");
+        };
+        if let Some(f) = unary(token) {
+            *pos += 1;
+            return Ok(f(self.expression(expr, pos)?));
+        }
+        if token == "@" {
+            *pos += 1;
+            let addr = integer(self.expression(expr, pos)?);
+            let byte = u16::try_from(addr)
+                .ok()
+                .and_then(|addr| addr.checked_sub(PROGRAM_START))
+                .and_then(|offset| self.rom.get(usize::from(offset)));
+            return Ok(byte.map_or(0.0, |&byte| f64::from(byte)));
+        }
+
+        let left = self.terminal(expr, pos)?;
+        match expr.get(*pos).and_then(|token| binary(token)) {
+            Some(f) => {
+                *pos += 1;
+                Ok(f(left, self.expression(expr, pos)?))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn terminal(&self, expr: &[String], pos: &mut usize) -> Result<f64> {
+        let token = &expr[*pos];
+        *pos += 1;
+        if token == "(" {
+            let value = self.expression(expr, pos)?;
+            return match expr.get(*pos) {
+                Some(token) if token == ")" => {
+                    *pos += 1;
+                    Ok(value)
+                }
+                _ => self.error("missing ')' in the expression"),
+            };
+        }
+
+        let value = match token.as_str() {
+            "HERE" => Some(f64::from(self.here)),
+            "PI" => Some(std::f64::consts::PI),
+            "E" => Some(std::f64::consts::E),
+            _ => parse_number(token)
+                .map(float)
+                .or_else(|| self.constants.get(token).copied())
+                .or_else(|| self.labels.get(token).map(|&addr| f64::from(addr)))
+                .or_else(|| self.parse_register(token).map(f64::from)),
+        };
+        match value {
+            Some(value) => Ok(value),
+            None => self.error(format!("'{token}' is not defined yet")),
+        }
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>> {
+        match self.control.last() {
+            Some(Control::If { line, .. }) => {
+                self.line = *line;
+                return self.error("'begin' without 'end'");
+            }
+            Some(Control::Loop { line, .. }) => {
+                self.line = *line;
+                return self.error("'loop' without 'again'");
+            }
+            None => {}
+        }
+        if let Some((name, uses)) = self.pending.iter().min_by_key(|(_, uses)| uses[0].1) {
+            self.line = uses[0].1;
+            return self.error(format!("'{name}' is never defined"));
+        }
+
+        if self.jump_to_main {
+            let Some(&main) = self.labels.get("main") else {
+                return self.error("the program has no 'main' label");
+            };
+            self.rom[0] = 0x10;
+            self.patch(PROGRAM_START, main)?;
+        }
+        Ok(self.rom)
+    }
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn integer(value: f64) -> i64 {
+    value.floor() as i64
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn float(value: i64) -> f64 {
+    value as f64
+}
+
+fn unary(token: &str) -> Option<fn(f64) -> f64> {
+    Some(match token {
+        "-" => |a: f64| -a,
+        "~" => |a| float(!integer(a)),
+        "!" => |a| if a == 0.0 { 1.0 } else { 0.0 },
+        "sin" => f64::sin,
+        "cos" => f64::cos,
+        "tan" => f64::tan,
+        "exp" => f64::exp,
+        "log" => f64::ln,
+        "abs" => f64::abs,
+        "sqrt" => f64::sqrt,
+        "sign" => f64::signum,
+        "ceil" => f64::ceil,
+        "floor" => f64::floor,
+        _ => return None,
+    })
+}
+
+fn binary(token: &str) -> Option<fn(f64, f64) -> f64> {
+    Some(match token {
+        "-" => |a, b| a - b,
+        "+" => |a, b| a + b,
+        "*" => |a, b| a * b,
+        "/" => |a, b| a / b,
+        "%" => |a, b| a % b,
+        "pow" => f64::powf,
+        "min" => f64::min,
+        "max" => f64::max,
+        "&" => |a, b| float(integer(a) & integer(b)),
+        "|" => |a, b| float(integer(a) | integer(b)),
+        "^" => |a, b| float(integer(a) ^ integer(b)),
+        "<<" => |a, b| float(integer(a).wrapping_shl(shift(b))),
+        ">>" => |a, b| float(integer(a).wrapping_shr(shift(b))),
+        "<" => |a, b| truth(a < b),
+        "<=" => |a, b| truth(a <= b),
+        "==" => |a, b| truth((a - b).abs() < f64::EPSILON),
+        "!=" => |a, b| truth((a - b).abs() >= f64::EPSILON),
+        ">=" => |a, b| truth(a >= b),
+        ">" => |a, b| truth(a > b),
+        _ => return None,
+    })
+}
+
+fn truth(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn shift(b: f64) -> u32 {
+    u32::try_from(integer(b)).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chip::Chip8;
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn instructions() {
+        let rom = assemble(
+            ": main
+               clear
+               v0 := 5  v1 := v0  v2 += 0x10  v2 -= 1  v3 += v2  v3 -= v1
+               v4 |= v5  v4 &= v5  v4 ^= v5  v4 >>= v5  v4 <<= v5  v4 =- v5
+               v6 := random 0xFF  v7 := key  v8 := delay  delay := v8  buzzer := v9
+               i := sprites  i += v0  i := hex v0  bcd v0  save v3  load v3
+               sprite v0 v1 5
+               sub
+               jump0 sprites
+               : sub ;
+               : sprites 0xFF 0b10000001 -1 0",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&rom),
+            [
+                0x00E0, 0x6005, 0x8100, 0x7210, 0x72FF, 0x8324, 0x8315, 0x8451, 0x8452, 0x8453,
+                0x8456, 0x845E, 0x8457, 0xC6FF, 0xF70A, 0xF807, 0xF815, 0xF918, 0xA238, 0xF01E,
+                0xF029, 0xF033, 0xF355, 0xF365, 0xD015, 0x2236, 0xB238, 0x00EE, 0xFF81, 0xFF00
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let rom = assemble(
+            ": main
+               loop
+                 if v0 == 1 then v1 := 2
+                 if v0 != v1 begin
+                   v2 := 3
+                 else
+                   v2 := 4
+                 end
+                 while v3 key
+                 if v0 < v1 then clear
+               again",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&rom),
+            [
+                0x4001, 0x6102, 0x9010, 0x120C, 0x6203, 0x120E, 0x6204, 0xE39E, 0x121C, 0x8E10,
+                0x8E07, 0x3F01, 0x00E0, 0x1200
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_to_main() {
+        let rom = assemble(": data 0x12 0x34 : main jump main").unwrap();
+        assert_eq!(words(&rom), [0x1204, 0x1234, 0x1204]);
+
+        let err = assemble(": start clear").unwrap_err();
+        assert_eq!(err.message, "the program has no 'main' label");
+    }
+
+    #[test]
+    fn directives() {
+        let rom = assemble(
+            ":const speed 3
+             :alias x v5
+             :calc twice { 1 + speed * 2 }
+             :macro add-to reg amount { reg += amount }
+             : main
+               x := speed
+               add-to x twice
+               :unpack 0xA target
+               :next target v0 := 0
+               :org 0x210
+               :byte { 1 << 3 }
+               :byte 0",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&rom),
+            [0x6503, 0x7507, 0x60A2, 0x6109, 0x6000, 0x0000, 0x0000, 0x0000, 0x0800]
+        );
+    }
+
+    /// Runs `source` for a while, and returns the value of `v2`.
+    fn run(source: &str) -> u8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&assemble(source).unwrap()).unwrap();
+        for _ in 0..32 {
+            chip8.cycle().unwrap();
+        }
+        chip8.register(0x2)
+    }
+
+    #[test]
+    fn comparisons_run() {
+        type Compare = fn(u8, u8) -> bool;
+        let ops: [(&str, Compare); 6] = [
+            ("==", |a, b| a == b),
+            ("!=", |a, b| a != b),
+            ("<", |a, b| a < b),
+            (">", |a, b| a > b),
+            ("<=", |a, b| a <= b),
+            (">=", |a, b| a >= b),
+        ];
+        for (a, b) in [(3, 5), (5, 3), (5, 5), (0, 255), (255, 0)] {
+            for (op, holds) in &ops {
+                for rhs in [String::from("v1"), b.to_string()] {
+                    let condition = format!("v0 := {a} v1 := {b} if v0 {op} {rhs}");
+                    let then = format!(": main {condition} then v2 := 1 loop again");
+                    let block =
+                        format!(": main {condition} begin v2 := 1 else v2 := 2 end loop again");
+                    let expected = u8::from(holds(a, b));
+                    assert_eq!(run(&then), expected, "{a} {op} {rhs}");
+                    assert_eq!(run(&block), 2 - expected, "{a} {op} {rhs}");
+                }
+            }
+        }
+
+        assert!(assemble(":alias compare-temp vF : main if v0 < 1 then clear").is_err());
+        assert!(assemble(": main if vE < 1 then clear").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let err = assemble(": main\n  hires").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("'hires'"));
+
+        let err = assemble(": main\n  jump nowhere").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: 'nowhere' is never defined");
+
+        assert!(assemble(": main v0 := 256").is_err());
+        assert!(assemble(": main loop").is_err());
+        assert!(assemble(": main : main").is_err());
+        assert!(assemble(": main save v0 - v3").is_err());
+    }
+}
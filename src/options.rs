@@ -0,0 +1,466 @@
+use chipper::audio::{Tone, Waveform};
+use chipper::chip::OpClass;
+use chipper::chip::OpPolicy;
+use chipper::chip::Platform;
+use chipper::chip::Settings;
+use chipper::chip::{Font, FontSet};
+use chipper::crt::{Effect, Effects};
+use chipper::image::Palette;
+use chipper::library::RomLibrary;
+use chipper::phosphor::{Filter, DEFAULT_DECAY};
+use chipper::trace::TraceFilter;
+
+use anyhow::Error;
+use anyhow::Result;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// The deepest stack that fits in a save state.
+const MAX_STACK_SIZE: usize = 255;
+
+pub struct Options {
+    /// Path of the ROM to run, which is left to the client with `--dap`.
+    pub rom_path: Option<String>,
+    pub settings: Settings,
+    /// The platform given with `--platform`, whose quirks `settings` has.
+    pub platform: Option<Platform>,
+    pub tone: Tone,
+    pub palette: Palette,
+    pub filter: Filter,
+    /// Whether to show the frames and instructions per second from the start.
+    pub fps: bool,
+    /// CRT post-processing of the window.
+    pub effects: Effects,
+    /// How much brightness pixels keep every frame with the phosphor filter.
+    pub decay: f32,
+    /// GIF file or directory of PNG images to capture the display to.
+    pub capture: Option<String>,
+    /// Octo cartridge to take the speed, quirks and colors from.
+    pub cartridge: Option<String>,
+    /// File to write the source code of the cartridge to, instead of running.
+    pub cart_source: Option<String>,
+    /// Octo options file to take the speed, quirks and colors from.
+    pub octo_json: Option<String>,
+    /// File to write the settings to as Octo options, instead of running.
+    pub save_octo_json: Option<String>,
+    /// Image file to save the display to on exit.
+    pub screenshot: Option<String>,
+    /// WAV file to record the sound to.
+    pub wav: Option<String>,
+    /// Number of frames to run without a window or audio device, if any.
+    pub headless: Option<u64>,
+    /// Symbol file to label addresses with, which otherwise defaults to the
+    /// ROM path with a `.sym` extension when that file exists.
+    pub symbols: Option<String>,
+    pub trace: Option<TraceOptions>,
+    /// File to write the profiler report to.
+    pub profile: Option<String>,
+    /// File to write the profiler memory heatmap to.
+    pub heatmap: Option<String>,
+    pub memory_viewer: bool,
+    /// How to react to suspicious behaviour, if it should be detected at all.
+    pub detect: Option<OpPolicy>,
+    /// File to export the control-flow graph of the ROM to, instead of running it.
+    pub cfg: Option<String>,
+    /// Enter the monitor before running the ROM.
+    pub monitor: bool,
+    /// Reload the ROM whenever the file changes.
+    pub watch: Option<WatchOptions>,
+    /// Local port to serve the GDB remote serial protocol on.
+    pub gdb_port: Option<u16>,
+    /// Local port to serve the Debug Adapter Protocol on.
+    pub dap_port: Option<u16>,
+}
+
+pub struct TraceOptions {
+    /// File to write the trace to, where `-` is stdout.
+    pub path: String,
+    pub filter: TraceFilter,
+    pub ring: Option<usize>,
+}
+
+pub struct WatchOptions {
+    /// Keep the breakpoints and the memory viewer when reloading.
+    pub keep_breakpoints: bool,
+}
+
+impl Options {
+    #[allow(clippy::too_many_lines)]
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut rom_path = None;
+        let mut settings = Settings::new();
+        let mut platform = None;
+        let mut stack_size = None;
+        let mut font = None;
+        let mut font_addr = None;
+        let mut tone = Tone::new();
+        let mut palette = Palette::new();
+        let mut filter = Filter::None;
+        let mut fps = false;
+        let mut effects = Effects::new();
+        let mut decay = DEFAULT_DECAY;
+        let mut capture = None;
+        let mut screenshot = None;
+        let mut cartridge = None;
+        let mut cart_source = None;
+        let mut octo_json = None;
+        let mut save_octo_json = None;
+        let mut wav = None;
+        let mut headless = None;
+        let mut symbols = None;
+        let mut trace_path = None;
+        let mut trace_filter = TraceFilter::default();
+        let mut trace_ring = None;
+        let mut profile = None;
+        let mut heatmap = None;
+        let mut memory_viewer = false;
+        let mut detect = None;
+        let mut cfg = None;
+        let mut monitor = false;
+        let mut watch = false;
+        let mut keep_breakpoints = false;
+        let mut gdb_port = None;
+        let mut dap_port = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--platform" => {
+                    let name = Self::value(&arg, args.next())?;
+                    platform = Some(Platform::from_name(&name).ok_or_else(|| {
+                        Error::msg(format!("'{name}' is not a platform. Exiting."))
+                    })?);
+                }
+                "--font" => {
+                    let name = Self::value(&arg, args.next())?;
+                    font = Some(Self::parse_font(&name)?);
+                }
+                "--font-addr" => {
+                    let addr = Self::value(&arg, args.next())?;
+                    font_addr = Some(Self::parse_addr(&arg, &addr)?);
+                }
+                "--stack-size" => {
+                    let size = Self::value(&arg, args.next())?;
+                    stack_size = Some(
+                        size.parse()
+                            .ok()
+                            .filter(|size| (1..=MAX_STACK_SIZE).contains(size))
+                            .ok_or_else(|| {
+                                Error::msg(format!(
+                                    "'{arg}' expects a number from 1 to {MAX_STACK_SIZE}. Exiting."
+                                ))
+                            })?,
+                    );
+                }
+                "--tone" => {
+                    let frequency = Self::value(&arg, args.next())?;
+                    tone.frequency = frequency
+                        .parse()
+                        .ok()
+                        .filter(|frequency: &f32| *frequency > 0.0 && frequency.is_finite())
+                        .ok_or_else(|| {
+                            Error::msg(format!("'{arg}' expects a frequency in Hz. Exiting."))
+                        })?;
+                }
+                "--volume" => {
+                    let volume = Self::value(&arg, args.next())?;
+                    let percent: u8 = volume
+                        .parse()
+                        .ok()
+                        .filter(|percent| *percent <= 100)
+                        .ok_or_else(|| {
+                            Error::msg(format!("'{arg}' expects a percentage. Exiting."))
+                        })?;
+                    tone.volume = f32::from(percent) / 100.0;
+                }
+                "--waveform" => {
+                    let name = Self::value(&arg, args.next())?;
+                    tone.waveform = Waveform::from_name(&name).ok_or_else(|| {
+                        Error::msg(format!("'{name}' is not a waveform. Exiting."))
+                    })?;
+                }
+                "--palette" => {
+                    let colors = Self::value(&arg, args.next())?;
+                    palette = Palette::parse(&colors).ok_or_else(|| {
+                        Error::msg(format!(
+                            "'{arg}' expects two colors such as 000000,FFFFFF. Exiting."
+                        ))
+                    })?;
+                }
+                "--filter" => {
+                    let name = Self::value(&arg, args.next())?;
+                    filter = Filter::from_name(&name).ok_or_else(|| {
+                        Error::msg(format!("'{name}' is not a display filter. Exiting."))
+                    })?;
+                }
+                "--decay" => {
+                    let value = Self::value(&arg, args.next())?;
+                    let percent: u8 = value
+                        .parse()
+                        .ok()
+                        .filter(|percent| *percent <= 100)
+                        .ok_or_else(|| {
+                            Error::msg(format!("'{arg}' expects a percentage. Exiting."))
+                        })?;
+                    decay = f32::from(percent) / 100.0;
+                }
+                "--fps" => {
+                    fps = true;
+                }
+                "--crt" => {
+                    for name in Self::value(&arg, args.next())?.split(',') {
+                        if name == "all" {
+                            effects = Effects::all();
+                            continue;
+                        }
+
+                        let effect = Effect::from_name(name).ok_or_else(|| {
+                            Error::msg(format!("'{name}' is not a CRT effect. Exiting."))
+                        })?;
+                        effects.insert(effect);
+                    }
+                }
+                "--capture" => {
+                    capture = Some(Self::value(&arg, args.next())?);
+                }
+                "--cartridge" => {
+                    cartridge = Some(Self::value(&arg, args.next())?);
+                }
+                "--cart-source" => {
+                    cart_source = Some(Self::value(&arg, args.next())?);
+                }
+                "--octo-options" => {
+                    octo_json = Some(Self::value(&arg, args.next())?);
+                }
+                "--save-octo-options" => {
+                    save_octo_json = Some(Self::value(&arg, args.next())?);
+                }
+                "--screenshot" => {
+                    screenshot = Some(Self::value(&arg, args.next())?);
+                }
+                "--wav" => {
+                    wav = Some(Self::value(&arg, args.next())?);
+                }
+                "--headless" => {
+                    let frames = Self::value(&arg, args.next())?;
+                    headless = Some(frames.parse().map_err(|_| {
+                        Error::msg(format!("'{arg}' expects a number of frames. Exiting."))
+                    })?);
+                }
+                "--sys-policy" => {
+                    settings.sys_addr_policy = Self::parse_policy(&arg, args.next().as_deref())?;
+                }
+                "--invalid-op-policy" => {
+                    settings.invalid_op_policy = Self::parse_policy(&arg, args.next().as_deref())?;
+                }
+                "--symbols" => {
+                    symbols = Some(Self::value(&arg, args.next())?);
+                }
+                "--trace" => {
+                    trace_path = Some(Self::value(&arg, args.next())?);
+                }
+                "--trace-range" => {
+                    let range = Self::value(&arg, args.next())?;
+                    trace_filter.ranges.push(Self::parse_range(&arg, &range)?);
+                }
+                "--trace-ops" => {
+                    for name in Self::value(&arg, args.next())?.split(',') {
+                        let class = OpClass::from_name(name).ok_or_else(|| {
+                            Error::msg(format!("'{name}' is not an opcode class. Exiting."))
+                        })?;
+                        trace_filter.classes.push(class);
+                    }
+                }
+                "--trace-ring" => {
+                    let len = Self::value(&arg, args.next())?;
+                    trace_ring =
+                        Some(len.parse().ok().filter(|&len| len > 0).ok_or_else(|| {
+                            Error::msg(format!(
+                                "'{arg}' expects a positive number of instructions. Exiting."
+                            ))
+                        })?);
+                }
+                "--profile" => {
+                    profile = Some(Self::value(&arg, args.next())?);
+                }
+                "--heatmap" => {
+                    heatmap = Some(Self::value(&arg, args.next())?);
+                }
+                "--memory-viewer" => {
+                    memory_viewer = true;
+                }
+                "--detect" => {
+                    detect = Some(Self::parse_policy(&arg, args.next().as_deref())?);
+                }
+                "--cfg" => {
+                    cfg = Some(Self::value(&arg, args.next())?);
+                }
+                "--monitor" => {
+                    monitor = true;
+                }
+                "--watch" => {
+                    watch = true;
+                }
+                "--keep-breakpoints" => {
+                    keep_breakpoints = true;
+                }
+                "--gdb" => {
+                    let port = Self::value(&arg, args.next())?;
+                    gdb_port = Some(port.parse().map_err(|_| {
+                        Error::msg(format!("'{arg}' expects a port number. Exiting."))
+                    })?);
+                }
+                "--dap" => {
+                    let port = Self::value(&arg, args.next())?;
+                    dap_port = Some(port.parse().map_err(|_| {
+                        Error::msg(format!("'{arg}' expects a port number. Exiting."))
+                    })?);
+                }
+                _ if arg.starts_with("--") => {
+                    return Err(Error::msg(format!("Unknown option '{arg}'. Exiting.")));
+                }
+                _ => rom_path = Some(arg),
+            }
+        }
+
+        // The policies are kept, whatever the order of the options
+        if let Some(platform) = platform {
+            settings = Settings {
+                sys_addr_policy: settings.sys_addr_policy,
+                invalid_op_policy: settings.invalid_op_policy,
+                ..Settings::for_platform(platform)
+            };
+        }
+        if let Some(size) = stack_size {
+            settings.stack_size = size;
+        }
+        if let Some(font) = font {
+            settings.font = font;
+        }
+        if let Some(addr) = font_addr {
+            settings.font_addr = addr;
+        }
+        let font_range = settings.font_range();
+        if font_range.end > settings.rom_addr {
+            return Err(Error::msg(format!(
+                "The font at 0x{:03X}-0x{:03X} does not fit below the rom at 0x{:03X}. Exiting.",
+                font_range.start,
+                font_range.end - 1,
+                settings.rom_addr
+            )));
+        }
+
+        if cart_source.is_some() && cartridge.is_none() {
+            return Err(Error::msg(
+                "'--cart-source' needs a '--cartridge'. Exiting.",
+            ));
+        }
+
+        if keep_breakpoints && !watch {
+            return Err(Error::msg("'--keep-breakpoints' needs '--watch'. Exiting."));
+        }
+
+        let needs_rom = dap_port.is_none() || cfg.is_some() || headless.is_some() || watch;
+        let writes_only = cart_source.is_some() || save_octo_json.is_some();
+        if rom_path.is_none() && !writes_only && needs_rom {
+            return Err(Error::msg("No rom path provided. Exiting."));
+        }
+
+        let collection = rom_path
+            .as_deref()
+            .is_some_and(|path| RomLibrary::is_collection(Path::new(path)));
+        if collection && (cfg.is_some() || headless.is_some() || watch) {
+            return Err(Error::msg(
+                "'--cfg', '--headless' and '--watch' expect a ROM file, not a directory or archive. Exiting.",
+            ));
+        }
+
+        Ok(Self {
+            rom_path,
+            settings,
+            platform,
+            tone,
+            palette,
+            filter,
+            fps,
+            effects,
+            decay,
+            capture,
+            cartridge,
+            cart_source,
+            octo_json,
+            save_octo_json,
+            screenshot,
+            wav,
+            headless,
+            symbols,
+            trace: trace_path.map(|path| TraceOptions {
+                path,
+                filter: trace_filter,
+                ring: trace_ring,
+            }),
+            profile,
+            heatmap,
+            memory_viewer,
+            detect,
+            cfg,
+            monitor,
+            watch: watch.then_some(WatchOptions { keep_breakpoints }),
+            gdb_port,
+            dap_port,
+        })
+    }
+
+    fn value(flag: &str, value: Option<String>) -> Result<String> {
+        value.ok_or_else(|| Error::msg(format!("'{flag}' expects a value. Exiting.")))
+    }
+
+    /// Parses an address, given in hexadecimal with an optional `0x` prefix.
+    fn parse_addr(flag: &str, addr: &str) -> Result<u16> {
+        let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(digits, 16).map_err(|_| {
+            Error::msg(format!(
+                "'{flag}': '{addr}' is not a valid address. Exiting."
+            ))
+        })
+    }
+
+    /// Returns the built-in font named `name`, or reads it from the file at
+    /// that path.
+    fn parse_font(name: &str) -> Result<Font> {
+        if let Some(set) = FontSet::from_name(name) {
+            return Ok(set.font());
+        }
+
+        let bytes = std::fs::read(name)
+            .map_err(|e| Error::msg(format!("Could not read font '{name}': {e}. Exiting.")))?;
+        Font::from_bytes(bytes).ok_or_else(|| {
+            Error::msg(format!(
+                "'{name}' is not a font: it should hold 80 bytes of small glyphs, \
+                 optionally followed by 100 or 160 bytes of big ones. Exiting."
+            ))
+        })
+    }
+
+    /// Parses an inclusive address range of the form `start-end`.
+    fn parse_range(flag: &str, range: &str) -> Result<RangeInclusive<u16>> {
+        let (start, end) = range.split_once('-').ok_or_else(|| {
+            Error::msg(format!(
+                "'{flag}' expects a range like 0x200-0x2FF. Exiting."
+            ))
+        })?;
+
+        Ok(Self::parse_addr(flag, start)?..=Self::parse_addr(flag, end)?)
+    }
+
+    fn parse_policy(flag: &str, value: Option<&str>) -> Result<OpPolicy> {
+        match value {
+            Some("ignore") => Ok(OpPolicy::Ignore),
+            Some("warn") => Ok(OpPolicy::Warn),
+            Some("pause") => Ok(OpPolicy::Pause),
+            Some("error") => Ok(OpPolicy::Error),
+            _ => Err(Error::msg(format!(
+                "'{flag}' expects one of: ignore, warn, pause, error. Exiting."
+            ))),
+        }
+    }
+}
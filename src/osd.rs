@@ -0,0 +1,160 @@
+//! An on-screen display drawn over the emulated display, for status messages
+//! and a speed counter.
+
+use super::font;
+
+use anyhow::Error;
+use anyhow::Result;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use std::time::{Duration, Instant};
+
+/// Size of a font pixel in window pixels.
+const SCALE: u32 = 4;
+/// Space between the text and the edges of the window and its backdrop.
+const MARGIN: u32 = 8;
+const PADDING: u32 = 4;
+/// How long a message stays on screen.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+/// How often the counter is updated.
+const COUNTER_PERIOD: Duration = Duration::from_secs(1);
+
+const TEXT: Color = Color::RGB(0xF0, 0xC8, 0x3C);
+const BACKDROP: Color = Color::RGBA(0x00, 0x00, 0x00, 0xB0);
+
+/// Where a line of text goes in the window.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+}
+
+pub struct Osd {
+    message: Option<(String, Instant)>,
+    show_counter: bool,
+    /// Frames and instructions since `counter_start`.
+    frames: u32,
+    instructions: u32,
+    counter_start: Instant,
+    /// The counter as last updated.
+    counter: String,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Self {
+            message: None,
+            show_counter: false,
+            frames: 0,
+            instructions: 0,
+            counter_start: Instant::now(),
+            counter: String::new(),
+        }
+    }
+
+    /// Shows `message` for a couple of seconds, replacing the previous one.
+    pub fn show(&mut self, message: impl Into<String>) {
+        self.message = Some((message.into(), Instant::now()));
+    }
+
+    pub fn show_counter(&self) -> bool {
+        self.show_counter
+    }
+
+    /// Shows or hides the frames and instructions per second.
+    pub fn set_show_counter(&mut self, show: bool) {
+        self.show_counter = show;
+        self.frames = 0;
+        self.instructions = 0;
+        self.counter_start = Instant::now();
+        self.counter = String::from("-- FPS -- IPS");
+    }
+
+    /// Counts a 60 Hz frame of emulated time.
+    pub fn count_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn count_instruction(&mut self) {
+        self.instructions += 1;
+    }
+
+    /// Returns the lines to draw now, dropping the message once it expired.
+    /// `status` is shown for as long as it is given, and `platform` next to
+    /// the counter.
+    pub fn lines(&mut self, status: Option<&str>, platform: &str) -> Vec<(Corner, String)> {
+        let mut lines = Vec::new();
+        if let Some(status) = status {
+            lines.push((Corner::TopLeft, String::from(status)));
+        }
+
+        if self.show_counter {
+            let elapsed = self.counter_start.elapsed();
+            if elapsed >= COUNTER_PERIOD {
+                let seconds = elapsed.as_secs_f64();
+                self.counter = format!(
+                    "{:.0} FPS {:.0} IPS",
+                    f64::from(self.frames) / seconds,
+                    f64::from(self.instructions) / seconds
+                );
+                self.frames = 0;
+                self.instructions = 0;
+                self.counter_start = Instant::now();
+            }
+
+            lines.push((Corner::TopRight, format!("{platform} {}", self.counter)));
+        }
+
+        if let Some((_, shown)) = &self.message {
+            if shown.elapsed() >= MESSAGE_DURATION {
+                self.message = None;
+            }
+        }
+        if let Some((message, _)) = &self.message {
+            lines.push((Corner::BottomLeft, message.clone()));
+        }
+
+        lines
+    }
+}
+
+/// Draws lines of text on a dark backdrop in the corners of the canvas,
+/// without presenting it.
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+pub fn draw(canvas: &mut Canvas<Window>, lines: &[(Corner, String)]) -> Result<()> {
+    let (width, height) = canvas.output_size().map_err(Error::msg)?;
+    canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+
+    let line_height = font::LINE_HEIGHT * SCALE + 2 * PADDING;
+    let mut rows = [0; 3];
+    for (corner, text) in lines {
+        let text_width = text.chars().count() as u32 * font::ADVANCE * SCALE;
+        let row = &mut rows[*corner as usize];
+        let x = match corner {
+            Corner::TopLeft | Corner::BottomLeft => MARGIN,
+            Corner::TopRight => width.saturating_sub(MARGIN + text_width + 2 * PADDING),
+        };
+        let y = match corner {
+            Corner::TopLeft | Corner::TopRight => MARGIN + *row * line_height,
+            Corner::BottomLeft => height.saturating_sub(MARGIN + (*row + 1) * line_height),
+        };
+        *row += 1;
+
+        canvas.set_draw_color(BACKDROP);
+        canvas
+            .fill_rect(Rect::new(
+                x as i32,
+                y as i32,
+                text_width + 2 * PADDING,
+                line_height,
+            ))
+            .map_err(Error::msg)?;
+        let position = ((x + PADDING) as i32, (y + PADDING) as i32);
+        font::draw_text(canvas, &text.to_ascii_uppercase(), position, SCALE, TEXT)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+//! Phosphor persistence, to hide the flicker of sprites being erased and
+//! redrawn.
+//!
+//! Programs move sprites by drawing them once to erase them with XOR and
+//! again at their new position, so a display shown after every instruction
+//! keeps catching them half drawn. Like the phosphor of a CRT, the filter
+//! shows every pixel that was lit at any time during a 60 Hz frame at full
+//! brightness, then lets it fade by a constant factor every frame. With no
+//! decay, it shows the pixels lit in the current or the previous frame.
+
+use crate::chip::FrameBuffer;
+use crate::{CHIP8_HEIGHT, CHIP8_WIDTH};
+
+/// The brightness of every pixel, from 0 for the background to 1 for the
+/// foreground color.
+pub type Levels = [[f32; CHIP8_WIDTH]; CHIP8_HEIGHT];
+
+/// The fraction of its brightness a pixel keeps every frame by default.
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+/// Levels below this are shown as the background.
+const CUTOFF: f32 = 1.0 / 256.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// Shows the frame buffer as it is.
+    None,
+    /// Shows the pixels lit in the current or the previous frame.
+    Blend,
+    /// Fades pixels out over several frames.
+    Phosphor,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "blend" => Some(Self::Blend),
+            "phosphor" => Some(Self::Phosphor),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Blend => "blend",
+            Self::Phosphor => "phosphor",
+        }
+    }
+
+    /// Returns the filter after this one, for cycling through them.
+    #[must_use]
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Blend,
+            Self::Blend => Self::Phosphor,
+            Self::Phosphor => Self::None,
+        }
+    }
+}
+
+pub struct Phosphor {
+    filter: Filter,
+    decay: f32,
+    /// What is left of the previous frames.
+    levels: Levels,
+    /// The pixels lit at some point during the current frame.
+    lit: FrameBuffer,
+}
+
+impl Phosphor {
+    /// Creates a filter where pixels keep `decay`, from 0 to 1, of their
+    /// brightness every frame with `Filter::Phosphor`.
+    pub fn new(filter: Filter, decay: f32) -> Self {
+        Self {
+            filter,
+            decay: decay.clamp(0.0, 1.0),
+            levels: [[0.0; CHIP8_WIDTH]; CHIP8_HEIGHT],
+            lit: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// Switches to another filter, forgetting the previous frames.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.levels = [[0.0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        self.lit = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+    }
+
+    pub fn decay(&self) -> f32 {
+        self.decay
+    }
+
+    /// Returns how bright each pixel should be shown while the frame buffer
+    /// holds `frame_buffer`, remembering its lit pixels until the end of the
+    /// frame.
+    pub fn render(&mut self, frame_buffer: &FrameBuffer) -> Levels {
+        if self.filter == Filter::None {
+            return frame_buffer.map(|row| row.map(|pixel| f32::from(pixel != 0)));
+        }
+
+        let mut levels = self.levels;
+        for ((row, lit), pixels) in levels.iter_mut().zip(&mut self.lit).zip(frame_buffer) {
+            for ((level, lit), &pixel) in row.iter_mut().zip(lit).zip(pixels) {
+                *lit |= u8::from(pixel != 0);
+                if *lit != 0 {
+                    *level = 1.0;
+                }
+            }
+        }
+
+        levels
+    }
+
+    /// Fades what was shown during the frame that just ended by one frame.
+    /// `frame_buffer` is the display at the end of it.
+    pub fn end_frame(&mut self, frame_buffer: &FrameBuffer) {
+        let decay = match self.filter {
+            Filter::None => return,
+            Filter::Blend => 0.0,
+            Filter::Phosphor => self.decay,
+        };
+
+        self.levels = self.render(frame_buffer);
+        for (row, lit) in self.levels.iter_mut().zip(&mut self.lit) {
+            for (level, lit) in row.iter_mut().zip(lit) {
+                if *lit == 0 {
+                    *level *= decay;
+                }
+                if *level < CUTOFF {
+                    *level = 0.0;
+                }
+                *lit = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+// The levels compared are exact fractions of a power of two
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades_pixels_out() {
+        let mut frame_buffer = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        let mut phosphor = Phosphor::new(Filter::Phosphor, 0.5);
+
+        // Lit for a moment during the frame
+        frame_buffer[0][0] = 1;
+        phosphor.render(&frame_buffer);
+        frame_buffer[0][0] = 0;
+        assert_eq!(phosphor.render(&frame_buffer)[0][0], 1.0);
+        phosphor.end_frame(&frame_buffer);
+        assert_eq!(phosphor.render(&frame_buffer)[0][0], 1.0);
+
+        phosphor.end_frame(&frame_buffer);
+        assert_eq!(phosphor.render(&frame_buffer)[0][0], 0.5);
+        phosphor.end_frame(&frame_buffer);
+        assert_eq!(phosphor.render(&frame_buffer)[0][0], 0.25);
+
+        // Lit again at full brightness
+        frame_buffer[0][0] = 1;
+        assert_eq!(phosphor.render(&frame_buffer)[0][0], 1.0);
+    }
+
+    #[test]
+    fn blends_two_frames() {
+        let mut frame_buffer = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        let mut phosphor = Phosphor::new(Filter::Blend, DEFAULT_DECAY);
+
+        frame_buffer[0][0] = 1;
+        phosphor.end_frame(&frame_buffer);
+        frame_buffer[0][0] = 0;
+        frame_buffer[0][1] = 1;
+        let levels = phosphor.render(&frame_buffer);
+        assert_eq!(levels[0][..3], [1.0, 1.0, 0.0]);
+
+        phosphor.end_frame(&frame_buffer);
+        assert_eq!(phosphor.render(&frame_buffer)[0][..3], [0.0, 1.0, 0.0]);
+
+        let mut none = Phosphor::new(Filter::None, DEFAULT_DECAY);
+        none.end_frame(&frame_buffer);
+        assert_eq!(none.render(&[[0; CHIP8_WIDTH]; CHIP8_HEIGHT])[0][1], 0.0);
+    }
+}
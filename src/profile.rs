@@ -0,0 +1,292 @@
+use crate::chip::{Chip8, MemAccess, OpCode};
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::io::Write;
+
+/// Number of bytes covered by the heatmap.
+const HEATMAP_SIZE: usize = 0x1000;
+/// Width (and height) of the heatmap in addresses.
+const HEATMAP_WIDTH: usize = 64;
+/// Number of entries listed in each table of the report.
+const TOP: usize = 20;
+
+/// Execution statistics of a subroutine, measured in executed instructions.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Routine {
+    pub calls: u64,
+    /// Instructions executed between entering and returning from the routine.
+    pub inclusive: u64,
+    /// Instructions executed by the routine itself, excluding its callees.
+    pub exclusive: u64,
+}
+
+/// Collects execution statistics over `Chip8::cycle`.
+///
+/// `record` is to be called before every cycle. Time is measured in executed
+/// instructions, and attributed to subroutines by following `Call` and `Return`.
+pub struct Profiler {
+    cycles: u64,
+    executions: Vec<u64>,
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    ops: HashMap<&'static str, u64>,
+    routines: BTreeMap<u16, Routine>,
+    frames: Vec<(u16, u64)>,
+    entry: Option<u16>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            cycles: 0,
+            executions: vec![0; 0x10000],
+            reads: vec![0; 0x10000],
+            writes: vec![0; 0x10000],
+            ops: HashMap::new(),
+            routines: BTreeMap::new(),
+            frames: Vec::new(),
+            entry: None,
+        }
+    }
+
+    pub fn record(&mut self, chip8: &Chip8) {
+        let pc = chip8.program_counter();
+        self.cycles += 1;
+        self.executions[pc as usize] += 1;
+
+        let entry = *self.entry.get_or_insert(pc);
+        let current = self.frames.last().map_or(entry, |&(routine, _)| routine);
+        self.routines.entry(current).or_default().exclusive += 1;
+
+        let Some(op) = chip8
+            .peek_word(pc)
+            .ok()
+            .and_then(|word| OpCode::try_from(word).ok())
+        else {
+            return;
+        };
+
+        *self.ops.entry(op.name()).or_default() += 1;
+
+        if let Some((access, range)) = op.memory_access(chip8.index()) {
+            let counts = match access {
+                MemAccess::Read => &mut self.reads,
+                MemAccess::Write => &mut self.writes,
+            };
+
+            for addr in range {
+                counts[addr as usize] += 1;
+            }
+        }
+
+        match op {
+            OpCode::Call(addr) => {
+                self.routines.entry(addr).or_default().calls += 1;
+                self.frames.push((addr, self.cycles));
+            }
+            OpCode::Return => {
+                if let Some((routine, start)) = self.frames.pop() {
+                    self.routines.entry(routine).or_default().inclusive += self.cycles - start;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executions[addr as usize]
+    }
+
+    pub fn op_count(&self, name: &str) -> u64 {
+        self.ops.get(name).copied().unwrap_or(0)
+    }
+
+    /// The statistics of the subroutine at `addr`, including calls that have
+    /// not returned yet.
+    pub fn routine(&self, addr: u16) -> Option<Routine> {
+        let mut routine = *self.routines.get(&addr)?;
+        for &(_, start) in self.frames.iter().filter(|&&(frame, _)| frame == addr) {
+            routine.inclusive += self.cycles - start;
+        }
+
+        // The program itself is never called, so everything happens within it
+        if Some(addr) == self.entry {
+            routine.inclusive = self.cycles;
+        }
+
+        Some(routine)
+    }
+
+    /// Writes a plain text report, using `chip8` to disassemble hot addresses.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn write_report(&self, chip8: &Chip8, mut w: impl Write) -> io::Result<()> {
+        let total = self.cycles.max(1) as f64;
+        let percent = |count: u64| 100.0 * count as f64 / total;
+
+        writeln!(w, "Profile of {} executed instructions", self.cycles)?;
+
+        writeln!(w)?;
+        writeln!(w, "Hottest addresses:")?;
+        let mut hot: Vec<_> = (0..=u16::MAX)
+            .filter(|&addr| self.executions(addr) > 0)
+            .collect();
+        hot.sort_by_key(|&addr| std::cmp::Reverse(self.executions(addr)));
+        for addr in hot.into_iter().take(TOP) {
+            let count = self.executions(addr);
+            let op = chip8
+                .peek_word(addr)
+                .ok()
+                .and_then(|word| OpCode::try_from(word).ok())
+                .map_or_else(|| String::from("???"), |op| op.to_string());
+            writeln!(
+                w,
+                "    0x{addr:03X} {count:>12} {:>6.2}%  {op}",
+                percent(count)
+            )?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Instructions:")?;
+        let mut ops: Vec<_> = self.ops.iter().collect();
+        ops.sort_by_key(|&(name, &count)| (std::cmp::Reverse(count), *name));
+        for (name, &count) in ops {
+            writeln!(w, "    {name:<22} {count:>12} {:>6.2}%", percent(count))?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Subroutines:")?;
+        writeln!(
+            w,
+            "    {:<7} {:>8} {:>12} {:>8} {:>12} {:>8}",
+            "entry", "calls", "inclusive", "%", "exclusive", "%"
+        )?;
+        let mut routines: Vec<_> = self
+            .routines
+            .keys()
+            .filter_map(|&addr| Some((addr, self.routine(addr)?)))
+            .collect();
+        routines.sort_by_key(|&(addr, routine)| (std::cmp::Reverse(routine.inclusive), addr));
+        for (addr, routine) in routines.into_iter().take(TOP) {
+            writeln!(
+                w,
+                "    0x{addr:03X}   {:>8} {:>12} {:>7.2}% {:>12} {:>7.2}%",
+                routine.calls,
+                routine.inclusive,
+                percent(routine.inclusive),
+                routine.exclusive,
+                percent(routine.exclusive),
+            )?;
+        }
+
+        w.flush()
+    }
+
+    /// Writes a PNG image of the first 4 KiB of memory, with one `scale` sized
+    /// square per address. Writes are shown in red, execution in green and reads
+    /// in blue, with a logarithmic intensity.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_sign_loss)]
+    pub fn write_heatmap(&self, w: impl Write, scale: u32) -> io::Result<()> {
+        let intensity = |counts: &[u64]| {
+            let max = counts[..HEATMAP_SIZE].iter().copied().max().unwrap_or(0);
+            let max = ((max + 1) as f64).ln();
+            move |count: u64| {
+                if max == 0.0 {
+                    0
+                } else {
+                    (((count + 1) as f64).ln() / max * 255.0) as u8
+                }
+            }
+        };
+        let writes = intensity(&self.writes);
+        let executions = intensity(&self.executions);
+        let reads = intensity(&self.reads);
+
+        let size = HEATMAP_WIDTH as u32 * scale;
+        let mut data = Vec::with_capacity((size * size * 3) as usize);
+        for y in 0..size as usize {
+            for x in 0..size as usize {
+                let addr = (y / scale as usize) * HEATMAP_WIDTH + x / scale as usize;
+                data.push(writes(self.writes[addr]));
+                data.push(executions(self.executions[addr]));
+                data.push(reads(self.reads[addr]));
+            }
+        }
+
+        let mut encoder = png::Encoder::new(w, size, size);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(io::Error::other)
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(profiler: &mut Profiler, chip8: &mut Chip8, cycles: usize) {
+        for _ in 0..cycles {
+            profiler.record(chip8);
+            chip8.cycle().unwrap();
+        }
+    }
+
+    #[test]
+    fn counts_executions_and_ops() {
+        // 0x200: LD V0, 1; ADD V0, 1; JP 0x202
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02])
+            .unwrap();
+
+        let mut profiler = Profiler::new();
+        run(&mut profiler, &mut chip8, 7);
+
+        assert_eq!(profiler.cycles(), 7);
+        assert_eq!(profiler.executions(0x200), 1);
+        assert_eq!(profiler.executions(0x202), 3);
+        assert_eq!(profiler.executions(0x204), 3);
+        assert_eq!(profiler.op_count("Add"), 3);
+        assert_eq!(profiler.op_count("Jump"), 3);
+    }
+
+    #[test]
+    fn attributes_subroutines() {
+        // 0x200: CALL 0x206; JP 0x202; (padding)
+        // 0x206: LD V0, 1; LD [I], V0; RET
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[
+                0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x60, 0x01, 0xF0, 0x55, 0x00, 0xEE,
+            ])
+            .unwrap();
+        chip8.set_index(0x300);
+
+        let mut profiler = Profiler::new();
+        run(&mut profiler, &mut chip8, 6);
+
+        let routine = profiler.routine(0x206).unwrap();
+        assert_eq!(routine.calls, 1);
+        assert_eq!(routine.inclusive, 3);
+        assert_eq!(routine.exclusive, 3);
+        assert_eq!(profiler.routine(0x200).unwrap().exclusive, 3);
+        assert_eq!(profiler.writes[0x300], 1);
+    }
+}
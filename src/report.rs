@@ -0,0 +1,64 @@
+use chipper::chip::{Chip8, Fault, OpCode};
+use chipper::debug;
+use chipper::symbols::Symbols;
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// Number of instructions shown on either side of the faulting instruction.
+const CONTEXT: u16 = 4;
+
+pub fn crash_report(chip8: &Chip8, fault: &Fault, symbols: &Symbols) -> String {
+    let mut report = String::new();
+    let snapshot = &fault.snapshot;
+
+    // Writing to a `String` cannot fail, so the results are ignored
+    let _ = writeln!(report, "Chipper crashed: {}", fault.error);
+    let _ = writeln!(report);
+    let _ = writeln!(report, "Registers:");
+    for (i, row) in snapshot.v.chunks(8).enumerate() {
+        let _ = write!(report, "   ");
+        for (j, value) in row.iter().enumerate() {
+            let _ = write!(report, " V{:X}={:02X}", i * 8 + j, value);
+        }
+        let _ = writeln!(report);
+    }
+    let _ = writeln!(
+        report,
+        "    I={:03X} PC={:03X} DT={:02X} ST={:02X}",
+        snapshot.index, fault.program_counter, snapshot.delay_timer, snapshot.sound_timer
+    );
+
+    let _ = writeln!(report);
+    let _ = writeln!(report, "Backtrace ({} calls deep):", snapshot.stack.len());
+    for frame in debug::backtrace(fault.program_counter, &snapshot.stack, symbols) {
+        let _ = writeln!(report, "    {frame}");
+    }
+
+    let _ = writeln!(report);
+    let _ = writeln!(report, "Disassembly:");
+    let start = fault.program_counter.saturating_sub(CONTEXT * 2);
+    let end = fault.program_counter.saturating_add(CONTEXT * 2);
+    for addr in (start..=end).step_by(2) {
+        let marker = if addr == fault.program_counter {
+            "=>"
+        } else {
+            "  "
+        };
+        if let Some((label, 0)) = symbols.lookup(addr) {
+            let _ = writeln!(report, "   {label}:");
+        }
+        match chip8.peek_word(addr) {
+            Ok(word) => {
+                let op = OpCode::try_from(word)
+                    .map_or_else(|_| String::from("???"), |op| symbols.disassemble(op));
+                let _ = writeln!(report, "{marker} 0x{addr:03X}: {word:04X}  {op}");
+            }
+            Err(_) => {
+                let _ = writeln!(report, "{marker} 0x{addr:03X}: ----");
+            }
+        }
+    }
+
+    report
+}
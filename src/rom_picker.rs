@@ -0,0 +1,145 @@
+//! A list of the ROMs of a directory or archive, drawn in the main window to
+//! pick the next one to run.
+
+#![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_possible_wrap)]
+
+use super::font;
+
+use chipper::library::RomLibrary;
+
+use anyhow::Error;
+use anyhow::Result;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+/// Size of a font pixel in window pixels.
+const SCALE: u32 = 4;
+const LINE_HEIGHT: u32 = font::LINE_HEIGHT * SCALE;
+const MARGIN: u32 = 16;
+/// Rows moved by page up and page down.
+const PAGE: usize = 10;
+
+const BACKGROUND: Color = Color::RGB(0x10, 0x10, 0x18);
+const TEXT: Color = Color::RGB(0xAA, 0xAA, 0xAA);
+const TITLE: Color = Color::RGB(0xF0, 0xC8, 0x3C);
+const SELECTION: Color = Color::RGB(0x00, 0x3C, 0x8C);
+
+pub enum PickerAction {
+    /// Run the ROM at this index of the library.
+    Load(usize),
+    /// Go back without picking a ROM.
+    Cancel,
+}
+
+pub struct RomPicker {
+    library: RomLibrary,
+    selected: usize,
+    /// The first row visible.
+    top: usize,
+    /// Whether to switch to the platform matching the extension of the ROM.
+    match_platform: bool,
+    /// The ROM loaded last, if any.
+    current: Option<usize>,
+}
+
+impl RomPicker {
+    pub fn new(library: RomLibrary, match_platform: bool) -> Self {
+        Self {
+            library,
+            selected: 0,
+            top: 0,
+            match_platform,
+            current: None,
+        }
+    }
+
+    pub fn library(&mut self) -> &mut RomLibrary {
+        &mut self.library
+    }
+
+    pub fn match_platform(&self) -> bool {
+        self.match_platform
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn set_current(&mut self, index: usize) {
+        self.current = Some(index);
+    }
+
+    /// Moves the selection with the arrow, page, home and end keys, and
+    /// returns what to do on enter and escape.
+    pub fn handle_key(&mut self, scancode: Scancode) -> Option<PickerAction> {
+        let last = self.library.names().len().saturating_sub(1);
+        self.selected = match scancode {
+            Scancode::Up => self.selected.saturating_sub(1),
+            Scancode::Down => (self.selected + 1).min(last),
+            Scancode::PageUp => self.selected.saturating_sub(PAGE),
+            Scancode::PageDown => (self.selected + PAGE).min(last),
+            Scancode::Home => 0,
+            Scancode::End => last,
+            Scancode::Return | Scancode::KpEnter if !self.library.is_empty() => {
+                return Some(PickerAction::Load(self.selected));
+            }
+            Scancode::Escape => return Some(PickerAction::Cancel),
+            _ => return None,
+        };
+
+        None
+    }
+
+    /// Draws the list over the whole canvas, without presenting it.
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>) -> Result<()> {
+        let (width, height) = canvas.output_size().map_err(Error::msg)?;
+        // The title takes two rows
+        let rows = ((height.saturating_sub(2 * MARGIN) / LINE_HEIGHT) as usize)
+            .saturating_sub(2)
+            .max(1);
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + rows {
+            self.top = self.selected + 1 - rows;
+        }
+
+        canvas.set_draw_color(BACKGROUND);
+        canvas.clear();
+
+        let count = self.library.names().len();
+        let title = if count == 0 {
+            String::from("NO ROMS FOUND  ESC TO GO BACK")
+        } else {
+            format!("{count} ROMS  ENTER TO RUN  ESC TO GO BACK")
+        };
+        let position = (MARGIN as i32, MARGIN as i32);
+        font::draw_text(canvas, &title, position, SCALE, TITLE)?;
+
+        let names = self.library.names().iter().enumerate();
+        for (row, (i, name)) in names.skip(self.top).take(rows).enumerate() {
+            let y = MARGIN + (row as u32 + 2) * LINE_HEIGHT;
+            if i == self.selected {
+                canvas.set_draw_color(SELECTION);
+                canvas
+                    .fill_rect(Rect::new(
+                        (MARGIN - SCALE) as i32,
+                        (y - SCALE) as i32,
+                        width - 2 * (MARGIN - SCALE),
+                        LINE_HEIGHT,
+                    ))
+                    .map_err(Error::msg)?;
+            }
+
+            let marker = if Some(i) == self.current { "> " } else { "  " };
+            let text = format!("{marker}{}", name.to_ascii_uppercase());
+            let position = (MARGIN as i32, y as i32);
+            font::draw_text(canvas, &text, position, SCALE, TEXT)?;
+        }
+
+        Ok(())
+    }
+}
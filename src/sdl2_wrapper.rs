@@ -1,56 +1,104 @@
 #![allow(clippy::cast_possible_truncation)]
 
-use super::chip::FrameBuffer;
-use super::{CHIP8_HEIGHT, CHIP8_WIDTH, SCALE};
+use super::osd;
+use super::osd::Corner;
+use super::rom_picker::RomPicker;
+use super::SCALE;
+use chipper::audio::SAMPLE_RATE;
+use chipper::crt;
+use chipper::crt::Effects;
+use chipper::image::Palette;
+use chipper::phosphor::Levels;
+use chipper::{CHIP8_HEIGHT, CHIP8_WIDTH};
 
 use anyhow::Error;
 use anyhow::Result;
 
-use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
 use sdl2::keyboard::Scancode;
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 use sdl2::EventPump;
 use sdl2::Sdl;
+use sdl2::VideoSubsystem;
 
 use std::convert::TryFrom;
 
+/// The most sound queued for playback, in milliseconds.
+const MAX_AUDIO_LATENCY: u32 = 100;
+const BYTES_PER_SAMPLE: u32 = 4;
+
 pub struct Sdl2Wrapper {
+    video: VideoSubsystem,
     canvas: Canvas<Window>,
     event_pump: EventPump,
-    audio_device: AudioDevice<SquareWave>,
+    audio_queue: AudioQueue<f32>,
+    palette: Palette,
+    effects: Effects,
+    /// The last levels drawn with effects, and the image they gave, as the
+    /// display is often drawn again unchanged.
+    crt_image: Option<(Levels, Vec<u8>)>,
 }
 
 impl Sdl2Wrapper {
     pub fn new() -> Result<Self> {
         let sdl_context = Self::create_sdl_context()?;
-        let audio_device = Self::setup_audio_device(&sdl_context)?;
-        let canvas = Self::setup_canvas(&sdl_context)?;
+        let audio_queue = Self::setup_audio_queue(&sdl_context)?;
+        let video = sdl_context.video().map_err(Error::msg)?;
+        let canvas = Self::setup_canvas(&video)?;
         let event_pump = sdl_context.event_pump().map_err(Error::msg)?;
 
         Ok(Self {
+            video,
             canvas,
             event_pump,
-            audio_device,
+            audio_queue,
+            palette: Palette::new(),
+            effects: Effects::new(),
+            crt_image: None,
         })
     }
 
-    pub fn draw_on_canvas(&mut self, buffer: &FrameBuffer) -> Result<()> {
+    /// Draws every pixel with a color between the background, at level 0,
+    /// and the foreground, at level 1, then the on-screen display.
+    pub fn draw_on_canvas(&mut self, levels: &Levels, overlay: &[(Corner, String)]) -> Result<()> {
+        if self.effects.is_empty() {
+            self.draw_levels(levels)?;
+        } else {
+            self.draw_crt(levels)?;
+        }
+
+        osd::draw(&mut self.canvas, overlay)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    /// Draws the ROM picker instead of the display, then the on-screen
+    /// display.
+    pub fn draw_picker(
+        &mut self,
+        picker: &mut RomPicker,
+        overlay: &[(Corner, String)],
+    ) -> Result<()> {
+        picker.draw(&mut self.canvas)?;
+        osd::draw(&mut self.canvas, overlay)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn draw_levels(&mut self, levels: &Levels) -> Result<()> {
         // TODO:
         // Clean this up and add checks for proper conversion between u32 and i32
-        for (y, row) in buffer.iter().enumerate() {
-            for (x, &col) in row.iter().enumerate() {
+        for (y, row) in levels.iter().enumerate() {
+            for (x, &level) in row.iter().enumerate() {
                 let x = (x * SCALE) as u32;
                 let y = (y * SCALE) as u32;
 
-                let color = if col == 0 {
-                    sdl2::pixels::Color::RGB(0, 0, 0)
-                } else {
-                    sdl2::pixels::Color::RGB(0xFF, 0xFF, 0xFF)
-                };
-
-                self.canvas.set_draw_color(color);
+                let [red, green, blue] = self.color(level);
+                self.canvas
+                    .set_draw_color(sdl2::pixels::Color::RGB(red, green, blue));
                 self.canvas
                     .fill_rect(sdl2::rect::Rect::new(
                         TryFrom::try_from(x)?,
@@ -62,10 +110,73 @@ impl Sdl2Wrapper {
             }
         }
 
-        self.canvas.present();
         Ok(())
     }
 
+    /// Draws the display with the CRT effects, through a texture.
+    fn draw_crt(&mut self, levels: &Levels) -> Result<()> {
+        if self.crt_image.as_ref().map(|(drawn, _)| drawn) != Some(levels) {
+            let image = crt::render(levels, SCALE, &self.palette, self.effects);
+            self.crt_image = Some((*levels, image));
+        }
+
+        let (width, height) = (SCALE * CHIP8_WIDTH, SCALE * CHIP8_HEIGHT);
+        let creator = self.canvas.texture_creator();
+        let mut texture = creator.create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            width as u32,
+            height as u32,
+        )?;
+        if let Some((_, image)) = &self.crt_image {
+            texture.update(None, image, width * 3)?;
+        }
+
+        self.canvas.copy(&texture, None, None).map_err(Error::msg)?;
+        Ok(())
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    fn color(&self, level: f32) -> [u8; 3] {
+        let Palette {
+            background,
+            foreground,
+        } = self.palette;
+        if level <= 0.0 {
+            return background;
+        }
+        if level >= 1.0 {
+            return foreground;
+        }
+
+        let mut color = [0; 3];
+        for ((channel, &from), &to) in color.iter_mut().zip(&background).zip(&foreground) {
+            let (from, to) = (f32::from(from), f32::from(to));
+            *channel = (from + (to - from) * level).round() as u8;
+        }
+
+        color
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.crt_image = None;
+    }
+
+    pub fn set_effects(&mut self, effects: Effects) {
+        self.effects = effects;
+        self.crt_image = None;
+    }
+
+    /// Opens an additional window, such as a debugging view.
+    pub fn create_window(&self, title: &str, width: u32, height: u32) -> Result<Canvas<Window>> {
+        let window = self.video.window(title, width, height).build()?;
+        Ok(window.into_canvas().build()?)
+    }
+
     pub fn poll_event(&mut self) -> Option<Event> {
         self.event_pump.poll_event()
     }
@@ -84,41 +195,48 @@ impl Sdl2Wrapper {
         pressed_keys
     }
 
-    pub fn beep(&mut self) {
-        self.audio_device.resume();
+    pub fn audio_sample_rate(&self) -> u32 {
+        u32::try_from(self.audio_queue.spec().freq).unwrap_or(0)
     }
 
-    pub fn stop_beep(&mut self) {
-        self.audio_device.pause();
+    /// Plays the samples after those queued before. When the queue already
+    /// holds more than `MAX_AUDIO_LATENCY` milliseconds, they are dropped instead,
+    /// so the sound never lags far behind the picture.
+    pub fn queue_audio(&mut self, samples: &[f32]) -> Result<()> {
+        let queued = self.audio_queue.size() / BYTES_PER_SAMPLE;
+        if queued > self.audio_sample_rate() * MAX_AUDIO_LATENCY / 1000 {
+            return Ok(());
+        }
+
+        if self.audio_queue.queue(samples) {
+            Ok(())
+        } else {
+            Err(Error::msg(sdl2::get_error()))
+        }
     }
 
     fn create_sdl_context() -> Result<Sdl> {
         sdl2::init().map_err(Error::msg)
     }
 
-    fn setup_audio_device(sdl_context: &sdl2::Sdl) -> Result<AudioDevice<SquareWave>> {
-        let audio_subsystem = sdl_context.audio().unwrap();
+    fn setup_audio_queue(sdl_context: &sdl2::Sdl) -> Result<AudioQueue<f32>> {
+        let audio_subsystem = sdl_context.audio().map_err(Error::msg)?;
 
         let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
+            freq: i32::try_from(SAMPLE_RATE).ok(),
             channels: Some(1),
             samples: None,
         };
 
-        let device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| SquareWave {
-                phase_inc: (240.0 / f64::from(spec.freq)) as f32,
-                phase: 0.0,
-                volume: 0.25,
-            })
+        let queue = audio_subsystem
+            .open_queue(None, &desired_spec)
             .map_err(Error::msg)?;
+        queue.resume();
 
-        Ok(device)
+        Ok(queue)
     }
 
-    fn setup_canvas(sdl_context: &sdl2::Sdl) -> Result<Canvas<Window>> {
-        let video = sdl_context.video().map_err(Error::msg)?;
-
+    fn setup_canvas(video: &VideoSubsystem) -> Result<Canvas<Window>> {
         let window = video
             .window(
                 "Chipper: Chip8 Emulator",
@@ -160,24 +278,3 @@ impl Sdl2Wrapper {
         }
     }
 }
-
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
-}
-
-impl AudioCallback for SquareWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        for x in out {
-            *x = if self.phase < 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
-    }
-}
@@ -0,0 +1,140 @@
+//! Mapping between ROM addresses and lines of the source they were assembled from.
+//!
+//! A source map is a text file with one instruction per line, giving its
+//! address in hexadecimal and the source line it came from:
+//!
+//! ```text
+//! # address  file:line
+//! 200 game.8o:3
+//! 202 game.8o:4
+//! ```
+//!
+//! Empty lines and lines starting with `#` are ignored.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// A line of a source map that could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceMapError {
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub text: String,
+}
+
+impl fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid source map entry on line {}: '{}'",
+            self.line, self.text
+        )
+    }
+}
+
+impl Error for SourceMapError {}
+
+/// A source location: a file, as written in the source map, and a line number.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    locations: BTreeMap<u16, Location>,
+}
+
+impl SourceMap {
+    pub fn parse(text: &str) -> Result<Self, SourceMapError> {
+        let mut locations = BTreeMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = || SourceMapError {
+                line: i + 1,
+                text: String::from(line),
+            };
+
+            let (addr, location) = line.split_once(char::is_whitespace).ok_or_else(error)?;
+            let (file, number) = location.trim().rsplit_once(':').ok_or_else(error)?;
+            let addr = addr.trim_start_matches("0x").trim_start_matches("0X");
+
+            locations.insert(
+                u16::from_str_radix(addr, 16).map_err(|_| error())?,
+                Location {
+                    file: String::from(file),
+                    line: number.parse().map_err(|_| error())?,
+                },
+            );
+        }
+
+        Ok(Self { locations })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// Returns the source location of the instruction at `addr`.
+    pub fn location(&self, addr: u16) -> Option<&Location> {
+        self.locations.get(&addr)
+    }
+
+    /// Returns the first instruction generated by `line` of `path`, or by the
+    /// next line that generated code, along with the line it belongs to.
+    ///
+    /// `path` matches a file of the source map if it ends with it, so absolute
+    /// paths can be looked up in a map of relative ones.
+    pub fn find(&self, path: &Path, line: u32) -> Option<(u16, u32)> {
+        self.locations
+            .iter()
+            .filter(|(_, location)| location.line >= line && path.ends_with(&location.file))
+            .min_by_key(|(&addr, location)| (location.line, addr))
+            .map(|(&addr, location)| (addr, location.line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_find() {
+        let map =
+            SourceMap::parse("# comment\n200 main.8o:3\n\n202 main.8o:3\n0x210 lib/util.8o:7\n")
+                .unwrap();
+
+        assert_eq!(
+            map.location(0x210),
+            Some(&Location {
+                file: String::from("lib/util.8o"),
+                line: 7
+            })
+        );
+        assert_eq!(map.location(0x204), None);
+        assert_eq!(
+            map.find(Path::new("/home/user/game/main.8o"), 2),
+            Some((0x200, 3))
+        );
+        assert_eq!(
+            map.find(Path::new("/home/user/game/lib/util.8o"), 7),
+            Some((0x210, 7))
+        );
+        assert_eq!(map.find(Path::new("main.8o"), 4), None);
+
+        assert_eq!(
+            SourceMap::parse("200 main.8o").unwrap_err(),
+            SourceMapError {
+                line: 1,
+                text: String::from("200 main.8o")
+            }
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Symbol files, to show addresses as `label+offset`.
+//!
+//! A symbol file has one label per line, either as `label = address` or as
+//! `label address`, with the address in hexadecimal and an optional `0x`
+//! prefix. Empty lines and lines starting with `#` are ignored.
+
+use crate::chip::{Fault, OpCode};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+/// A line of a symbol file that could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymbolsError {
+    /// The line number, starting at 1.
+    pub line: usize,
+    pub text: String,
+}
+
+impl fmt::Display for SymbolsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid symbol on line {}: '{}'", self.line, self.text)
+    }
+}
+
+impl Error for SymbolsError {}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+    addresses: BTreeMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SymbolsError> {
+        let mut symbols = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = || SymbolsError {
+                line: i + 1,
+                text: String::from(line),
+            };
+
+            let (name, addr) = line
+                .split_once('=')
+                .or_else(|| line.split_once(char::is_whitespace))
+                .ok_or_else(error)?;
+            let name = name.trim();
+            let addr = addr.trim();
+            let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+            let addr = u16::from_str_radix(digits, 16).map_err(|_| error())?;
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(error());
+            }
+
+            symbols.insert(name, addr);
+        }
+
+        Ok(symbols)
+    }
+
+    /// Adds a label. When several labels share an address, the first one
+    /// is used to display it.
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        self.labels
+            .entry(addr)
+            .or_insert_with(|| String::from(name));
+        self.addresses.insert(String::from(name), addr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Returns the address of a label.
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    /// Returns the label an address belongs to, which is the closest one at or
+    /// before it, with the offset from it.
+    pub fn lookup(&self, addr: u16) -> Option<(&str, u16)> {
+        self.labels
+            .range(..=addr)
+            .next_back()
+            .map(|(&start, name)| (name.as_str(), addr - start))
+    }
+
+    /// Returns `label` or `label+0x4`, if there is a label at or before `addr`.
+    pub fn label(&self, addr: u16) -> Option<String> {
+        self.lookup(addr).map(|(name, offset)| match offset {
+            0 => String::from(name),
+            _ => format!("{name}+0x{offset:X}"),
+        })
+    }
+
+    /// Returns the label of `addr`, or the address in hexadecimal.
+    pub fn format(&self, addr: u16) -> String {
+        self.label(addr).unwrap_or_else(|| format!("0x{addr:03X}"))
+    }
+
+    /// Disassembles an instruction, with labels for the addresses it uses.
+    pub fn disassemble(&self, op: OpCode) -> String {
+        match op {
+            OpCode::SysAddr(addr) => format!("SYS  {}", self.format(addr)),
+            OpCode::Jump(addr) => format!("JP   {}", self.format(addr)),
+            OpCode::Call(addr) => format!("CALL {}", self.format(addr)),
+            OpCode::SetIndexRegister(addr) => format!("LD   I, {}", self.format(addr)),
+            OpCode::JumpWithOffset(addr) => format!("JP   V0, {}", self.format(addr)),
+            _ => op.to_string(),
+        }
+    }
+
+    /// Describes a fault like its `Display` implementation, with labels.
+    pub fn describe(&self, fault: &Fault) -> String {
+        let pc = self.format(fault.program_counter);
+        match fault.word {
+            Some(word) => format!("{} (PC: {pc}, op: 0x{word:04X})", fault.error),
+            None => format!("{} (PC: {pc})", fault.error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let symbols = Symbols::parse("# comment\nmain = 0x200\nloop 20A\n\nsprites=300\n").unwrap();
+
+        assert_eq!(symbols.address("loop"), Some(0x20A));
+        assert_eq!(symbols.format(0x200), "main");
+        assert_eq!(symbols.format(0x20E), "loop+0x4");
+        assert_eq!(symbols.format(0x100), "0x100");
+        assert_eq!(symbols.disassemble(OpCode::Call(0x20A)), "CALL loop");
+        assert_eq!(
+            symbols.disassemble(OpCode::SetIndexRegister(0x305)),
+            "LD   I, sprites+0x5"
+        );
+
+        assert_eq!(
+            Symbols::parse("main").unwrap_err(),
+            SymbolsError {
+                line: 1,
+                text: String::from("main")
+            }
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::chip::{Chip8, Fault, OpClass, OpCode, Snapshot};
+use crate::symbols::Symbols;
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Decides which executed instructions end up in the trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Addresses of instructions to trace. Empty means all addresses.
+    pub ranges: Vec<RangeInclusive<u16>>,
+    /// Classes of instructions to trace. Empty means all classes.
+    pub classes: Vec<OpClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, addr: u16, op: Option<OpCode>) -> bool {
+        let in_range = self.ranges.is_empty() || self.ranges.iter().any(|r| r.contains(&addr));
+        let in_class =
+            self.classes.is_empty() || op.is_some_and(|op| self.classes.contains(&op.class()));
+
+        in_range && in_class
+    }
+}
+
+/// Writes one line per executed instruction to `W`.
+///
+/// The tracer is driven by calling `before_cycle` and `after_cycle` around
+/// every `Chip8::cycle`. With a ring buffer, lines are held back and only the
+/// last `n` of them are written once `after_cycle` is given a `Fault`.
+pub struct Tracer<W: Write> {
+    output: W,
+    filter: TraceFilter,
+    symbols: Symbols,
+    ring: Option<(usize, VecDeque<String>)>,
+    cycles: u64,
+    before: Option<(Snapshot, Option<u16>)>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            filter: TraceFilter::default(),
+            symbols: Symbols::new(),
+            ring: None,
+            cycles: 0,
+            before: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Shows addresses as `label+offset` where the symbols allow it.
+    #[must_use]
+    pub fn with_symbols(mut self, symbols: Symbols) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Keeps only the last `len` lines, at least one, and writes them out
+    /// when a fault occurs.
+    #[must_use]
+    pub fn with_ring_buffer(mut self, len: usize) -> Self {
+        let len = len.max(1);
+        self.ring = Some((len, VecDeque::with_capacity(len)));
+        self
+    }
+
+    pub fn before_cycle(&mut self, chip8: &Chip8) {
+        let snapshot = chip8.snapshot();
+        let word = chip8.peek_word(snapshot.program_counter).ok();
+        self.before = Some((snapshot, word));
+    }
+
+    pub fn after_cycle(&mut self, chip8: &Chip8, fault: Option<&Fault>) -> io::Result<()> {
+        let Some((before, word)) = self.before.take() else {
+            return Ok(());
+        };
+
+        self.cycles += 1;
+        let pc = before.program_counter;
+        let op = word.and_then(|word| OpCode::try_from(word).ok());
+
+        if self.filter.matches(pc, op) || fault.is_some() {
+            let line = self.format_line(&before, &chip8.snapshot(), word, op, fault);
+            match &mut self.ring {
+                Some((len, lines)) => {
+                    if lines.len() == *len {
+                        lines.pop_front();
+                    }
+                    lines.push_back(line);
+                }
+                None => writeln!(self.output, "{line}")?,
+            }
+        }
+
+        if fault.is_some() {
+            self.dump()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes out and empties the ring buffer, if there is one.
+    pub fn dump(&mut self) -> io::Result<()> {
+        if let Some((_, lines)) = &mut self.ring {
+            for line in lines.drain(..) {
+                writeln!(self.output, "{line}")?;
+            }
+        }
+
+        self.output.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    fn format_line(
+        &self,
+        before: &Snapshot,
+        after: &Snapshot,
+        word: Option<u16>,
+        op: Option<OpCode>,
+        fault: Option<&Fault>,
+    ) -> String {
+        let pc = before.program_counter;
+        let pc = self
+            .symbols
+            .label(pc)
+            .unwrap_or_else(|| format!("{pc:03X}"));
+        let mut line = format!("{:>10} {pc} ", self.cycles);
+
+        // Writing to a `String` cannot fail, so the results are ignored
+        match (word, op) {
+            (Some(word), Some(op)) => {
+                let _ = write!(line, "{word:04X}  {:<18}", self.symbols.disassemble(op));
+            }
+            (Some(word), None) => {
+                let _ = write!(line, "{word:04X}  {:<18}", "???");
+            }
+            (None, _) => {
+                let _ = write!(line, "----  {:<18}", "");
+            }
+        }
+
+        for (x, (old, new)) in before.v.iter().zip(after.v.iter()).enumerate() {
+            if old != new {
+                let _ = write!(line, " V{x:X}:{old:02X}->{new:02X}");
+            }
+        }
+
+        let _ = write!(
+            line,
+            " | I={:03X} VF={:02X} SP={}",
+            after.index,
+            after.v[0xF],
+            after.stack.len()
+        );
+
+        if let Some(fault) = fault {
+            let _ = write!(line, " !! {}", fault.error);
+        }
+
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(tracer: &mut Tracer<Vec<u8>>, chip8: &mut Chip8, cycles: usize) {
+        for _ in 0..cycles {
+            tracer.before_cycle(chip8);
+            let result = chip8.cycle();
+            tracer.after_cycle(chip8, result.as_ref().err()).unwrap();
+        }
+    }
+
+    #[test]
+    fn traces_every_instruction() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x6A, 0x02, 0xA3, 0x00]).unwrap();
+
+        let mut tracer = Tracer::new(Vec::new());
+        run(&mut tracer, &mut chip8, 2);
+
+        let output = String::from_utf8(tracer.into_inner()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("200 6A02"));
+        assert!(lines[0].contains("VA:00->02"));
+        assert!(lines[1].contains("I=300"));
+    }
+
+    #[test]
+    fn filters_by_class() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x6A, 0x02, 0xA3, 0x00]).unwrap();
+
+        let filter = TraceFilter {
+            classes: vec![OpClass::Index],
+            ..TraceFilter::default()
+        };
+        let mut tracer = Tracer::new(Vec::new()).with_filter(filter);
+        run(&mut tracer, &mut chip8, 2);
+
+        let output = String::from_utf8(tracer.into_inner()).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.contains("202 A300"));
+    }
+
+    #[test]
+    fn ring_buffer_dumps_on_fault() {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xFF, 0xFF])
+            .unwrap();
+
+        let mut tracer = Tracer::new(Vec::new()).with_ring_buffer(2);
+        run(&mut tracer, &mut chip8, 3);
+        assert!(tracer.output.is_empty());
+
+        run(&mut tracer, &mut chip8, 1);
+        let output = String::from_utf8(tracer.into_inner()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("204 6203"));
+        assert!(lines[1].contains("!! Invalid op: 0xFFFF"));
+    }
+
+    #[test]
+    fn empty_ring_buffer_keeps_one_line() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x60, 0x01, 0x61, 0x02]).unwrap();
+
+        let mut tracer = Tracer::new(Vec::new()).with_ring_buffer(0);
+        run(&mut tracer, &mut chip8, 2);
+        assert_eq!(tracer.ring.as_ref().map(|(_, lines)| lines.len()), Some(1));
+    }
+}
@@ -0,0 +1,88 @@
+//! Recording sound to WAV files, as 16 bit mono PCM.
+
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+const HEADER_LEN: u32 = 44;
+const BYTES_PER_SAMPLE: u16 = 2;
+
+/// Writes samples to a WAV file as they come. The sizes in the header are
+/// only filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    output: W,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut output: W, sample_rate: u32) -> io::Result<Self> {
+        let byte_rate = sample_rate * u32::from(BYTES_PER_SAMPLE);
+
+        output.write_all(b"RIFF")?;
+        output.write_all(&0u32.to_le_bytes())?;
+        output.write_all(b"WAVE")?;
+        output.write_all(b"fmt ")?;
+        output.write_all(&16u32.to_le_bytes())?;
+        // PCM, one channel
+        output.write_all(&1u16.to_le_bytes())?;
+        output.write_all(&1u16.to_le_bytes())?;
+        output.write_all(&sample_rate.to_le_bytes())?;
+        output.write_all(&byte_rate.to_le_bytes())?;
+        output.write_all(&BYTES_PER_SAMPLE.to_le_bytes())?;
+        output.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        output.write_all(b"data")?;
+        output.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self { output, samples: 0 })
+    }
+
+    /// Appends samples from -1 to 1. Louder ones are clipped.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            self.output.write_all(&sample.to_le_bytes())?;
+        }
+
+        let count = u32::try_from(samples.len()).unwrap_or(u32::MAX);
+        self.samples = self.samples.saturating_add(count);
+        Ok(())
+    }
+
+    /// Fills in the header and returns the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_len = self.samples.saturating_mul(u32::from(BYTES_PER_SAMPLE));
+
+        self.output.seek(SeekFrom::Start(4))?;
+        self.output
+            .write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.output
+            .seek(SeekFrom::Start(u64::from(HEADER_LEN) - 4))?;
+        self.output.write_all(&data_len.to_le_bytes())?;
+        self.output.seek(SeekFrom::End(0))?;
+        self.output.flush()?;
+
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn writes_pcm() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 8000).unwrap();
+        wav.write_samples(&[0.0, 1.0, -2.0]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 6);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(bytes[4..8], 42u32.to_le_bytes());
+        assert_eq!(bytes[24..28], 8000u32.to_le_bytes());
+        assert_eq!(bytes[40..44], 6u32.to_le_bytes());
+        assert_eq!(bytes[44..], [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+}